@@ -0,0 +1,158 @@
+//! Simulated physical memory, so page tables can be tested on the host
+
+use crate::{
+    page_table::{
+        identity_page, managed_page_table::ManagedPageTable,
+        DEFAULT_IDENTITY_BASE, IDENTITY_BASE,
+    },
+    physical::{
+        map::{MapBuffer, PhysicalMemoryMap},
+        page_usage::{PageUsage, PageUsageRawType},
+        run_length_map::Run,
+    },
+    platform,
+};
+use core::sync::atomic::Ordering;
+use spin::{Mutex, MutexGuard};
+use std::{
+    alloc::{alloc_zeroed, dealloc, Layout},
+    boxed::Box,
+    vec,
+};
+use x86_64::{
+    structures::paging::{
+        FrameAllocator, PageSize, PageTable, PageTableFlags, PhysFrame,
+        Size4KiB,
+    },
+    PhysAddr,
+};
+
+/// The global state can only be used by one simulation at a time
+static SIMULATION: Mutex<()> = Mutex::new(());
+
+/// Physical memory backed by a heap buffer
+///
+/// While it exists, the identity base points to the buffer
+/// and the global physical memory map describes it.
+pub struct SimulatedMemory {
+    memory: *mut u8,
+    layout: Layout,
+    _guard: MutexGuard<'static, ()>,
+}
+
+impl SimulatedMemory {
+    /// Simulate frames frames, starting at physical address 0
+    ///
+    /// Frame 0 is unusable, just like on real hardware.
+    pub fn new(frames: u64) -> Self {
+        let buffer = Box::leak(
+            vec![PageUsageRawType::from_category(0); frames as usize]
+                .into_boxed_slice(),
+        );
+
+        Self::with_map(frames, |base| {
+            PhysicalMemoryMap::create(buffer, base, PageUsage::Empty)
+        })
+    }
+
+    /// Simulate frames frames, described by a run length map of at most runs runs
+    pub fn with_run_length_map(frames: u64, runs: usize) -> Self {
+        let buffer = Box::leak(
+            vec![
+                Run {
+                    start: 0,
+                    usage: PageUsageRawType::from_category(0),
+                };
+                runs
+            ]
+            .into_boxed_slice(),
+        );
+
+        Self::with_map(frames, |base| {
+            PhysicalMemoryMap::create_run_length(
+                buffer,
+                base,
+                frames,
+                PageUsage::Empty,
+            )
+        })
+    }
+
+    fn with_map<F>(frames: u64, create_map: F) -> Self
+    where
+        F: FnOnce(PhysFrame) -> PhysicalMemoryMap<'static>,
+    {
+        let guard = SIMULATION.lock();
+
+        let layout = Layout::from_size_align(
+            (frames * Size4KiB::SIZE) as usize,
+            Size4KiB::SIZE as usize,
+        )
+        .unwrap();
+        let memory = unsafe { alloc_zeroed(layout) };
+        assert!(!memory.is_null());
+
+        IDENTITY_BASE.store(memory as usize, Ordering::Release);
+
+        let base = PhysFrame::containing_address(PhysAddr::new(0));
+        unsafe {
+            create_map(base).register_global();
+        }
+        PhysicalMemoryMap::global(|map| map.set(base, PageUsage::Unusable));
+
+        SimulatedMemory {
+            memory,
+            layout,
+            _guard: guard,
+        }
+    }
+
+    /// Create a page table whose kernel half is prepared like the loader does it
+    pub fn create_page_table(&self) -> ManagedPageTable {
+        PhysicalMemoryMap::global(|map| unsafe {
+            let root = Self::allocate_table(map, PageUsage::PageTableRoot);
+            let root_table = Self::table_at(root);
+
+            for entry in root_table.iter_mut().skip(512 / 2) {
+                entry.set_frame(
+                    Self::allocate_table(map, PageUsage::PageTable),
+                    PageTableFlags::PRESENT | PageTableFlags::WRITABLE,
+                );
+            }
+
+            ManagedPageTable::from_raw_frame(root)
+        })
+    }
+
+    unsafe fn table_at<'lt>(frame: PhysFrame) -> &'lt mut PageTable {
+        &mut *identity_page(frame).start_address().as_mut_ptr()
+    }
+
+    unsafe fn allocate_table(
+        map: &mut PhysicalMemoryMap,
+        usage: PageUsage,
+    ) -> PhysFrame {
+        let frame =
+            map.frame_allocator(usage).allocate_frame().unwrap().frame();
+        Self::table_at(frame).zero();
+        frame
+    }
+}
+
+impl Drop for SimulatedMemory {
+    fn drop(&mut self) {
+        unsafe {
+            match PhysicalMemoryMap::take_global().release() {
+                (MapBuffer::Flat(buffer), _) => drop(Box::from_raw(buffer)),
+                (MapBuffer::RunLength(buffer), _) => {
+                    drop(Box::from_raw(buffer))
+                },
+            }
+
+            platform::write_cr3(0);
+            IDENTITY_BASE.store(DEFAULT_IDENTITY_BASE, Ordering::Release);
+
+            dealloc(self.memory, self.layout);
+        }
+    }
+}
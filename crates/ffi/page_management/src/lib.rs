@@ -1,4 +1,11 @@
 #![no_std]
+#![cfg_attr(any(target_os = "none", target_os = "uefi"), feature(global_asm))]
 
+#[cfg(not(any(target_os = "none", target_os = "uefi")))]
+extern crate std;
+
+#[cfg(not(any(target_os = "none", target_os = "uefi")))]
+pub mod host;
 pub mod page_table;
 pub mod physical;
+mod platform;
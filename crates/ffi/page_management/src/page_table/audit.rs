@@ -0,0 +1,110 @@
+use crate::page_table::{
+    inspect::MappedRange,
+    managed_page_table::{
+        is_in_identity_region, is_in_kernel_space, ManagedPageTable,
+    },
+};
+use log::*;
+use x86_64::structures::paging::{page::PageRange, PageTableFlags, Size4KiB};
+
+/// A broken mapping invariant
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Violation {
+    WritableAndExecutable,
+    UserAccessibleKernelPage,
+    ExecutableIdentityMapping,
+}
+
+impl Violation {
+    /// Call f for each invariant range breaks
+    ///
+    /// Exempt ranges may be writable and executable, even in the identity region.
+    /// Code ranges may be executed from the identity region, but not written.
+    fn check<F>(range: &MappedRange, exempt: bool, code: bool, mut f: F)
+    where
+        F: FnMut(Violation),
+    {
+        let flags = range.flags;
+        let executable = !flags.contains(PageTableFlags::NO_EXECUTE);
+
+        if is_in_kernel_space(range.start)
+            && flags.contains(PageTableFlags::USER_ACCESSIBLE)
+        {
+            f(Violation::UserAccessibleKernelPage);
+        }
+
+        if exempt || !executable {
+            return;
+        }
+
+        // Nothing else in the identity region may be executed, which covers writable mappings
+        if is_in_identity_region(range.start) && !code {
+            f(Violation::ExecutableIdentityMapping);
+        } else if flags.contains(PageTableFlags::WRITABLE) {
+            f(Violation::WritableAndExecutable);
+        }
+    }
+}
+
+/// range lies within one of ranges
+fn is_within(range: &MappedRange, ranges: &[PageRange<Size4KiB>]) -> bool {
+    ranges.iter().any(|pages| {
+        pages.start.start_address() <= range.start
+            && range.end <= pages.end.start_address()
+    })
+}
+
+impl ManagedPageTable {
+    /// Call f for every mapped range that breaks an invariant
+    ///
+    /// The flags of each range are combined with those of the tables above it.
+    /// Ranges within one of exempt are only checked for user access.
+    /// Ranges within one of code may be executable in the identity region.
+    /// Returns the number of violations.
+    pub fn audit<F>(
+        &self,
+        exempt: &[PageRange<Size4KiB>],
+        code: &[PageRange<Size4KiB>],
+        mut f: F,
+    ) -> usize
+    where
+        F: FnMut(&MappedRange, Violation),
+    {
+        let mut count = 0;
+
+        self.mapped_ranges(|range| {
+            let exempt = is_within(&range, exempt);
+            let code = is_within(&range, code);
+
+            Violation::check(&range, exempt, code, |violation| {
+                count += 1;
+                f(&range, violation);
+            });
+        });
+
+        count
+    }
+}
+
+/// Audit the active page table and log all violations
+///
+/// Panics afterwards if there were any and panic_on_violation is set.
+pub fn audit_global(
+    exempt: &[PageRange<Size4KiB>],
+    code: &[PageRange<Size4KiB>],
+    panic_on_violation: bool,
+) {
+    let page_table = unsafe { ManagedPageTable::read_global() };
+
+    let violations = page_table.audit(exempt, code, |range, violation| {
+        error!("Mapping violation {:?}: {}", violation, range);
+    });
+
+    if violations == 0 {
+        info!("Mapping audit passed");
+    } else if panic_on_violation {
+        panic!("Mapping audit found {} violations", violations);
+    } else {
+        warn!("Mapping audit found {} violations", violations);
+    }
+}
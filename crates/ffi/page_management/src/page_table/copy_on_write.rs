@@ -0,0 +1,274 @@
+use crate::{
+    page_table::{
+        identity_page,
+        managed_page_table::{is_in_user_space, ManagedPageTable},
+        shared_memory::SHARED_MEMORY,
+    },
+    physical::{map::PhysicalMemoryMap, page_usage::PageUsage},
+    platform,
+};
+use core::ptr::copy_nonoverlapping;
+use log::*;
+use x86_64::{
+    structures::paging::{
+        page_table::{FrameError, PageTableEntry},
+        FrameAllocator, Page, PageSize, PageTable, PageTableFlags, PhysFrame,
+        Size4KiB,
+    },
+    VirtAddr,
+};
+
+/// Marks a page table entry that was made read-only by fork
+///
+/// Writing to such a page must give the writer its own copy of the frame.
+pub const COPY_ON_WRITE: PageTableFlags = PageTableFlags::BIT_9;
+
+/// The number of level 4 entries covering user space
+const USER_SPACE_ENTRIES: usize = 512 / 2;
+
+unsafe fn table_at<'lt>(frame: PhysFrame) -> &'lt mut PageTable {
+    &mut *identity_page(frame).start_address().as_mut_ptr()
+}
+
+/// The flags of a level 1 entry after fork
+///
+/// Shared memory stays writable, it is meant to be shared with the child.
+fn copy_on_write_flags(flags: PageTableFlags) -> PageTableFlags {
+    if flags.contains(PageTableFlags::WRITABLE)
+        && !flags.contains(SHARED_MEMORY)
+    {
+        (flags - PageTableFlags::WRITABLE) | COPY_ON_WRITE
+    } else {
+        flags
+    }
+}
+
+/// Why a write fault could not be resolved as copy on write
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum CopyOnWriteError {
+    /// The page is not mapped copy on write, so the fault is an access violation
+    NotCopyOnWrite,
+    /// The faulting core holds the physical memory map, so the frame can't be copied
+    MapLocked,
+    /// There is no frame left for the copy
+    OutOfMemory,
+}
+
+/// Copy source_entry of a page table with the given level into target_entry
+///
+/// Level 1 entries share their frame, everything above is copied recursively.
+/// source_entry is not changed.
+/// If this fails, target_entry holds what was cloned so far, to be released by release_entry.
+unsafe fn clone_entry(
+    physical_map: &mut PhysicalMemoryMap,
+    source_entry: &PageTableEntry,
+    target_entry: &mut PageTableEntry,
+    level: u8,
+) -> Option<()> {
+    let frame = match source_entry.frame() {
+        Ok(frame) => frame,
+        Err(FrameError::FrameNotPresent) => return Some(()),
+        Err(FrameError::HugeFrame) => {
+            error!("Copy on write is not supported for huge pages");
+            return None;
+        },
+    };
+
+    if level == 1 {
+        if physical_map.acquire_shared(frame).is_none() {
+            error!("User frame {:?} can't be shared", frame);
+            return None;
+        }
+        target_entry
+            .set_frame(frame, copy_on_write_flags(source_entry.flags()));
+    } else {
+        let table_frame = physical_map
+            .frame_allocator(PageUsage::PageTable)
+            .allocate_frame()?
+            .frame();
+
+        let target_table = table_at(table_frame);
+        target_table.zero();
+        target_entry.set_frame(table_frame, source_entry.flags());
+
+        for (source_entry, target_entry) in
+            table_at(frame).iter().zip(target_table.iter_mut())
+        {
+            clone_entry(physical_map, source_entry, target_entry, level - 1)?;
+        }
+    }
+
+    Some(())
+}
+
+/// Drop the references of a cloned entry and free its page tables
+unsafe fn release_entry(
+    physical_map: &mut PhysicalMemoryMap,
+    entry: &mut PageTableEntry,
+    level: u8,
+) {
+    let frame = match entry.frame() {
+        Ok(frame) => frame,
+        Err(_) => return,
+    };
+
+    if level == 1 {
+        physical_map.release_shared(frame);
+    } else {
+        for entry in table_at(frame).iter_mut() {
+            release_entry(physical_map, entry, level - 1);
+        }
+        physical_map.free(frame);
+    }
+
+    entry.set_unused();
+}
+
+/// Make the writable level 1 entries below entry copy on write
+unsafe fn mark_copy_on_write(entry: &mut PageTableEntry, level: u8) {
+    if level == 1 {
+        entry.set_flags(copy_on_write_flags(entry.flags()));
+    } else if let Ok(frame) = entry.frame() {
+        for entry in table_at(frame).iter_mut() {
+            mark_copy_on_write(entry, level - 1);
+        }
+    }
+}
+
+impl ManagedPageTable {
+    /// Create a page table that shares the kernel half with self
+    /// and holds a copy on write clone of the user half
+    ///
+    /// Writable user pages become read-only in both page tables.
+    /// The first write to such a page faults and is resolved by
+    /// handle_copy_on_write_fault.
+    ///
+    /// If this fails, everything the partial clone took is given back and self is unchanged.
+    pub fn fork(&mut self) -> Option<Self> {
+        let mut child = self.create_offspring()?;
+
+        let cloned = PhysicalMemoryMap::global(|physical_map| unsafe {
+            let self_table = self.page_table_ref();
+            let child_table = child.page_table_mut();
+
+            let cloned = self_table
+                .iter()
+                .zip(child_table.iter_mut())
+                .take(USER_SPACE_ENTRIES)
+                .all(|(self_entry, child_entry)| {
+                    clone_entry(physical_map, self_entry, child_entry, 4)
+                        .is_some()
+                });
+
+            if !cloned {
+                for entry in child_table.iter_mut().take(USER_SPACE_ENTRIES) {
+                    release_entry(physical_map, entry, 4);
+                }
+                physical_map.free(child.frame());
+            }
+
+            cloned
+        });
+
+        if !cloned {
+            return None;
+        }
+
+        unsafe {
+            for entry in
+                self.page_table_mut().iter_mut().take(USER_SPACE_ENTRIES)
+            {
+                mark_copy_on_write(entry, 4);
+            }
+        }
+
+        // Self has lost write access to its pages
+        self.invalidate_cached_translations();
+
+        Some(child)
+    }
+
+    /// Resolve a write to a copy on write page
+    ///
+    /// If the frame is still shared, it is copied and the copy is mapped instead.
+    /// Otherwise this was the last mapping and the page is simply made writable again.
+    ///
+    /// This runs in the page fault handler, so it fails with MapLocked
+    /// instead of deadlocking if the faulting core holds the physical memory map.
+    /// User space mappings are not protected by a region lock, so no other lock is taken.
+    pub fn handle_copy_on_write_fault(
+        &mut self,
+        address: VirtAddr,
+    ) -> Result<(), CopyOnWriteError> {
+        if !is_in_user_space(address) {
+            return Err(CopyOnWriteError::NotCopyOnWrite);
+        }
+
+        let page = Page::<Size4KiB>::containing_address(address);
+
+        PhysicalMemoryMap::try_global(|physical_map| unsafe {
+            let entry = self
+                .entry_mut(page)
+                .ok_or(CopyOnWriteError::NotCopyOnWrite)?;
+
+            let flags = entry.flags();
+            if !flags.contains(COPY_ON_WRITE) {
+                return Err(CopyOnWriteError::NotCopyOnWrite);
+            }
+            let flags = (flags - COPY_ON_WRITE) | PageTableFlags::WRITABLE;
+
+            let frame = entry
+                .frame()
+                .map_err(|_| CopyOnWriteError::NotCopyOnWrite)?;
+
+            match physical_map.get(frame) {
+                Some(PageUsage::Shared { refcount }) if refcount > 1 => {
+                    let copy = physical_map
+                        .frame_allocator(PageUsage::UserData)
+                        .allocate_frame()
+                        .ok_or(CopyOnWriteError::OutOfMemory)?
+                        .frame();
+
+                    copy_nonoverlapping(
+                        identity_page(frame).start_address().as_ptr::<u8>(),
+                        identity_page(copy).start_address().as_mut_ptr::<u8>(),
+                        Size4KiB::SIZE as usize,
+                    );
+
+                    physical_map.release_shared(frame);
+                    entry.set_frame(copy, flags);
+                },
+                Some(PageUsage::Shared { .. }) => {
+                    // The last holder owns the frame again
+                    physical_map.set(frame, PageUsage::UserData);
+                    entry.set_flags(flags);
+                },
+                _ => entry.set_flags(flags),
+            }
+
+            Ok(())
+        })
+        .ok_or(CopyOnWriteError::MapLocked)??;
+
+        if self.is_active() {
+            platform::flush_page(page.start_address());
+        } else {
+            self.invalidate_cached_translations();
+        }
+
+        Ok(())
+    }
+
+    /// Find the level 1 entry for page
+    unsafe fn entry_mut(
+        &mut self,
+        page: Page<Size4KiB>,
+    ) -> Option<&mut PageTableEntry> {
+        let level_4 = self.page_table_mut();
+        let level_3 = table_at(level_4[page.p4_index()].frame().ok()?);
+        let level_2 = table_at(level_3[page.p3_index()].frame().ok()?);
+        let level_1 = table_at(level_2[page.p2_index()].frame().ok()?);
+
+        Some(&mut level_1[page.p1_index()])
+    }
+}
@@ -0,0 +1,168 @@
+use crate::page_table::{
+    identity_page,
+    managed_page_table::{region_name, ManagedPageTable},
+};
+use core::fmt;
+use log::*;
+use x86_64::{
+    structures::paging::{
+        PageSize, PageTable, PageTableFlags, PhysFrame, Size1GiB, Size2MiB,
+        Size4KiB,
+    },
+    PhysAddr, VirtAddr,
+};
+
+/// A contiguous virtual range mapped to a contiguous physical range
+///
+/// All pages in the range have the same flags and size.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct MappedRange {
+    pub start: VirtAddr,
+    /// The first address after the range
+    pub end: VirtAddr,
+    pub physical: PhysAddr,
+    /// The flags of the final entry, restricted by the tables above it
+    pub flags: PageTableFlags,
+    pub page_size: u64,
+}
+
+impl MappedRange {
+    pub fn size(&self) -> u64 {
+        self.end - self.start
+    }
+
+    pub fn region_name(&self) -> &'static str {
+        region_name(self.start)
+    }
+
+    /// Extend self by next if it continues the range
+    fn merge(&mut self, next: &MappedRange) -> bool {
+        let continues = self.end == next.start
+            && self.physical + self.size() == next.physical
+            && self.flags == next.flags
+            && self.page_size == next.page_size
+            && self.region_name() == next.region_name();
+
+        if continues {
+            self.end = next.end;
+        }
+        continues
+    }
+}
+
+impl fmt::Display for MappedRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let page_size = match self.page_size {
+            Size1GiB::SIZE => "1G",
+            Size2MiB::SIZE => "2M",
+            _ => "4K",
+        };
+
+        write!(
+            f,
+            "{:#018X}-{:#018X} -> {:#014X} {} {:>8} [{}] {:?}",
+            self.start.as_u64(),
+            self.end.as_u64(),
+            self.physical.as_u64(),
+            page_size,
+            self.size() / Size4KiB::SIZE,
+            self.region_name(),
+            self.flags,
+        )
+    }
+}
+
+unsafe fn table_at<'lt>(frame: PhysFrame) -> &'lt PageTable {
+    &*identity_page(frame).start_address().as_ptr()
+}
+
+/// The flags of an entry as seen through a parent entry with parent_flags
+///
+/// Writing and user access are only allowed if every level allows them,
+/// while NO_EXECUTE on any level applies to everything below it.
+fn inherit_flags(
+    flags: PageTableFlags,
+    parent_flags: PageTableFlags,
+) -> PageTableFlags {
+    let allowed = PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
+
+    (flags - (allowed - parent_flags))
+        | (parent_flags & PageTableFlags::NO_EXECUTE)
+}
+
+/// Call f for each present leaf entry below table
+///
+/// base is the virtual address covered by the first entry of table,
+/// parent_flags are the combined flags of the entries leading to it.
+unsafe fn walk<F>(
+    table: &PageTable,
+    level: u8,
+    base: u64,
+    parent_flags: PageTableFlags,
+    f: &mut F,
+) where
+    F: FnMut(MappedRange),
+{
+    let entry_size = Size4KiB::SIZE << (9 * (level as u64 - 1));
+
+    for (index, entry) in table.iter().enumerate() {
+        if !entry.flags().contains(PageTableFlags::PRESENT) {
+            continue;
+        }
+        let flags = inherit_flags(entry.flags(), parent_flags);
+
+        let address = base + entry_size * index as u64;
+
+        if level == 1 || flags.contains(PageTableFlags::HUGE_PAGE) {
+            let start = VirtAddr::new(address);
+
+            f(MappedRange {
+                start,
+                end: start + entry_size,
+                physical: entry.addr(),
+                flags,
+                page_size: entry_size,
+            });
+        } else {
+            let frame = PhysFrame::containing_address(entry.addr());
+            walk(table_at(frame), level - 1, address, flags, f);
+        }
+    }
+}
+
+impl ManagedPageTable {
+    /// Call f for every mapping, merging neighbouring pages into ranges
+    pub fn mapped_ranges<F>(&self, mut f: F)
+    where
+        F: FnMut(MappedRange),
+    {
+        let mut current: Option<MappedRange> = None;
+
+        unsafe {
+            let root_flags =
+                PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
+
+            walk(self.page_table_ref(), 4, 0, root_flags, &mut |range| {
+                let merged = current
+                    .as_mut()
+                    .map_or(false, |current| current.merge(&range));
+
+                if !merged {
+                    if let Some(previous) = current.replace(range) {
+                        f(previous);
+                    }
+                }
+            });
+        }
+
+        if let Some(last) = current {
+            f(last);
+        }
+    }
+
+    /// Log all mappings, one range per line
+    pub fn dump(&self) {
+        debug!("Page table {:?}:", unsafe { self.frame() });
+        self.mapped_ranges(|range| debug!("    {}", range));
+    }
+}
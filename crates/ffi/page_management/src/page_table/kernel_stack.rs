@@ -0,0 +1,156 @@
+use crate::{
+    page_table::managed_page_table::{
+        kernel_stack_range, ManagedPageTable, ModificationFlags,
+    },
+    physical::page_usage::PageUsage,
+};
+use x86_64::{
+    structures::paging::{page::PageRange, Page, PageTableFlags, Size4KiB},
+    VirtAddr,
+};
+
+/// The number of mapped pages in each kernel stack
+pub const KERNEL_STACK_PAGES: u64 = 256;
+
+/// The number of unmapped pages below each kernel stack
+///
+/// A stack overflow runs into these and faults,
+/// instead of overwriting the stack below it.
+pub const KERNEL_STACK_GUARD_PAGES: u64 = 16;
+
+const SLOT_PAGES: u64 = KERNEL_STACK_GUARD_PAGES + KERNEL_STACK_PAGES;
+
+/// The slot of the stack the bootloader maps for the kernel entry
+const BOOT_STACK_SLOT: u64 = 0;
+
+fn kernel_stack_flags() -> ModificationFlags {
+    ModificationFlags {
+        kernel_stack: true,
+        ..Default::default()
+    }
+}
+
+/// A stack in the kernel stack region
+///
+/// The region is divided into slots, each holding guard pages followed by a stack.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct KernelStack {
+    slot: u64,
+}
+
+impl KernelStack {
+    /// The stack used by the kernel entry
+    ///
+    /// This is mapped by the bootloader.
+    pub fn boot() -> Self {
+        KernelStack {
+            slot: BOOT_STACK_SLOT,
+        }
+    }
+
+    fn slot_count() -> u64 {
+        let range = kernel_stack_range();
+        (range.end - range.start) / SLOT_PAGES
+    }
+
+    pub fn guard(&self) -> PageRange<Size4KiB> {
+        let start = kernel_stack_range().start + self.slot * SLOT_PAGES;
+
+        PageRange {
+            start,
+            end: start + KERNEL_STACK_GUARD_PAGES,
+        }
+    }
+
+    pub fn pages(&self) -> PageRange<Size4KiB> {
+        let start = self.guard().end;
+
+        PageRange {
+            start,
+            end: start + KERNEL_STACK_PAGES,
+        }
+    }
+
+    /// The initial stack pointer
+    pub fn top(&self) -> VirtAddr {
+        self.pages().end.start_address()
+    }
+
+    /// Find the stack whose guard pages contain address
+    pub fn from_guard_address(address: VirtAddr) -> Option<Self> {
+        let range = kernel_stack_range();
+        let page = Page::<Size4KiB>::containing_address(address);
+
+        if page < range.start || page >= range.end {
+            return None;
+        }
+
+        let offset = page - range.start;
+        if offset % SLOT_PAGES < KERNEL_STACK_GUARD_PAGES {
+            Some(KernelStack {
+                slot: offset / SLOT_PAGES,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Find the stack whose mapped pages contain address
+    pub fn containing_address(address: VirtAddr) -> Option<Self> {
+        let range = kernel_stack_range();
+        let page = Page::<Size4KiB>::containing_address(address);
+
+        if page < range.start || page >= range.end {
+            return None;
+        }
+
+        let stack = KernelStack {
+            slot: (page - range.start) / SLOT_PAGES,
+        };
+        if page >= stack.pages().start {
+            Some(stack)
+        } else {
+            None
+        }
+    }
+
+    /// Map a new stack for thread
+    ///
+    /// Its frames are tagged with the thread id.
+    pub fn allocate(thread: u32) -> Option<Self> {
+        ManagedPageTable::modify_global(kernel_stack_flags(), |manager| {
+            let stack = (0..Self::slot_count())
+                .filter(|slot| *slot != BOOT_STACK_SLOT)
+                .map(|slot| KernelStack { slot })
+                .find(|stack| manager.is_free_page(stack.pages().end - 1u64))?;
+
+            unsafe {
+                manager.map_blank_pages(
+                    stack.pages().start,
+                    KERNEL_STACK_PAGES as usize,
+                    PageTableFlags::PRESENT
+                        | PageTableFlags::WRITABLE
+                        | PageTableFlags::NO_EXECUTE,
+                    true,
+                    PageUsage::KernelStack { thread },
+                )
+            }
+            .ok()?;
+
+            Some(stack)
+        })
+    }
+
+    /// Unmap the stack and free its frames
+    ///
+    /// # Safety
+    /// Nobody may use the stack anymore, including the current thread.
+    pub unsafe fn release(self) {
+        assert_ne!(self, Self::boot());
+
+        ManagedPageTable::modify_global(kernel_stack_flags(), |manager| {
+            manager.unmap_pages_and_release(self.pages(), true)
+        })
+        .unwrap();
+    }
+}
@@ -1,16 +1,17 @@
 use crate::{
-    page_table::{identity_base, identity_page},
+    page_table::{
+        identity_base, identity_page, pcid,
+        tlb_shootdown::{shootdown, ShootdownBatch},
+    },
     physical::{map::PhysicalMemoryMap, page_usage::PageUsage},
+    platform,
 };
-use core::cell::RefCell;
 use log::*;
 use spin::{Mutex, MutexGuard};
 use x86_64::{
-    instructions::interrupts,
-    registers::control::{Cr3, Cr3Flags},
     structures::paging::{
-        mapper::TranslateResult, page::PageRange, FrameAllocator,
-        FrameDeallocator, Mapper, MapperAllSizes, OffsetPageTable, Page,
+        mapper::TranslateResult, page::PageRange, page_table::PageTableEntry,
+        FrameAllocator, Mapper, MapperAllSizes, OffsetPageTable, Page,
         PageTable, PageTableFlags, PhysFrame, Size4KiB, UnusedPhysFrame,
     },
     PhysAddr, VirtAddr,
@@ -44,6 +45,14 @@ pub const KERNEL_HEAP_END: u64 =
 const KERNEL_STACK_REGION: u64 = 7;
 pub const KERNEL_STACK_BASE: u64 =
     KERNEL_ADDRESS_SPACE_BASE + KERNEL_REGION_SIZE * KERNEL_STACK_REGION;
+pub const KERNEL_STACK_END: u64 =
+    KERNEL_ADDRESS_SPACE_BASE + KERNEL_REGION_SIZE * (KERNEL_STACK_REGION + 1);
+
+const KERNEL_MMIO_REGION: u64 = 8;
+pub const KERNEL_MMIO_BASE: u64 =
+    KERNEL_ADDRESS_SPACE_BASE + KERNEL_REGION_SIZE * KERNEL_MMIO_REGION;
+pub const KERNEL_MMIO_END: u64 =
+    KERNEL_ADDRESS_SPACE_BASE + KERNEL_REGION_SIZE * (KERNEL_MMIO_REGION + 1);
 
 fn address_region(address: VirtAddr) -> u64 {
     if !is_in_kernel_space(address) {
@@ -60,12 +69,27 @@ fn address_region(address: VirtAddr) -> u64 {
     }
 }
 
+/// The name of the region containing address
+pub fn region_name(address: VirtAddr) -> &'static str {
+    match address_region(address) {
+        core::u64::MAX => "user",
+        IDENTITY_REGION => "identity",
+        KERNEL_HEAP_REGION => "heap",
+        KERNEL_STACK_REGION => "stack",
+        KERNEL_MMIO_REGION => "mmio",
+        _ => "unknown",
+    }
+}
+
 pub fn is_in_user_space(address: VirtAddr) -> bool {
     address.as_u64() < USER_ADDRESS_SPACE_END
 }
 pub fn is_in_kernel_space(address: VirtAddr) -> bool {
     address.as_u64() >= KERNEL_ADDRESS_SPACE_BASE
 }
+pub fn is_in_identity_region(address: VirtAddr) -> bool {
+    address_region(address) == IDENTITY_REGION
+}
 
 pub fn kernel_heap_range() -> PageRange<Size4KiB> {
     PageRange {
@@ -80,11 +104,38 @@ pub fn kernel_heap_range() -> PageRange<Size4KiB> {
     }
 }
 
+pub fn kernel_stack_range() -> PageRange<Size4KiB> {
+    PageRange {
+        start: Page::<Size4KiB>::from_start_address(VirtAddr::new(
+            KERNEL_STACK_BASE,
+        ))
+        .unwrap(),
+        end: Page::<Size4KiB>::from_start_address(VirtAddr::new(
+            KERNEL_STACK_END,
+        ))
+        .unwrap(),
+    }
+}
+
+pub fn kernel_mmio_range() -> PageRange<Size4KiB> {
+    PageRange {
+        start: Page::<Size4KiB>::from_start_address(VirtAddr::new(
+            KERNEL_MMIO_BASE,
+        ))
+        .unwrap(),
+        end: Page::<Size4KiB>::from_start_address(VirtAddr::new(
+            KERNEL_MMIO_END,
+        ))
+        .unwrap(),
+    }
+}
+
 /// A standard page table
 ///
 /// All page tables share their high half mappings and have unique user space mappings.
 #[repr(transparent)]
 pub struct ManagedPageTable {
+    /// The root frame, with the PCID in the low bits if one is assigned
     root: u64,
 }
 
@@ -104,7 +155,9 @@ impl ManagedPageTable {
     /// You don't know which one this exactly is. You should keep your hands off of any
     /// unserspace mappings
     pub unsafe fn read_global() -> Self {
-        Self::from_raw_frame(Cr3::read().0)
+        ManagedPageTable {
+            root: pcid::read_cr3(),
+        }
     }
 
     /// Get the raw, underlying physical frame
@@ -114,20 +167,39 @@ impl ManagedPageTable {
     /// Using this function, you can create copies of the page table, which may lead to
     /// double frees of page tables.
     pub unsafe fn frame(&self) -> PhysFrame<Size4KiB> {
-        PhysFrame::from_start_address(PhysAddr::new(self.root)).unwrap()
+        PhysFrame::containing_address(PhysAddr::new(self.root))
     }
 
     /// Write this page table to Cr3
     ///
+    /// If PCIDs are enabled, cached translations of this page table are kept.
+    ///
     /// # Safety
     /// You can break any and all pointers by using this.
     /// You better be sure all old references are still valid after changing the page table
-    pub unsafe fn activate(&self) -> PhysFrame<Size4KiB> {
-        let (old_frame, _flags) = Cr3::read();
-        Cr3::write(self.frame(), Cr3Flags::empty());
+    pub unsafe fn activate(&mut self) -> PhysFrame<Size4KiB> {
+        let old_frame =
+            PhysFrame::containing_address(PhysAddr::new(pcid::read_cr3()));
+        pcid::switch_to(&mut self.root);
         old_frame
     }
 
+    pub fn is_active(&self) -> bool {
+        unsafe { self.frame() == Self::read_global().frame() }
+    }
+
+    /// Make sure no cached translations of this page table are used anymore
+    ///
+    /// The current core flushes the active page table.
+    /// Otherwise, the PCID is dropped and a fresh one is assigned on activation.
+    pub fn invalidate_cached_translations(&mut self) {
+        if self.is_active() {
+            pcid::flush_current();
+        } else {
+            pcid::revoke(&mut self.root);
+        }
+    }
+
     pub unsafe fn page_table_ref(&self) -> &PageTable {
         &*identity_page(self.frame()).start_address().as_ptr()
     }
@@ -164,6 +236,45 @@ impl ManagedPageTable {
         )
     }
 
+    /// Mark all kernel mappings of self as global
+    ///
+    /// The loader does not map anything global.
+    /// With PCIDs, such translations are cached per address space and invlpg only
+    /// drops them for the current one, so they are made global before PCIDs are used.
+    ///
+    /// # Safety
+    /// No other core may modify the kernel mappings at the same time.
+    pub(crate) unsafe fn make_kernel_mappings_global(&mut self) {
+        unsafe fn mark_global(entry: &mut PageTableEntry, level: u8) {
+            let flags = entry.flags();
+            if !flags.contains(PageTableFlags::PRESENT) {
+                return;
+            }
+
+            if level == 1 || flags.contains(PageTableFlags::HUGE_PAGE) {
+                entry.set_flags(flags | PageTableFlags::GLOBAL);
+                return;
+            }
+
+            let table: &mut PageTable = &mut *identity_page(
+                PhysFrame::containing_address(entry.addr()),
+            )
+            .start_address()
+            .as_mut_ptr();
+
+            for entry in table.iter_mut() {
+                mark_global(entry, level - 1);
+            }
+        }
+
+        let table = self.page_table_mut();
+        let half_size = table.iter().count() / 2;
+
+        for entry in table.iter_mut().skip(half_size) {
+            mark_global(entry, 4);
+        }
+    }
+
     pub fn create_offspring(&self) -> Option<Self> {
         PhysicalMemoryMap::global(|physical_memory_map| {
             let root_frame = physical_memory_map
@@ -199,7 +310,7 @@ impl ManagedPageTable {
     /// The memory used for this page table is released.
     /// Any attempts to use it at a later point will lead to nasty bugs.
     pub unsafe fn dispose(self) {
-        assert_ne!(self.frame(), Cr3::read().0);
+        assert!(!self.is_active());
         unimplemented!("Dispose not implemented")
     }
 
@@ -210,7 +321,7 @@ impl ManagedPageTable {
     where
         F: FnOnce(&mut ModificationManager) -> R,
     {
-        interrupts::without_interrupts(|| {
+        platform::without_interrupts(|| {
             // We have to be careful.
             // Because we use raw Mutexes here, we must disable interrupts in this context
 
@@ -218,9 +329,25 @@ impl ManagedPageTable {
                 user_space: flags.user_space,
                 guards: MUTEXES.lock(flags),
                 page_table: self,
+                shootdown: ShootdownBatch::new(),
+                user_space_unmapped: false,
             };
 
-            f(&mut manager)
+            let result = f(&mut manager);
+
+            // Cores waiting for a region lock spin with interrupts disabled
+            // and could never acknowledge the shootdown, so the locks are released first
+            drop(manager.guards);
+
+            // The kernel half is shared, so other cores may still use stale translations
+            shootdown(&manager.shootdown);
+
+            // Translations of inactive page tables may still be cached under their PCID
+            if manager.user_space_unmapped && !manager.page_table.is_active() {
+                pcid::revoke(&mut manager.page_table.root);
+            }
+
+            result
         })
     }
 
@@ -250,12 +377,14 @@ pub struct ModificationFlags {
     pub identity: bool,
     pub kernel_stack: bool,
     pub kernel_heap: bool,
+    pub mmio: bool,
 }
 
 struct ModificationMutexes {
     identity: Mutex<()>,
     kernel_stack: Mutex<()>,
     kernel_heap: Mutex<()>,
+    mmio: Mutex<()>,
 }
 
 impl ModificationMutexes {
@@ -275,11 +404,17 @@ impl ModificationMutexes {
         } else {
             None
         };
+        let mmio = if flags.mmio {
+            Some(self.mmio.lock())
+        } else {
+            None
+        };
 
         ModificationGuards {
             identity,
             kernel_stack,
             kernel_heap,
+            mmio,
         }
     }
 }
@@ -288,12 +423,14 @@ struct ModificationGuards<'lt> {
     identity: Option<MutexGuard<'lt, ()>>,
     kernel_stack: Option<MutexGuard<'lt, ()>>,
     kernel_heap: Option<MutexGuard<'lt, ()>>,
+    mmio: Option<MutexGuard<'lt, ()>>,
 }
 
 static MUTEXES: ModificationMutexes = ModificationMutexes {
     identity: Mutex::new(()),
     kernel_stack: Mutex::new(()),
     kernel_heap: Mutex::new(()),
+    mmio: Mutex::new(()),
 };
 
 /// A struct that makes sure the correct Mutexes are held to make the modifications safe(ish)
@@ -301,20 +438,29 @@ pub struct ModificationManager<'page_table> {
     user_space: bool,
     guards: ModificationGuards<'static>,
     page_table: &'page_table mut ManagedPageTable,
+    shootdown: ShootdownBatch,
+    user_space_unmapped: bool,
 }
 
 impl<'page_table> ModificationManager<'page_table> {
-    unsafe fn map_pages_impl<It, A, Af>(
+    /// Map frame_count pages starting at start_page to the frames returned by frames
+    ///
+    /// If frames runs out or a page table can not be allocated,
+    /// the pages mapped so far are unmapped again and each of their frames,
+    /// as well as the one that could not be mapped, is passed to release.
+    unsafe fn map_pages_impl<It, R, A, Af>(
         &mut self,
         start_page: Page<Size4KiB>,
         mut frames: It,
+        mut release: R,
         frame_count: usize,
         flags: PageTableFlags,
         flush: bool,
         mut frame_allocator_function: Af,
     ) -> Result<(), ()>
     where
-        It: FnMut(&mut PhysicalMemoryMap) -> PhysFrame,
+        It: FnMut(&mut PhysicalMemoryMap) -> Option<PhysFrame>,
+        R: FnMut(&mut PhysicalMemoryMap, PhysFrame),
         A: FrameAllocator<Size4KiB>,
         Af: FnMut(*mut PhysicalMemoryMap<'static>) -> A,
     {
@@ -325,29 +471,61 @@ impl<'page_table> ModificationManager<'page_table> {
             end: start_page + frame_count,
         })?;
 
-        PhysicalMemoryMap::global(|physical_map| {
-            let physical_map = RefCell::new(physical_map);
+        // With PCIDs, kernel mappings have to be global to be shared between address spaces
+        let flags = if pcid::is_enabled()
+            && is_in_kernel_space(start_page.start_address())
+        {
+            flags | PageTableFlags::GLOBAL
+        } else {
+            flags
+        };
 
+        PhysicalMemoryMap::global(|physical_map| {
             let mut mapper = self.page_table.mapper();
 
-            for (index, frame) in (0..frame_count)
-                .map(|index| (index, frames(&mut physical_map.borrow_mut())))
-            {
-                let mut physical_map = physical_map.borrow_mut();
-                let physical_map: &mut PhysicalMemoryMap = &mut physical_map;
-
-                let flusher = mapper
-                    .map_to(
-                        start_page + index,
-                        UnusedPhysFrame::new(frame),
-                        flags,
-                        &mut frame_allocator_function(physical_map as *mut _),
-                    )
-                    .unwrap();
+            for index in 0..frame_count {
+                let page = start_page + index;
+
+                let mapped = match frames(physical_map) {
+                    Some(frame) => {
+                        let result = mapper.map_to(
+                            page,
+                            UnusedPhysFrame::new(frame),
+                            flags,
+                            &mut frame_allocator_function(
+                                &mut *physical_map as *mut _,
+                            ),
+                        );
+
+                        match result {
+                            Ok(flusher) => {
+                                flusher.ignore();
+                                true
+                            },
+                            Err(_) => {
+                                release(physical_map, frame);
+                                false
+                            },
+                        }
+                    },
+                    None => false,
+                };
+
+                if !mapped {
+                    // The pages mapped so far were not present before and have
+                    // not been used, so no flush is needed
+                    for page in (0..index).map(|index| start_page + index) {
+                        if let Ok((frame, flusher)) = mapper.unmap(page) {
+                            flusher.ignore();
+                            release(physical_map, frame);
+                        }
+                    }
+
+                    return Err(());
+                }
+
                 if flush {
-                    flusher.flush();
-                } else {
-                    flusher.ignore();
+                    platform::flush_page(page.start_address());
                 }
             }
 
@@ -368,7 +546,8 @@ impl<'page_table> ModificationManager<'page_table> {
         let frame_count = frames.len();
         self.map_pages_impl(
             start_page,
-            |_| frames.next().unwrap(),
+            |_| frames.next(),
+            |_, _| {},
             frame_count,
             flags,
             flush,
@@ -393,7 +572,8 @@ impl<'page_table> ModificationManager<'page_table> {
         let frame_count = frames.len();
         self.map_pages_impl(
             start_page,
-            |_| frames.next().unwrap(),
+            |_| frames.next(),
+            |_, _| {},
             frame_count,
             flags,
             flush,
@@ -419,6 +599,9 @@ impl<'page_table> ModificationManager<'page_table> {
         )
     }
 
+    /// Map length pages to newly allocated frames
+    ///
+    /// Fails without mapping anything if the frames run out.
     pub unsafe fn map_blank_pages(
         &mut self,
         start_page: Page<Size4KiB>,
@@ -433,8 +616,10 @@ impl<'page_table> ModificationManager<'page_table> {
                 physical_map
                     .frame_allocator(usage)
                     .allocate_frame()
-                    .unwrap()
-                    .frame()
+                    .map(|frame| frame.frame())
+            },
+            |physical_map, frame| {
+                physical_map.free(frame);
             },
             length,
             flags,
@@ -456,15 +641,24 @@ impl<'page_table> ModificationManager<'page_table> {
     {
         self.is_valid_range(range.clone())?;
 
+        // Newly mapped pages were not present before, so only unmapping
+        // has to be propagated to the other cores
+        if is_in_kernel_space(range.start.start_address()) {
+            if flush {
+                self.shootdown.add(range.clone());
+            }
+        } else {
+            self.user_space_unmapped = true;
+        }
+
         PhysicalMemoryMap::global(|mut physical_map| {
             let mut mapper = self.page_table.mapper();
 
             for page in range {
                 let result = mapper.unmap(page).map(|(frame, flusher)| {
+                    flusher.ignore();
                     if flush {
-                        flusher.flush();
-                    } else {
-                        flusher.ignore();
+                        platform::flush_page(page.start_address());
                     }
                     frame
                 });
@@ -475,6 +669,9 @@ impl<'page_table> ModificationManager<'page_table> {
         })
     }
 
+    /// Unmap range and drop a reference to each frame
+    ///
+    /// Frames are only freed once their last reference is gone.
     pub unsafe fn unmap_pages_and_release(
         &mut self,
         range: PageRange<Size4KiB>,
@@ -482,7 +679,9 @@ impl<'page_table> ModificationManager<'page_table> {
     ) -> Result<(), ()> {
         self.unmap_pages(range, flush, |physical_map, frame| {
             if let Some(frame) = frame {
-                physical_map.deallocate_frame(UnusedPhysFrame::new(frame));
+                if physical_map.release_shared(frame).is_none() {
+                    warn!("Released frame {:?} was not in use", frame);
+                }
             }
         })
     }
@@ -547,6 +746,12 @@ impl<'page_table> ModificationManager<'page_table> {
                         return Err(());
                     }
                 },
+                KERNEL_MMIO_REGION => {
+                    if self.guards.mmio.is_none() {
+                        error!("Attempted to modify mmio region without lock");
+                        return Err(());
+                    }
+                },
                 _ => {
                     error!(
                         "Attempted to modify unknown region {}",
@@ -560,7 +765,7 @@ impl<'page_table> ModificationManager<'page_table> {
         Ok(())
     }
 
-    fn is_free_page(&self, page: Page<Size4KiB>) -> bool {
+    pub fn is_free_page(&self, page: Page<Size4KiB>) -> bool {
         match unsafe {
             self.page_table
                 .mapper_from_ref()
@@ -585,7 +790,7 @@ impl<'page_table> ModificationManager<'page_table> {
 
         self.is_valid_range(range).ok()?;
 
-        'start_index_loop: for start in 0..(range_size - desired_size) {
+        'start_index_loop: for start in 0..=(range_size - desired_size) {
             let start_page = range.start + start;
 
             // This can also be implemented more efficiently
@@ -607,7 +812,7 @@ impl<'page_table> ModificationManager<'page_table> {
             // If all the pages we checked above are free, we can report that we found a free range
             return Some(PageRange {
                 start: start_page,
-                end: start_page + range_size,
+                end: start_page + desired_size,
             });
         }
 
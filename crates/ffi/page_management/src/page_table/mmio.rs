@@ -0,0 +1,238 @@
+use crate::{
+    page_table::{
+        identity_page,
+        managed_page_table::{
+            kernel_mmio_range, ManagedPageTable, ModificationFlags,
+            ModificationManager,
+        },
+    },
+    physical::map::PhysicalMemoryMap,
+};
+use core::{
+    cmp::{max, min},
+    sync::atomic::{AtomicBool, Ordering},
+};
+use raw_cpuid::CpuId;
+use x86_64::{
+    registers::model_specific::Msr,
+    structures::paging::{
+        frame::PhysFrameRange, page::PageRange, Page, PageSize, PageTableFlags,
+        PhysFrame, Size4KiB,
+    },
+    PhysAddr, VirtAddr,
+};
+
+const IA32_PAT: u32 = 0x277;
+
+const PAT_UNCACHED: u64 = 0x00;
+const PAT_WRITE_COMBINING: u64 = 0x01;
+const PAT_WRITE_THROUGH: u64 = 0x04;
+const PAT_WRITE_BACK: u64 = 0x06;
+
+/// The memory type of each PAT entry
+///
+/// This matches the reset value, except that entry 2 (PCD) is write combining
+/// instead of weakly uncached. The upper half mirrors the lower one,
+/// as the PAT bit of 4KiB entries shares its position with HUGE_PAGE.
+const PAT_ENTRIES: [u64; 8] = [
+    PAT_WRITE_BACK,
+    PAT_WRITE_THROUGH,
+    PAT_WRITE_COMBINING,
+    PAT_UNCACHED,
+    PAT_WRITE_BACK,
+    PAT_WRITE_THROUGH,
+    PAT_WRITE_COMBINING,
+    PAT_UNCACHED,
+];
+
+static PAT_INITIALIZED: AtomicBool = AtomicBool::new(false);
+
+/// The caching behaviour of a mapping
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum CacheType {
+    WriteBack,
+    WriteThrough,
+    WriteCombining,
+    Uncached,
+}
+
+impl CacheType {
+    /// The page table flags selecting the PAT entry of this type
+    pub fn flags(self) -> PageTableFlags {
+        match self {
+            CacheType::WriteBack => PageTableFlags::empty(),
+            CacheType::WriteThrough => PageTableFlags::WRITE_THROUGH,
+            CacheType::WriteCombining => PageTableFlags::NO_CACHE,
+            CacheType::Uncached => {
+                PageTableFlags::NO_CACHE | PageTableFlags::WRITE_THROUGH
+            },
+        }
+    }
+}
+
+/// Program the PAT, so every CacheType can be selected
+///
+/// Without PAT support, write combining mappings fall back to weakly uncached.
+///
+/// # Safety
+/// This must be called on each core before any mapping uses NO_CACHE
+/// without WRITE_THROUGH.
+pub unsafe fn init_pat() -> bool {
+    let supported = CpuId::new()
+        .get_feature_info()
+        .map_or(false, |info| info.has_pat());
+
+    if !supported {
+        return false;
+    }
+
+    let value = PAT_ENTRIES
+        .iter()
+        .enumerate()
+        .fold(0, |value, (index, entry)| value | (entry << (index * 8)));
+    Msr::new(IA32_PAT).write(value);
+    PAT_INITIALIZED.store(true, Ordering::Release);
+
+    true
+}
+
+pub fn is_pat_initialized() -> bool {
+    PAT_INITIALIZED.load(Ordering::Acquire)
+}
+
+fn mmio_flags() -> ModificationFlags {
+    ModificationFlags {
+        mmio: true,
+        ..Default::default()
+    }
+}
+
+fn mmio_and_identity_flags() -> ModificationFlags {
+    ModificationFlags {
+        identity: true,
+        ..mmio_flags()
+    }
+}
+
+fn page_count(address: PhysAddr, size: u64) -> u64 {
+    let start = address.align_down(Size4KiB::SIZE);
+    let end = (address + size).align_up(Size4KiB::SIZE);
+    (end - start) / Size4KiB::SIZE
+}
+
+/// Map frames back to their identity position
+///
+/// Only the physical memory map is identity mapped,
+/// so frames outside of it never had an alias.
+unsafe fn restore_identity_alias(
+    manager: &mut ModificationManager,
+    frames: PhysFrameRange,
+) -> Result<(), ()> {
+    let physical_range = PhysicalMemoryMap::global(|map| map.physical_range());
+    let start = max(frames.start, physical_range.start);
+    let end = min(frames.end, physical_range.end);
+
+    if start >= end {
+        return Ok(());
+    }
+
+    manager.map_pages(
+        identity_page(start),
+        PhysFrame::range(start, end),
+        PageTableFlags::PRESENT
+            | PageTableFlags::WRITABLE
+            | PageTableFlags::NO_EXECUTE,
+        true,
+    )
+}
+
+/// Map size bytes of device memory starting at address into the mmio region
+///
+/// Returns the virtual address of address, or None if size is 0.
+///
+/// The identity region maps all physical memory write back.
+/// Aliases with different memory types are undefined behaviour,
+/// so the identity mapping of the range is removed first.
+///
+/// # Safety
+/// The physical range must belong to a device and not to memory
+/// tracked by the physical memory map.
+pub unsafe fn map_mmio(
+    address: PhysAddr,
+    size: u64,
+    cache_type: CacheType,
+) -> Option<VirtAddr> {
+    if size == 0 {
+        return None;
+    }
+
+    let first_frame = PhysFrame::<Size4KiB>::containing_address(address);
+    let pages = page_count(address, size);
+
+    let identity_alias = PageRange {
+        start: identity_page(first_frame),
+        end: identity_page(first_frame) + pages,
+    };
+
+    let start_page = ManagedPageTable::modify_global(
+        mmio_and_identity_flags(),
+        |manager| {
+            manager.unmap_pages(identity_alias, true, |_, _| {}).ok()?;
+
+            let start_page = manager
+                .find_free_pages_in_range(kernel_mmio_range(), pages, 1)
+                .and_then(|range| {
+                    manager
+                        .map_pages(
+                            range.start,
+                            (0..pages as usize)
+                                .map(|index| first_frame + index as u64),
+                            PageTableFlags::PRESENT
+                                | PageTableFlags::WRITABLE
+                                | PageTableFlags::NO_EXECUTE
+                                | cache_type.flags(),
+                            true,
+                        )
+                        .ok()?;
+                    Some(range.start)
+                });
+
+            if start_page.is_none() {
+                restore_identity_alias(
+                    manager,
+                    PhysFrame::range(first_frame, first_frame + pages),
+                )
+                .ok()?;
+            }
+
+            start_page
+        },
+    )?;
+
+    Some(start_page.start_address() + (address - first_frame.start_address()))
+}
+
+/// Remove a mapping created by map_mmio and restore the identity alias of its frames
+///
+/// # Safety
+/// The mapping may not be used anymore.
+pub unsafe fn unmap_mmio(address: VirtAddr, size: u64) {
+    assert_ne!(size, 0, "map_mmio does not create empty mappings");
+
+    let start = Page::<Size4KiB>::containing_address(address);
+    let end = Page::<Size4KiB>::containing_address(address + size - 1u64) + 1;
+
+    ManagedPageTable::modify_global(mmio_and_identity_flags(), |manager| {
+        let mut first_frame = None;
+        manager.unmap_pages(PageRange { start, end }, true, |_, frame| {
+            first_frame = first_frame.or(frame);
+        })?;
+
+        let first_frame = first_frame.ok_or(())?;
+        restore_identity_alias(
+            manager,
+            PhysFrame::range(first_frame, first_frame + (end - start)),
+        )
+    })
+    .unwrap();
+}
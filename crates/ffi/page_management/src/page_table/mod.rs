@@ -1,4 +1,12 @@
+pub mod audit;
+pub mod copy_on_write;
+pub mod inspect;
+pub mod kernel_stack;
 pub mod managed_page_table;
+pub mod mmio;
+pub mod pcid;
+pub mod shared_memory;
+pub mod tlb_shootdown;
 
 use core::sync::atomic::{AtomicUsize, Ordering};
 use x86_64::{
@@ -6,7 +14,7 @@ use x86_64::{
     PhysAddr, VirtAddr,
 };
 
-const DEFAULT_IDENTITY_BASE: usize = core::usize::MAX;
+pub(crate) const DEFAULT_IDENTITY_BASE: usize = core::usize::MAX;
 pub(crate) static IDENTITY_BASE: AtomicUsize =
     AtomicUsize::new(DEFAULT_IDENTITY_BASE);
 
@@ -0,0 +1,199 @@
+use crate::{
+    page_table::managed_page_table::ManagedPageTable,
+    platform::{self, Lock},
+};
+use core::sync::atomic::{AtomicBool, Ordering};
+use cpu_local_storage::get_core_id;
+use raw_cpuid::CpuId;
+use x86_64::VirtAddr;
+
+/// The PCID is stored in the low bits of cr3, below the root frame
+const PCID_MASK: u64 = 0xFFF;
+const PCID_COUNT: usize = 4096;
+
+/// Set in cr3 to keep the cached translations of the new PCID
+const CR3_NO_FLUSH: u64 = 1 << 63;
+
+const CR4_GLOBAL_PAGES: u64 = 1 << 7;
+const CR4_PCID_ENABLE: u64 = 1 << 17;
+
+const MAX_CORES: usize = 64;
+
+#[repr(u64)]
+enum InvalidationType {
+    SingleContext = 1,
+    AllContextsIncludingGlobal = 2,
+    AllContexts = 3,
+}
+
+/// Hands out PCIDs to page tables
+///
+/// PCIDs are never reused within a generation.
+/// Once they run out, a new generation starts and each core flushes
+/// all contexts before its next switch.
+/// This way, a page table is never switched to with stale entries tagged with its PCID.
+struct PcidAllocator {
+    /// The root frame address of each PCID's owner, or 0 if it has none
+    owners: [u64; PCID_COUNT],
+    next: usize,
+    generation: u64,
+    seen_generation: [u64; MAX_CORES],
+}
+
+impl PcidAllocator {
+    fn allocate(&mut self, frame: u64) -> u64 {
+        if self.next >= PCID_COUNT {
+            self.generation += 1;
+            self.next = 1;
+
+            for owner in self.owners.iter_mut() {
+                *owner = 0;
+            }
+        }
+
+        let pcid = self.next;
+        self.next += 1;
+        self.owners[pcid] = frame;
+
+        pcid as u64
+    }
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+// PCID 0 is left to page tables that were activated before PCIDs were enabled
+static ALLOCATOR: Lock<PcidAllocator> = Lock::new(PcidAllocator {
+    owners: [0; PCID_COUNT],
+    next: 1,
+    generation: 0,
+    seen_generation: [0; MAX_CORES],
+});
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Acquire)
+}
+
+fn core_index() -> usize {
+    let index = (get_core_id().optional_full_id() - 1) as usize;
+    assert!(
+        index < MAX_CORES,
+        "PCIDs support at most {} cores",
+        MAX_CORES
+    );
+    index
+}
+
+unsafe fn invpcid(kind: InvalidationType, pcid: u64, address: VirtAddr) {
+    platform::invpcid(kind as u64, pcid, address);
+}
+
+/// Enable PCIDs if the processor supports both PCID and INVPCID
+///
+/// Kernel mappings are made global, so they survive address space switches.
+///
+/// # Safety
+/// This must be called on each core while its cr3 still uses PCID 0,
+/// before any other page table is activated.
+pub unsafe fn init() -> bool {
+    let cpuid = CpuId::new();
+    let supported = cpuid
+        .get_feature_info()
+        .map_or(false, |info| info.has_pcid() && info.has_pge())
+        && cpuid
+            .get_extended_feature_info()
+            .map_or(false, |info| info.has_invpcid());
+
+    if !supported {
+        return false;
+    }
+
+    platform::write_cr4(
+        platform::read_cr4() | CR4_GLOBAL_PAGES | CR4_PCID_ENABLE,
+    );
+    ManagedPageTable::read_global().make_kernel_mappings_global();
+    ENABLED.store(true, Ordering::Release);
+
+    true
+}
+
+/// The raw value of cr3, including the PCID
+pub(crate) fn read_cr3() -> u64 {
+    platform::read_cr3()
+}
+
+/// Write root to cr3, assigning a fresh PCID if root does not own one
+pub(crate) unsafe fn switch_to(root: &mut u64) {
+    let frame = *root & !PCID_MASK;
+
+    if !is_enabled() {
+        platform::write_cr3(frame);
+        return;
+    }
+
+    let value = ALLOCATOR.lock(|allocator| {
+        let mut pcid = *root & PCID_MASK;
+        if pcid == 0 || allocator.owners[pcid as usize] != frame {
+            pcid = allocator.allocate(frame);
+        }
+        *root = frame | pcid;
+
+        let core = core_index();
+        if allocator.seen_generation[core] != allocator.generation {
+            allocator.seen_generation[core] = allocator.generation;
+            invpcid(InvalidationType::AllContexts, 0, VirtAddr::new(0));
+        }
+
+        frame | pcid | CR3_NO_FLUSH
+    });
+
+    platform::write_cr3(value);
+}
+
+/// Take away the PCID of root
+///
+/// Its next activation will get a fresh PCID without any cached translations.
+pub(crate) fn revoke(root: &mut u64) {
+    let frame = *root & !PCID_MASK;
+    let pcid = (*root & PCID_MASK) as usize;
+    *root = frame;
+
+    if pcid == 0 || !is_enabled() {
+        return;
+    }
+
+    ALLOCATOR.lock(|allocator| {
+        if allocator.owners[pcid] == frame {
+            allocator.owners[pcid] = 0;
+        }
+    });
+}
+
+/// Flush all non-global translations of the current address space
+pub fn flush_current() {
+    if is_enabled() {
+        unsafe {
+            invpcid(
+                InvalidationType::SingleContext,
+                read_cr3() & PCID_MASK,
+                VirtAddr::new(0),
+            );
+        }
+    } else {
+        platform::flush_all();
+    }
+}
+
+/// Flush all translations of every address space, including global ones
+pub fn flush_everything() {
+    if is_enabled() {
+        unsafe {
+            invpcid(
+                InvalidationType::AllContextsIncludingGlobal,
+                0,
+                VirtAddr::new(0),
+            );
+        }
+    } else {
+        platform::flush_all();
+    }
+}
@@ -0,0 +1,225 @@
+use crate::{
+    page_table::{
+        identity_page,
+        managed_page_table::{
+            is_in_user_space, ManagedPageTable, ModificationFlags,
+        },
+    },
+    physical::{map::PhysicalMemoryMap, page_usage::PageUsage},
+};
+use core::ptr::write_bytes;
+use log::*;
+use x86_64::{
+    structures::paging::{
+        page::PageRange, FrameAllocator, Page, PageSize, PageTableFlags,
+        PhysFrame, Size4KiB,
+    },
+    PhysAddr,
+};
+
+/// The frame list of an object fits into a single frame
+pub const SHARED_MEMORY_MAX_PAGES: usize = 512;
+
+/// Marks a page table entry that maps shared memory
+///
+/// Fork keeps such pages writable instead of making them copy on write.
+pub const SHARED_MEMORY: PageTableFlags = PageTableFlags::BIT_10;
+
+type FrameList = [u64; SHARED_MEMORY_MAX_PAGES];
+
+fn user_space() -> ModificationFlags {
+    ModificationFlags {
+        user_space: true,
+        ..Default::default()
+    }
+}
+
+/// A handle to frames that can be mapped into multiple user address spaces
+///
+/// Each handle and each mapping holds a reference to every frame.
+/// The frame list is itself shared, its reference count is the number of handles.
+/// Frames are only freed once the last handle is dropped and the last mapping unmapped.
+pub struct SharedMemory {
+    list: PhysFrame,
+    pages: usize,
+}
+
+impl SharedMemory {
+    /// Allocate an object of zeroed pages
+    ///
+    /// Returns None if pages is 0 or there is not enough memory.
+    pub fn new(pages: usize) -> Option<Self> {
+        assert!(pages <= SHARED_MEMORY_MAX_PAGES);
+
+        if pages == 0 {
+            return None;
+        }
+
+        PhysicalMemoryMap::global(|physical_map| unsafe {
+            let list = physical_map
+                .frame_allocator(PageUsage::Shared { refcount: 1 })
+                .allocate_frame()?
+                .frame();
+            let mut object = SharedMemory { list, pages: 0 };
+
+            while object.pages < pages {
+                let frame = match physical_map
+                    .frame_allocator(PageUsage::Shared { refcount: 1 })
+                    .allocate_frame()
+                {
+                    Some(frame) => frame.frame(),
+                    None => {
+                        // Dropping would lock the map again
+                        object.release(physical_map);
+                        core::mem::forget(object);
+                        return None;
+                    },
+                };
+
+                write_bytes(
+                    identity_page(frame).start_address().as_mut_ptr::<u8>(),
+                    0,
+                    Size4KiB::SIZE as usize,
+                );
+
+                object.frame_list()[object.pages] =
+                    frame.start_address().as_u64();
+                object.pages += 1;
+            }
+
+            Some(object)
+        })
+    }
+
+    pub fn pages(&self) -> usize {
+        self.pages
+    }
+
+    #[allow(clippy::mut_from_ref)]
+    unsafe fn frame_list(&self) -> &mut FrameList {
+        &mut *identity_page(self.list)
+            .start_address()
+            .as_mut_ptr::<FrameList>()
+    }
+
+    pub fn frames<'this>(
+        &'this self,
+    ) -> impl 'this + ExactSizeIterator<Item = PhysFrame> {
+        let list = unsafe { self.frame_list() };
+
+        list[..self.pages].iter().map(|address| {
+            PhysFrame::containing_address(PhysAddr::new(*address))
+        })
+    }
+
+    /// Map the object into the user half of page_table, starting at start
+    ///
+    /// PRESENT, USER_ACCESSIBLE and SHARED_MEMORY are added to flags.
+    /// Fails if any page in the range is already mapped.
+    pub fn map(
+        &self,
+        page_table: &mut ManagedPageTable,
+        start: Page<Size4KiB>,
+        flags: PageTableFlags,
+    ) -> Result<PageRange<Size4KiB>, ()> {
+        let range = PageRange {
+            start,
+            end: start + self.pages as u64,
+        };
+
+        if !is_in_user_space(range.start.start_address())
+            || !is_in_user_space((range.end - 1u64).start_address())
+        {
+            return Err(());
+        }
+
+        PhysicalMemoryMap::global(|physical_map| {
+            for frame in self.frames() {
+                physical_map.acquire_shared(frame).unwrap();
+            }
+        });
+
+        let result = page_table.modify(user_space(), |manager| unsafe {
+            if !range.clone().all(|page| manager.is_free_page(page)) {
+                return Err(());
+            }
+
+            manager.map_pages(
+                start,
+                self.frames(),
+                flags
+                    | PageTableFlags::PRESENT
+                    | PageTableFlags::USER_ACCESSIBLE
+                    | SHARED_MEMORY,
+                false,
+            )
+        });
+
+        if result.is_err() {
+            PhysicalMemoryMap::global(|physical_map| {
+                for frame in self.frames() {
+                    physical_map.release_shared(frame);
+                }
+            });
+        }
+
+        result.map(|_| range)
+    }
+
+    /// Remove a mapping created by map
+    ///
+    /// # Safety
+    /// The range starting at start must be a mapping of this object.
+    pub unsafe fn unmap(
+        &self,
+        page_table: &mut ManagedPageTable,
+        start: Page<Size4KiB>,
+    ) -> Result<(), ()> {
+        let range = PageRange {
+            start,
+            end: start + self.pages as u64,
+        };
+        let flush = page_table.is_active();
+
+        page_table.modify(user_space(), |manager| {
+            manager.unmap_pages_and_release(range, flush)
+        })
+    }
+
+    /// Drop the reference of this handle
+    ///
+    /// The frames are freed if it was the last reference.
+    unsafe fn release(&self, physical_map: &mut PhysicalMemoryMap) {
+        // The lock on the map keeps the list frame from being reused while we read it
+        if physical_map.release_shared(self.list) != Some(0) {
+            return;
+        }
+
+        for frame in self.frames() {
+            if physical_map.release_shared(frame).is_none() {
+                warn!("Shared memory frame {:?} was not in use", frame);
+            }
+        }
+    }
+}
+
+impl Clone for SharedMemory {
+    fn clone(&self) -> Self {
+        PhysicalMemoryMap::global(|physical_map| {
+            physical_map.acquire_shared(self.list).unwrap()
+        });
+
+        SharedMemory {
+            list: self.list,
+            pages: self.pages,
+        }
+    }
+}
+
+impl Drop for SharedMemory {
+    fn drop(&mut self) {
+        PhysicalMemoryMap::global(|physical_map| unsafe {
+            self.release(physical_map)
+        });
+    }
+}
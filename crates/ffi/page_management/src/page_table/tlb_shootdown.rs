@@ -0,0 +1,190 @@
+use crate::{page_table::pcid, platform};
+use core::sync::atomic::{spin_loop_hint, AtomicU64, AtomicUsize, Ordering};
+use cpu_local_storage::{data::CoreId, get_core_id};
+use spin::Mutex;
+use x86_64::structures::paging::{page::PageRange, Page, Size4KiB};
+
+/// A batch holding more ranges than this is flushed completely
+const MAX_BATCHED_RANGES: usize = 8;
+
+/// Ranges larger than this are cheaper to handle with a full flush
+const MAX_PAGES_PER_RANGE: u64 = 64;
+
+/// Page ranges whose translations have to be invalidated on all cores
+#[derive(Copy, Clone)]
+pub struct ShootdownBatch {
+    /// Start and end of each range
+    ranges: [Option<(Page<Size4KiB>, Page<Size4KiB>)>; MAX_BATCHED_RANGES],
+    flush_all: bool,
+}
+
+impl ShootdownBatch {
+    pub const fn new() -> Self {
+        ShootdownBatch {
+            ranges: [None; MAX_BATCHED_RANGES],
+            flush_all: false,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        !self.flush_all && self.ranges.iter().all(Option::is_none)
+    }
+
+    pub fn add(&mut self, range: PageRange<Size4KiB>) {
+        let PageRange { start, end } = range;
+
+        if self.flush_all || start >= end {
+            return;
+        }
+        if end - start > MAX_PAGES_PER_RANGE {
+            self.flush_all = true;
+            return;
+        }
+
+        for slot in self.ranges.iter_mut() {
+            match slot {
+                Some((_, existing_end)) if *existing_end == start => {
+                    *existing_end = end;
+                    return;
+                },
+                Some((existing_start, _)) if *existing_start == end => {
+                    *existing_start = start;
+                    return;
+                },
+                Some(_) => {},
+                None => {
+                    *slot = Some((start, end));
+                    return;
+                },
+            }
+        }
+
+        // The batch is full
+        self.flush_all = true;
+    }
+
+    /// Invalidate all ranges in the TLB of the current core
+    pub fn invalidate_local(&self) {
+        if self.flush_all {
+            pcid::flush_everything();
+            return;
+        }
+
+        for (start, end) in self.ranges.iter().flatten() {
+            for page in Page::range(*start, *end) {
+                platform::flush_page(page.start_address());
+            }
+        }
+    }
+}
+
+impl Default for ShootdownBatch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A fn() that sends the shootdown interrupt to all other cores
+static IPI_SENDER: AtomicUsize = AtomicUsize::new(0);
+
+/// Bitmask of all cores that take part in shootdowns
+static ONLINE_CORES: AtomicU64 = AtomicU64::new(0);
+
+/// Bitmask of all cores that have not acknowledged the current request yet
+static PENDING_CORES: AtomicU64 = AtomicU64::new(0);
+
+/// Only one core may publish a request at a time
+static INITIATOR: Mutex<()> = Mutex::new(());
+
+/// The current request.
+///
+/// It is only written by the holder of INITIATOR while PENDING_CORES is 0.
+static mut CURRENT_REQUEST: ShootdownBatch = ShootdownBatch::new();
+
+fn core_mask(core: CoreId) -> u64 {
+    let index = core.optional_full_id() - 1;
+    assert!(index < 64, "TLB shootdown supports at most 64 cores");
+    1 << index
+}
+
+fn ipi_sender() -> Option<fn()> {
+    match IPI_SENDER.load(Ordering::Acquire) {
+        0 => None,
+        raw => Some(unsafe { core::mem::transmute::<usize, fn()>(raw) }),
+    }
+}
+
+/// Register the function used to interrupt the other cores
+///
+/// # Safety
+/// sender must deliver an interrupt to every other online core,
+/// and the handler of that interrupt must call handle_pending.
+pub unsafe fn register_ipi_sender(sender: fn()) {
+    IPI_SENDER.store(sender as usize, Ordering::Release);
+}
+
+/// Let the current core receive shootdowns
+///
+/// This has to be done after its shootdown interrupt handler is installed.
+pub fn mark_current_core_online() {
+    ONLINE_CORES.fetch_or(core_mask(get_core_id()), Ordering::AcqRel);
+}
+
+/// Invalidate batch on all other online cores and wait until they are done
+///
+/// The current core is not touched, the caller flushes it when unmapping.
+pub fn shootdown(batch: &ShootdownBatch) {
+    if batch.is_empty() {
+        return;
+    }
+
+    let sender = match ipi_sender() {
+        Some(sender) => sender,
+        None => return,
+    };
+
+    let targets =
+        ONLINE_CORES.load(Ordering::Acquire) & !core_mask(get_core_id());
+    if targets == 0 {
+        return;
+    }
+
+    // Another core may be waiting for us to acknowledge its request,
+    // so we keep answering requests while waiting
+    let _guard = loop {
+        if let Some(guard) = INITIATOR.try_lock() {
+            break guard;
+        }
+        handle_pending();
+        spin_loop_hint();
+    };
+
+    unsafe {
+        CURRENT_REQUEST = *batch;
+    }
+    PENDING_CORES.store(targets, Ordering::Release);
+
+    sender();
+
+    while PENDING_CORES.load(Ordering::Acquire) != 0 {
+        handle_pending();
+        spin_loop_hint();
+    }
+}
+
+/// Handle the current request if it is addressed to this core
+///
+/// This is to be called from the shootdown interrupt handler.
+pub fn handle_pending() {
+    let mask = core_mask(get_core_id());
+
+    if PENDING_CORES.load(Ordering::Acquire) & mask == 0 {
+        return;
+    }
+
+    unsafe {
+        CURRENT_REQUEST.invalidate_local();
+    }
+
+    PENDING_CORES.fetch_and(!mask, Ordering::AcqRel);
+}
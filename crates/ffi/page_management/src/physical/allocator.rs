@@ -79,9 +79,10 @@ where
     A: FnMut(&PhysicalMemoryMap<'buf>) -> Option<UnusedPhysFrame>,
 {
     fn allocate_frame(&mut self) -> Option<UnusedPhysFrame<Size4KiB>> {
-        (self.allocator)(&self.map).map(|frame| {
-            self.map.set((&frame as &PhysFrame).clone(), self.usage);
-            frame
-        })
+        let frame = (self.allocator)(&self.map)?;
+
+        // A run length map may have no room left to record the frame
+        self.map.set((&frame as &PhysFrame).clone(), self.usage)?;
+        Some(frame)
     }
 }
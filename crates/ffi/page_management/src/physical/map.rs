@@ -1,25 +1,45 @@
-use crate::physical::{
-    allocator::{
-        ExternalPhysicalMemoryMapFrameAllocator,
-        PhysicalMemoryMapFrameAllocator,
+use crate::{
+    physical::{
+        allocator::{
+            ExternalPhysicalMemoryMapFrameAllocator,
+            PhysicalMemoryMapFrameAllocator,
+        },
+        page_usage::{PageUsage, PageUsageRawType, UsageCategory},
+        run_length_map::{Run, RunLengthMemoryMap},
+        statistics::{UsageCounters, UsageStatistics},
     },
-    page_usage::{PageUsage, PageUsageRawType},
+    platform::Lock,
 };
+use core::slice::from_raw_parts_mut;
 use ffi_utils::ffi_slice::FfiSliceMut;
-use kernel_spin::KernelMutex;
+use log::*;
 use x86_64::structures::paging::{
     frame::PhysFrameRange, FrameDeallocator, PhysFrame, Size4KiB,
     UnusedPhysFrame,
 };
 
-static mut PHYSICAL_MEMORY_MAP: Option<
-    KernelMutex<PhysicalMemoryMap<'static>>,
-> = None;
+static mut PHYSICAL_MEMORY_MAP: Option<Lock<PhysicalMemoryMap<'static>>> = None;
+
+/// How the usage of each frame is stored
+#[repr(C)]
+enum Storage<'buf> {
+    /// One entry per frame
+    Flat(FfiSliceMut<'buf, PageUsageRawType>),
+    /// Runs of frames with the same usage
+    RunLength(RunLengthMemoryMap<'buf>),
+}
+
+/// The buffer of a released memory map
+pub enum MapBuffer<'buf> {
+    Flat(&'buf mut [PageUsageRawType]),
+    RunLength(&'buf mut [Run]),
+}
 
 #[repr(C)]
 pub struct PhysicalMemoryMap<'buf> {
-    buffer: FfiSliceMut<'buf, PageUsageRawType>,
+    storage: Storage<'buf>,
     base: PhysFrame,
+    counters: UsageCounters,
 }
 
 impl<'buf> PhysicalMemoryMap<'buf> {
@@ -35,74 +55,137 @@ impl<'buf> PhysicalMemoryMap<'buf> {
         base: PhysFrame,
         value: PageUsage,
     ) -> Self {
-        let value = value.to_raw().unwrap();
+        let raw_value = value.to_raw().unwrap();
 
         for it in buffer.iter_mut() {
-            *it = value;
+            *it = raw_value;
         }
 
+        let counters = UsageCounters::from_usages(
+            core::iter::repeat(value).take(buffer.len()),
+        );
+
         PhysicalMemoryMap {
-            buffer: buffer.into(),
+            storage: Storage::Flat(buffer.into()),
             base,
+            counters,
         }
     }
 
-    /// Creates a memory map from the raw innards
+    /// Constructs a new memory map of pages frames that stores runs of frames with the same usage
     ///
-    /// # Safety
-    /// This is only safe if the parts have been obtained by calling release
-    /// on a PhysicalMemoryMap instance.
-    pub unsafe fn from_raw_parts(
-        buffer: &'buf mut [PageUsageRawType],
+    /// Its size depends on the number of runs instead of the number of frames,
+    /// which suits sparse or very large physical address spaces.
+    /// Once buffer can't hold any more runs, changes that split a run fail.
+    pub fn create_run_length(
+        buffer: &'buf mut [Run],
         base: PhysFrame,
+        pages: u64,
+        value: PageUsage,
     ) -> Self {
+        let counters = UsageCounters::with_count(value, pages);
+
         PhysicalMemoryMap {
-            buffer: buffer.into(),
+            storage: Storage::RunLength(RunLengthMemoryMap::create(
+                buffer, base, pages, value,
+            )),
             base,
+            counters,
         }
     }
 
+    /// Move the buffer of self by offset bytes
+    ///
+    /// # Safety
+    /// The buffer must be accessible at its address plus offset.
+    pub unsafe fn relocate(self, offset: u64) -> Self {
+        let storage = match self.storage {
+            Storage::Flat(buffer) => {
+                let buffer: &mut [PageUsageRawType] = buffer.into();
+                Storage::Flat(relocate_slice(buffer, offset).into())
+            },
+            Storage::RunLength(map) => Storage::RunLength(map.relocate(offset)),
+        };
+
+        PhysicalMemoryMap { storage, ..self }
+    }
+
     /// Consume self and return the underlying buffer
     ///
     /// This does not deallocate the buffer
     #[inline(always)]
-    pub fn release(self) -> (&'buf mut [PageUsageRawType], PhysFrame) {
-        (self.buffer.into(), self.base)
+    pub fn release(self) -> (MapBuffer<'buf>, PhysFrame) {
+        let buffer = match self.storage {
+            Storage::Flat(buffer) => MapBuffer::Flat(buffer.into()),
+            Storage::RunLength(map) => MapBuffer::RunLength(map.release()),
+        };
+
+        (buffer, self.base)
     }
 
+    /// Set the usage of frame and return the previous one
+    ///
+    /// Returns None if frame is not in the map,
+    /// or a run length map has no room for the runs this creates.
     pub fn set(
         &mut self,
         frame: PhysFrame,
         value: PageUsage,
     ) -> Option<PageUsage> {
-        let value = value.to_raw()?;
-        let base = self.base();
+        let old = match &mut self.storage {
+            Storage::Flat(buffer) => {
+                let raw_value = value.to_raw()?;
+                let index = frame_index(frame, self.base)?;
 
-        self.buffer_mut()
-            .get_mut((frame - base) as usize)
-            .map(|r| core::mem::replace(r, value))
-            .map(|v| PageUsage::from_raw(v).unwrap())
+                buffer
+                    .as_slice_mut()
+                    .get_mut(index)
+                    .map(|r| core::mem::replace(r, raw_value))
+                    .map(|v| PageUsage::from_raw(v).unwrap())?
+            },
+            Storage::RunLength(map) => map.set(frame, value)?,
+        };
+
+        self.counters.update(old, value);
+        Some(old)
     }
 
-    pub fn get(&self, frame: PhysFrame) -> Option<PageUsage> {
-        self.buffer()
-            .get((frame - self.base()) as usize)
-            .map(|v| PageUsage::from_raw(*v).unwrap())
+    /// Mark frame as empty
+    ///
+    /// If a run length map has no room to record this,
+    /// the frame keeps its usage and is counted as leaked.
+    pub fn free(&mut self, frame: PhysFrame) {
+        if self.set(frame, PageUsage::Empty).is_none() {
+            self.leak(frame);
+        }
     }
 
-    #[inline(always)]
-    pub fn buffer(&self) -> &[PageUsageRawType] {
-        self.buffer.as_slice()
+    /// The number of frames that could not be freed or released
+    pub fn leaked_frames(&self) -> u64 {
+        self.counters.leaked()
     }
 
-    #[inline(always)]
-    fn buffer_mut(&mut self) -> &mut [PageUsageRawType] {
-        self.buffer.as_slice_mut()
+    fn leak(&self, frame: PhysFrame) {
+        warn!("Could not release {:?}, the memory map is full", frame);
+        self.counters.leak();
+    }
+
+    pub fn get(&self, frame: PhysFrame) -> Option<PageUsage> {
+        match &self.storage {
+            Storage::Flat(buffer) => buffer
+                .as_slice()
+                .get(frame_index(frame, self.base)?)
+                .map(|v| PageUsage::from_raw(*v).unwrap()),
+            Storage::RunLength(map) => map.get(frame),
+        }
     }
 
     #[inline(always)]
     pub fn pages(&self) -> u64 {
-        self.buffer.len() as u64
+        match &self.storage {
+            Storage::Flat(buffer) => buffer.len() as u64,
+            Storage::RunLength(map) => map.pages(),
+        }
     }
 
     #[inline(always)]
@@ -118,21 +201,101 @@ impl<'buf> PhysicalMemoryMap<'buf> {
     }
 
     pub fn iter<'this>(&'this self) -> impl 'this + Iterator<Item = PageUsage> {
-        self.buffer()
-            .iter()
-            .map(|v| PageUsage::from_raw(*v).unwrap())
+        (0..self.pages()).map(move |index| self.get(self.base + index).unwrap())
     }
 
     pub fn empty_frames(&self) -> usize {
-        self.iter().filter(|frame| frame.is_empty()).count()
+        self.statistics().get(UsageCategory::Empty) as usize
+    }
+
+    /// The number of frames in each category
+    ///
+    /// The counters are kept up to date by set, so this does not scan the map.
+    pub fn statistics(&self) -> UsageStatistics {
+        self.counters.snapshot()
     }
 
     pub fn find_unused_frame(&self) -> Option<UnusedPhysFrame> {
-        self.iter()
-            .enumerate()
-            .find(|(_, usage)| usage.is_empty())
-            .map(|(index, _)| self.base + (index as u64))
-            .map(|frame| unsafe { UnusedPhysFrame::new(frame) })
+        match &self.storage {
+            Storage::Flat(buffer) => buffer
+                .as_slice()
+                .iter()
+                .position(|usage| {
+                    PageUsage::from_raw(*usage).unwrap().is_empty()
+                })
+                .map(|index| unsafe {
+                    UnusedPhysFrame::new(self.base + index as u64)
+                }),
+            Storage::RunLength(map) => map.find_unused_frame(),
+        }
+    }
+
+    /// Replace the usage of frame
+    ///
+    /// update receives the current usage and returns the new one, or None to leave it unchanged.
+    pub fn update<F>(
+        &mut self,
+        frame: PhysFrame,
+        update: F,
+    ) -> Option<PageUsage>
+    where
+        F: FnOnce(PageUsage) -> Option<PageUsage>,
+    {
+        let new_usage = update(self.get(frame)?)?;
+        self.set(frame, new_usage)?;
+        Some(new_usage)
+    }
+
+    /// Add a reference to frame and return the new reference count
+    ///
+    /// A user data frame becomes shared with two references: the existing owner and the new one.
+    /// Frames of any other usage can't be shared, since sharing would lose their usage.
+    pub fn acquire_shared(&mut self, frame: PhysFrame) -> Option<u32> {
+        let usage = self.update(frame, |usage| match usage {
+            PageUsage::Shared { refcount } => Some(PageUsage::Shared {
+                refcount: refcount.checked_add(1)?,
+            }),
+            PageUsage::UserData => Some(PageUsage::Shared { refcount: 2 }),
+            _ => None,
+        })?;
+
+        match usage {
+            PageUsage::Shared { refcount } => Some(refcount),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Drop a reference to frame and return the remaining reference count
+    ///
+    /// The frame becomes empty once the count reaches zero.
+    /// A frame that is not shared has a single reference.
+    /// Returns None if the frame was not in use.
+    pub fn release_shared(&mut self, frame: PhysFrame) -> Option<u32> {
+        let (usage, refcount) = match self.get(frame)? {
+            PageUsage::Shared { refcount } if refcount > 1 => (
+                PageUsage::Shared {
+                    refcount: refcount - 1,
+                },
+                refcount - 1,
+            ),
+            PageUsage::Empty | PageUsage::Unusable => return None,
+            _ => (PageUsage::Empty, 0),
+        };
+
+        // The reference is gone either way, a stale count only keeps the frame
+        if self.set(frame, usage).is_none() {
+            self.leak(frame);
+        }
+        Some(refcount)
+    }
+
+    /// The number of references to frame
+    pub fn refcount(&self, frame: PhysFrame) -> Option<u32> {
+        match self.get(frame)? {
+            PageUsage::Shared { refcount } => Some(refcount),
+            PageUsage::Empty | PageUsage::Unusable => Some(0),
+            _ => Some(1),
+        }
     }
 
     pub fn frame_allocator<'this>(
@@ -166,6 +329,19 @@ impl<'buf> PhysicalMemoryMap<'buf> {
     }
 }
 
+fn frame_index(frame: PhysFrame, base: PhysFrame) -> Option<usize> {
+    if frame < base {
+        None
+    } else {
+        Some((frame - base) as usize)
+    }
+}
+
+unsafe fn relocate_slice<'lt, T>(slice: &mut [T], offset: u64) -> &'lt mut [T] {
+    let pointer = (slice.as_mut_ptr() as u64 + offset) as *mut T;
+    from_raw_parts_mut(pointer, slice.len())
+}
+
 impl PhysicalMemoryMap<'static> {
     /// # Safety
     /// You must initialize PhysicalMemoryMap before any system tries to interact with it.
@@ -174,7 +350,7 @@ impl PhysicalMemoryMap<'static> {
     /// In general, once the system is up and running, you should not mess with this.
     pub unsafe fn register_global(self) {
         assert!(PHYSICAL_MEMORY_MAP.is_none());
-        PHYSICAL_MEMORY_MAP = Some(KernelMutex::new(self));
+        PHYSICAL_MEMORY_MAP = Some(Lock::new(self));
     }
 
     /// Take the global memory map away.
@@ -194,10 +370,27 @@ impl PhysicalMemoryMap<'static> {
     {
         unsafe { PHYSICAL_MEMORY_MAP.as_ref().unwrap().lock(f) }
     }
+
+    /// Lock the global memory map, unless the current core already holds it
+    ///
+    /// Code that may interrupt a holder of the lock, like a fault handler,
+    /// uses this instead of deadlocking.
+    pub fn try_global<F, R>(f: F) -> Option<R>
+    where
+        F: FnOnce(&mut Self) -> R,
+    {
+        let lock = unsafe { PHYSICAL_MEMORY_MAP.as_ref().unwrap() };
+
+        if lock.is_held_by_current_core() {
+            None
+        } else {
+            Some(lock.lock(f))
+        }
+    }
 }
 
 impl<'buf> FrameDeallocator<Size4KiB> for PhysicalMemoryMap<'buf> {
     fn deallocate_frame(&mut self, frame: UnusedPhysFrame<Size4KiB>) {
-        self.set(frame.frame(), PageUsage::Empty);
+        self.free(frame.frame());
     }
 }
@@ -1,3 +1,5 @@
 pub mod allocator;
 pub mod map;
 pub mod page_usage;
+pub mod run_length_map;
+pub mod statistics;
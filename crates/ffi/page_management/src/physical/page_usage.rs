@@ -33,9 +33,17 @@ pub enum PageUsage {
 
     PageTable,
 
-    KernelStack { thread: u32 },
+    KernelStack {
+        thread: u32,
+    },
     KernelHeap,
 
+    /// Mapped into a single user address space
+    UserData,
+    Shared {
+        refcount: u32,
+    },
+
     Custom(u32),
 }
 
@@ -47,6 +55,8 @@ impl PageUsage {
     const TAG_PAGE_TABLE: u32 = 3;
     const TAG_KERNEL_STACK: u32 = 4;
     const TAG_KERNEL_HEAP: u32 = 5;
+    const TAG_SHARED: u32 = 6;
+    const TAG_USER_DATA: u32 = 7;
 
     pub fn to_raw(self) -> Option<PageUsageRawType> {
         Some(match self {
@@ -74,6 +84,16 @@ impl PageUsage {
                 PageUsageRawType::from_category(Self::TAG_KERNEL_HEAP)
             },
 
+            PageUsage::UserData => {
+                PageUsageRawType::from_category(Self::TAG_USER_DATA)
+            },
+            PageUsage::Shared { refcount } => {
+                PageUsageRawType::from_category_and_data(
+                    Self::TAG_SHARED,
+                    refcount,
+                )
+            },
+
             PageUsage::Custom(i) => {
                 PageUsageRawType::from_category_and_data(Self::TAG_CUSTOM, i)
             },
@@ -93,6 +113,11 @@ impl PageUsage {
             },
             Self::TAG_KERNEL_HEAP => PageUsage::KernelHeap,
 
+            Self::TAG_USER_DATA => PageUsage::UserData,
+            Self::TAG_SHARED => PageUsage::Shared {
+                refcount: value.data(),
+            },
+
             Self::TAG_CUSTOM => PageUsage::Custom(value.data()),
 
             _ => return None,
@@ -102,4 +127,66 @@ impl PageUsage {
     pub fn is_empty(self) -> bool {
         self == PageUsage::Empty
     }
+
+    pub fn category(self) -> UsageCategory {
+        match self {
+            PageUsage::Empty => UsageCategory::Empty,
+            PageUsage::Unusable => UsageCategory::Unusable,
+            PageUsage::PageTableRoot => UsageCategory::PageTableRoot,
+            PageUsage::PageTable => UsageCategory::PageTable,
+            PageUsage::KernelStack { .. } => UsageCategory::KernelStack,
+            PageUsage::KernelHeap => UsageCategory::KernelHeap,
+            PageUsage::UserData => UsageCategory::UserData,
+            PageUsage::Shared { .. } => UsageCategory::Shared,
+            PageUsage::Custom(_) => UsageCategory::Custom,
+        }
+    }
+}
+
+/// A PageUsage without its data
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum UsageCategory {
+    Empty,
+    Unusable,
+    PageTableRoot,
+    PageTable,
+    KernelStack,
+    KernelHeap,
+    UserData,
+    Shared,
+    Custom,
+}
+
+impl UsageCategory {
+    pub const COUNT: usize = 9;
+
+    pub const ALL: [UsageCategory; Self::COUNT] = [
+        UsageCategory::Empty,
+        UsageCategory::Unusable,
+        UsageCategory::PageTableRoot,
+        UsageCategory::PageTable,
+        UsageCategory::KernelStack,
+        UsageCategory::KernelHeap,
+        UsageCategory::UserData,
+        UsageCategory::Shared,
+        UsageCategory::Custom,
+    ];
+
+    pub fn index(self) -> usize {
+        self as usize
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            UsageCategory::Empty => "Empty",
+            UsageCategory::Unusable => "Unusable",
+            UsageCategory::PageTableRoot => "PageTableRoot",
+            UsageCategory::PageTable => "PageTable",
+            UsageCategory::KernelStack => "KernelStack",
+            UsageCategory::KernelHeap => "KernelHeap",
+            UsageCategory::UserData => "UserData",
+            UsageCategory::Shared => "Shared",
+            UsageCategory::Custom => "Custom",
+        }
+    }
 }
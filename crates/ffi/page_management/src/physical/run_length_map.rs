@@ -0,0 +1,279 @@
+use crate::physical::{
+    map::PhysicalMemoryMap,
+    page_usage::{PageUsage, PageUsageRawType},
+};
+use core::slice::from_raw_parts_mut;
+use ffi_utils::ffi_slice::FfiSliceMut;
+use x86_64::structures::paging::{
+    frame::PhysFrameRange, PhysFrame, UnusedPhysFrame,
+};
+
+/// Frames with the same usage, from start up to the start of the next run
+#[repr(C)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Run {
+    /// Index of the first frame, relative to the base of the map
+    pub start: u64,
+    pub usage: PageUsageRawType,
+}
+
+/// A physical memory map that stores runs of frames with the same usage
+///
+/// Its size depends on the number of runs instead of the number of frames.
+/// This makes it suited for sparse or very large physical address spaces.
+/// PhysicalMemoryMap uses it as its storage when created with create_run_length.
+#[repr(C)]
+pub struct RunLengthMemoryMap<'buf> {
+    runs: FfiSliceMut<'buf, Run>,
+    run_count: usize,
+    pages: u64,
+    base: PhysFrame,
+}
+
+impl<'buf> RunLengthMemoryMap<'buf> {
+    /// Constructs a map of pages frames, starting at base, that are all value
+    ///
+    /// The map can hold at most buffer.len() runs.
+    pub fn create(
+        buffer: &'buf mut [Run],
+        base: PhysFrame,
+        pages: u64,
+        value: PageUsage,
+    ) -> Self {
+        assert!(!buffer.is_empty());
+
+        buffer[0] = Run {
+            start: 0,
+            usage: value.to_raw().unwrap(),
+        };
+
+        RunLengthMemoryMap {
+            runs: buffer.into(),
+            run_count: 1,
+            pages,
+            base,
+        }
+    }
+
+    /// Compress a flat map
+    ///
+    /// Returns None if buffer can't hold all runs.
+    pub fn from_map(
+        map: &PhysicalMemoryMap,
+        buffer: &'buf mut [Run],
+    ) -> Option<Self> {
+        let mut run_count = 0;
+
+        for (index, usage) in map.iter().enumerate() {
+            let usage = usage.to_raw().unwrap();
+            if run_count > 0 && buffer[run_count - 1].usage == usage {
+                continue;
+            }
+
+            *buffer.get_mut(run_count)? = Run {
+                start: index as u64,
+                usage,
+            };
+            run_count += 1;
+        }
+
+        Some(RunLengthMemoryMap {
+            runs: buffer.into(),
+            run_count,
+            pages: map.pages(),
+            base: map.base(),
+        })
+    }
+
+    /// Consume self and return the underlying buffer
+    pub fn release(self) -> &'buf mut [Run] {
+        self.runs.into()
+    }
+
+    /// Move the buffer of self by offset bytes
+    ///
+    /// # Safety
+    /// The buffer must be accessible at its address plus offset.
+    pub(crate) unsafe fn relocate(self, offset: u64) -> Self {
+        let runs: &mut [Run] = self.runs.into();
+        let pointer = (runs.as_mut_ptr() as u64 + offset) as *mut Run;
+
+        RunLengthMemoryMap {
+            runs: from_raw_parts_mut(pointer, runs.len()).into(),
+            ..self
+        }
+    }
+
+    #[inline(always)]
+    pub fn runs(&self) -> &[Run] {
+        &self.runs.as_slice()[..self.run_count]
+    }
+
+    /// The maximum number of runs
+    #[inline(always)]
+    pub fn capacity(&self) -> usize {
+        self.runs.len()
+    }
+
+    #[inline(always)]
+    pub fn pages(&self) -> u64 {
+        self.pages
+    }
+
+    #[inline(always)]
+    pub fn base(&self) -> PhysFrame {
+        self.base
+    }
+
+    pub fn physical_range(&self) -> PhysFrameRange {
+        PhysFrameRange {
+            start: self.base(),
+            end: self.base() + self.pages(),
+        }
+    }
+
+    fn frame_index(&self, frame: PhysFrame) -> Option<u64> {
+        if frame < self.base {
+            return None;
+        }
+
+        let index = frame - self.base;
+        if index < self.pages {
+            Some(index)
+        } else {
+            None
+        }
+    }
+
+    /// The index of the run containing the frame with index
+    fn run_index(&self, index: u64) -> usize {
+        match self.runs().binary_search_by_key(&index, |run| run.start) {
+            Ok(run) => run,
+            Err(next) => next - 1,
+        }
+    }
+
+    fn run_end(&self, run: usize) -> u64 {
+        self.runs()
+            .get(run + 1)
+            .map_or(self.pages, |next| next.start)
+    }
+
+    fn run_length(&self, run: usize) -> u64 {
+        self.run_end(run) - self.runs()[run].start
+    }
+
+    /// Replace the runs in range with replacement
+    fn splice(
+        &mut self,
+        first: usize,
+        last: usize,
+        replacement: &[Run],
+    ) -> Option<()> {
+        let removed = last - first + 1;
+        let run_count = self.run_count - removed + replacement.len();
+        if run_count > self.capacity() {
+            return None;
+        }
+
+        let old_count = self.run_count;
+        let runs = self.runs.as_slice_mut();
+        runs.copy_within(last + 1..old_count, first + replacement.len());
+        runs[first..first + replacement.len()].copy_from_slice(replacement);
+        self.run_count = run_count;
+
+        Some(())
+    }
+
+    /// Set the usage of frame and return the previous one
+    ///
+    /// Returns None if the frame is not in the map
+    /// or the map has no room for the runs this creates.
+    pub fn set(
+        &mut self,
+        frame: PhysFrame,
+        value: PageUsage,
+    ) -> Option<PageUsage> {
+        let value = value.to_raw()?;
+        let index = self.frame_index(frame)?;
+
+        let run = self.run_index(index);
+        let Run { start, usage } = self.runs()[run];
+        let end = self.run_end(run);
+
+        if usage != value {
+            let merge_previous = index == start
+                && run > 0
+                && self.runs()[run - 1].usage == value;
+            let merge_next = index + 1 == end
+                && self.runs().get(run + 1).map(|next| next.usage)
+                    == Some(value);
+
+            let mut replacement = [Run { start: 0, usage }; 3];
+            let mut length = 0;
+            let mut push = |entry| {
+                replacement[length] = entry;
+                length += 1;
+            };
+
+            // A merged frame extends the previous run,
+            // a merged next run is absorbed by the run of the frame
+            if merge_previous {
+                push(self.runs()[run - 1]);
+            } else {
+                if index > start {
+                    push(Run { start, usage });
+                }
+                push(Run {
+                    start: index,
+                    usage: value,
+                });
+            }
+            if !merge_next && index + 1 < end {
+                push(Run {
+                    start: index + 1,
+                    usage,
+                });
+            }
+
+            let first = if merge_previous { run - 1 } else { run };
+            let last = if merge_next { run + 1 } else { run };
+            self.splice(first, last, &replacement[..length])?;
+        }
+
+        Some(PageUsage::from_raw(usage).unwrap())
+    }
+
+    pub fn get(&self, frame: PhysFrame) -> Option<PageUsage> {
+        let index = self.frame_index(frame)?;
+        let run = self.run_index(index);
+
+        Some(PageUsage::from_raw(self.runs()[run].usage).unwrap())
+    }
+
+    pub fn iter<'this>(&'this self) -> impl 'this + Iterator<Item = PageUsage> {
+        (0..self.run_count).flat_map(move |run| {
+            let usage = PageUsage::from_raw(self.runs()[run].usage).unwrap();
+            core::iter::repeat(usage).take(self.run_length(run) as usize)
+        })
+    }
+
+    pub fn empty_frames(&self) -> usize {
+        (0..self.run_count)
+            .filter(|run| {
+                PageUsage::from_raw(self.runs()[*run].usage)
+                    .unwrap()
+                    .is_empty()
+            })
+            .map(|run| self.run_length(run) as usize)
+            .sum()
+    }
+
+    pub fn find_unused_frame(&self) -> Option<UnusedPhysFrame> {
+        self.runs()
+            .iter()
+            .find(|run| PageUsage::from_raw(run.usage).unwrap().is_empty())
+            .map(|run| self.base + run.start)
+            .map(|frame| unsafe { UnusedPhysFrame::new(frame) })
+    }
+}
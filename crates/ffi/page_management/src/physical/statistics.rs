@@ -0,0 +1,141 @@
+use crate::physical::page_usage::{PageUsage, UsageCategory};
+use core::{
+    fmt,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use x86_64::structures::paging::{PageSize, Size4KiB};
+
+/// Frame counts per category, updated whenever a frame changes its usage
+#[repr(C)]
+#[derive(Default)]
+pub(crate) struct UsageCounters {
+    counts: [AtomicU64; UsageCategory::COUNT],
+    /// Frames that could not be freed, since the map had no room for their usage
+    leaked: AtomicU64,
+}
+
+impl UsageCounters {
+    /// Counters for pages frames that all have the usage value
+    pub fn with_count(value: PageUsage, pages: u64) -> Self {
+        let counters = UsageCounters::default();
+        counters.counts[value.category().index()]
+            .store(pages, Ordering::Relaxed);
+        counters
+    }
+
+    pub fn from_usages<I>(usages: I) -> Self
+    where
+        I: Iterator<Item = PageUsage>,
+    {
+        let counters = UsageCounters::default();
+        for usage in usages {
+            counters.counts[usage.category().index()]
+                .fetch_add(1, Ordering::Relaxed);
+        }
+        counters
+    }
+
+    pub fn update(&self, old: PageUsage, new: PageUsage) {
+        let (old, new) = (old.category(), new.category());
+        if old != new {
+            self.counts[old.index()].fetch_sub(1, Ordering::Relaxed);
+            self.counts[new.index()].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn leak(&self) {
+        self.leaked.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn leaked(&self) -> u64 {
+        self.leaked.load(Ordering::Relaxed)
+    }
+
+    pub fn snapshot(&self) -> UsageStatistics {
+        let mut counts = [0; UsageCategory::COUNT];
+        for (count, counter) in counts.iter_mut().zip(self.counts.iter()) {
+            *count = counter.load(Ordering::Relaxed);
+        }
+        UsageStatistics { counts }
+    }
+}
+
+/// The number of frames in each category at some point in time
+///
+/// The Display implementation prints a human readable report.
+#[derive(Copy, Clone, PartialEq, Eq, Default, Debug)]
+pub struct UsageStatistics {
+    counts: [u64; UsageCategory::COUNT],
+}
+
+impl UsageStatistics {
+    pub fn get(&self, category: UsageCategory) -> u64 {
+        self.counts[category.index()]
+    }
+
+    pub fn iter<'this>(
+        &'this self,
+    ) -> impl 'this + Iterator<Item = (UsageCategory, u64)> {
+        UsageCategory::ALL
+            .iter()
+            .map(move |category| (*category, self.get(*category)))
+    }
+
+    pub fn total(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// The change of each category since earlier
+    ///
+    /// A category that keeps growing between snapshots hints at a leak.
+    pub fn difference(&self, earlier: &UsageStatistics) -> UsageDifference {
+        let mut counts = [0; UsageCategory::COUNT];
+        for (index, count) in counts.iter_mut().enumerate() {
+            *count = self.counts[index] as i64 - earlier.counts[index] as i64;
+        }
+        UsageDifference { counts }
+    }
+}
+
+impl fmt::Display for UsageStatistics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (category, count) in self.iter() {
+            writeln!(
+                f,
+                "{:<16}{:>10} frames {:>10} KiB",
+                category.name(),
+                count,
+                count * Size4KiB::SIZE / 1024,
+            )?;
+        }
+        write!(f, "{:<16}{:>10} frames", "Total", self.total())
+    }
+}
+
+/// The change between two UsageStatistics
+#[derive(Copy, Clone, PartialEq, Eq, Default, Debug)]
+pub struct UsageDifference {
+    counts: [i64; UsageCategory::COUNT],
+}
+
+impl UsageDifference {
+    pub fn get(&self, category: UsageCategory) -> i64 {
+        self.counts[category.index()]
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.counts.iter().all(|count| *count == 0)
+    }
+}
+
+impl fmt::Display for UsageDifference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for category in UsageCategory::ALL.iter() {
+            let count = self.get(*category);
+            if count != 0 {
+                writeln!(f, "{:<16}{:>+10} frames", category.name(), count)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,170 @@
+//! Privileged processor access
+//!
+//! When not built for the kernel or the UEFI loader, the processor state
+//! is simulated, so the crate can be tested on the host.
+
+pub(crate) use self::implementation::*;
+
+#[cfg(any(target_os = "none", target_os = "uefi"))]
+mod implementation {
+    use x86_64::{instructions::tlb, VirtAddr};
+
+    pub(crate) use kernel_spin::KernelMutex as Lock;
+    pub(crate) use x86_64::instructions::interrupts::without_interrupts;
+
+    #[repr(C, align(16))]
+    struct InvpcidDescriptor {
+        pcid: u64,
+        address: u64,
+    }
+
+    global_asm!(
+        "
+        .intel_syntax noprefix
+        .section .text
+
+        .align 16
+        .global asm_read_cr3
+        asm_read_cr3:
+            mov rax, cr3
+            ret
+
+        .align 16
+        .global asm_write_cr3
+        asm_write_cr3:
+            mov cr3, rdi
+            ret
+
+        .align 16
+        .global asm_read_cr4
+        asm_read_cr4:
+            mov rax, cr4
+            ret
+
+        .align 16
+        .global asm_write_cr4
+        asm_write_cr4:
+            mov cr4, rdi
+            ret
+
+        // rdi: invalidation type
+        // rsi: pointer to the descriptor
+        .align 16
+        .global asm_invpcid
+        asm_invpcid:
+            invpcid rdi, [rsi]
+            ret
+
+        .att_syntax prefix
+        "
+    );
+
+    extern "sysv64" {
+        fn asm_read_cr3() -> u64;
+        fn asm_write_cr3(value: u64);
+        fn asm_read_cr4() -> u64;
+        fn asm_write_cr4(value: u64);
+        fn asm_invpcid(kind: u64, descriptor: *const InvpcidDescriptor);
+    }
+
+    pub(crate) fn read_cr3() -> u64 {
+        unsafe { asm_read_cr3() }
+    }
+
+    pub(crate) unsafe fn write_cr3(value: u64) {
+        asm_write_cr3(value)
+    }
+
+    pub(crate) fn read_cr4() -> u64 {
+        unsafe { asm_read_cr4() }
+    }
+
+    pub(crate) unsafe fn write_cr4(value: u64) {
+        asm_write_cr4(value)
+    }
+
+    pub(crate) unsafe fn invpcid(kind: u64, pcid: u64, address: VirtAddr) {
+        let descriptor = InvpcidDescriptor {
+            pcid,
+            address: address.as_u64(),
+        };
+        asm_invpcid(kind, &descriptor);
+    }
+
+    pub(crate) fn flush_page(address: VirtAddr) {
+        tlb::flush(address)
+    }
+
+    pub(crate) fn flush_all() {
+        tlb::flush_all()
+    }
+}
+
+#[cfg(not(any(target_os = "none", target_os = "uefi")))]
+mod implementation {
+    use core::sync::atomic::{AtomicU64, Ordering};
+    use x86_64::VirtAddr;
+
+    /// Stands in for KernelMutex, there are no interrupts to disable
+    #[derive(Default)]
+    pub(crate) struct Lock<T> {
+        mutex: spin::Mutex<T>,
+    }
+
+    impl<T> Lock<T> {
+        pub const fn new(data: T) -> Self {
+            Lock {
+                mutex: spin::Mutex::new(data),
+            }
+        }
+
+        pub fn into_inner(self) -> T {
+            self.mutex.into_inner()
+        }
+
+        pub fn lock<F, R>(&self, function: F) -> R
+        where
+            F: FnOnce(&mut T) -> R,
+        {
+            function(&mut self.mutex.lock())
+        }
+
+        /// There is only one simulated core, so any holder is the current one
+        pub fn is_held_by_current_core(&self) -> bool {
+            self.mutex.try_lock().is_none()
+        }
+    }
+
+    static CR3: AtomicU64 = AtomicU64::new(0);
+    static CR4: AtomicU64 = AtomicU64::new(0);
+
+    pub(crate) fn without_interrupts<F, R>(f: F) -> R
+    where
+        F: FnOnce() -> R,
+    {
+        f()
+    }
+
+    pub(crate) fn read_cr3() -> u64 {
+        CR3.load(Ordering::Acquire)
+    }
+
+    pub(crate) unsafe fn write_cr3(value: u64) {
+        // Like the processor, ignore the no flush bit
+        CR3.store(value & !(1 << 63), Ordering::Release)
+    }
+
+    pub(crate) fn read_cr4() -> u64 {
+        CR4.load(Ordering::Acquire)
+    }
+
+    pub(crate) unsafe fn write_cr4(value: u64) {
+        CR4.store(value, Ordering::Release)
+    }
+
+    pub(crate) unsafe fn invpcid(_kind: u64, _pcid: u64, _address: VirtAddr) {}
+
+    pub(crate) fn flush_page(_address: VirtAddr) {}
+
+    pub(crate) fn flush_all() {}
+}
@@ -0,0 +1,177 @@
+use page_management::physical::{
+    map::PhysicalMemoryMap,
+    page_usage::{PageUsage, PageUsageRawType, UsageCategory},
+    run_length_map::{Run, RunLengthMemoryMap},
+};
+use x86_64::{
+    structures::paging::{FrameAllocator, PhysFrame},
+    PhysAddr,
+};
+
+const PAGES: u64 = 256;
+
+fn base() -> PhysFrame {
+    PhysFrame::containing_address(PhysAddr::new(0x10_0000))
+}
+
+fn empty_run() -> Run {
+    Run {
+        start: 0,
+        usage: PageUsageRawType::from_category(0),
+    }
+}
+
+#[test]
+pub fn test_matches_flat_map() {
+    let mut flat_buffer =
+        vec![PageUsageRawType::from_category(0); PAGES as usize];
+    let mut flat =
+        PhysicalMemoryMap::create(&mut flat_buffer, base(), PageUsage::Empty);
+
+    let mut run_buffer = vec![empty_run(); PAGES as usize];
+    let mut runs = RunLengthMemoryMap::create(
+        &mut run_buffer,
+        base(),
+        PAGES,
+        PageUsage::Empty,
+    );
+
+    let usages = [
+        PageUsage::Empty,
+        PageUsage::KernelHeap,
+        PageUsage::PageTable,
+        PageUsage::Shared { refcount: 2 },
+    ];
+
+    // A simple linear congruential generator, so the test is deterministic
+    let mut state = 12345u64;
+    for _ in 0..2000 {
+        state = state.wrapping_mul(6364136223846793005).wrapping_add(1);
+        let frame = base() + (state >> 33) % PAGES;
+        let usage = usages[((state >> 20) % 4) as usize];
+
+        assert_eq!(runs.set(frame, usage), flat.set(frame, usage));
+        assert_eq!(runs.get(frame), Some(usage));
+    }
+
+    assert!(runs.iter().eq(flat.iter()));
+    assert_eq!(runs.empty_frames(), flat.empty_frames());
+    assert_eq!(
+        runs.find_unused_frame().map(|frame| frame.frame()),
+        flat.find_unused_frame().map(|frame| frame.frame())
+    );
+
+    let compressed =
+        RunLengthMemoryMap::from_map(&flat, &mut run_buffer).unwrap();
+    assert!(compressed.iter().eq(flat.iter()));
+}
+
+#[test]
+pub fn test_runs_merge() {
+    let mut buffer = vec![empty_run(); 8];
+    let mut map = RunLengthMemoryMap::create(
+        &mut buffer,
+        base(),
+        PAGES,
+        PageUsage::Empty,
+    );
+
+    map.set(base() + 10, PageUsage::KernelHeap);
+    map.set(base() + 12, PageUsage::KernelHeap);
+    assert_eq!(map.runs().len(), 5);
+
+    map.set(base() + 11, PageUsage::KernelHeap);
+    assert_eq!(map.runs().len(), 3);
+
+    for index in 10..13 {
+        map.set(base() + index, PageUsage::Empty);
+    }
+    assert_eq!(map.runs().len(), 1);
+    assert_eq!(map.empty_frames(), PAGES as usize);
+}
+
+#[test]
+pub fn test_out_of_runs() {
+    let mut buffer = vec![empty_run(); 2];
+    let mut map = RunLengthMemoryMap::create(
+        &mut buffer,
+        base(),
+        PAGES,
+        PageUsage::Empty,
+    );
+
+    assert_eq!(
+        map.set(base() + 10, PageUsage::KernelHeap),
+        None,
+        "Splitting a run in the middle needs three runs"
+    );
+    assert_eq!(map.get(base() + 10), Some(PageUsage::Empty));
+
+    assert_eq!(
+        map.set(base(), PageUsage::KernelHeap),
+        Some(PageUsage::Empty)
+    );
+    assert_eq!(map.get(base() + PAGES), None);
+}
+
+#[test]
+pub fn test_run_length_physical_memory_map() {
+    let mut buffer = vec![empty_run(); 4];
+    let mut map = PhysicalMemoryMap::create_run_length(
+        &mut buffer,
+        base(),
+        PAGES,
+        PageUsage::Empty,
+    );
+
+    let frame = map
+        .frame_allocator(PageUsage::UserData)
+        .allocate_frame()
+        .unwrap()
+        .frame();
+    assert_eq!(frame, base());
+    assert_eq!(map.acquire_shared(frame), Some(2));
+    assert_eq!(map.refcount(frame), Some(2));
+
+    let statistics = map.statistics();
+    assert_eq!(statistics.get(UsageCategory::Shared), 1);
+    assert_eq!(statistics.get(UsageCategory::Empty), PAGES - 1);
+
+    map.frame_allocator(PageUsage::KernelHeap)
+        .allocate_frame()
+        .unwrap();
+    map.frame_allocator(PageUsage::PageTable)
+        .allocate_frame()
+        .unwrap();
+
+    // The next frame would need a fifth run
+    assert!(map
+        .frame_allocator(PageUsage::KernelHeap)
+        .allocate_frame()
+        .is_none());
+    assert_eq!(map.get(base() + 3), Some(PageUsage::Empty));
+    assert_eq!(map.empty_frames(), (PAGES - 3) as usize);
+}
+
+#[test]
+pub fn test_frees_without_room_are_counted_as_leaked() {
+    let mut buffer = vec![empty_run(); 2];
+    let mut map = PhysicalMemoryMap::create_run_length(
+        &mut buffer,
+        base(),
+        PAGES,
+        PageUsage::KernelHeap,
+    );
+    assert_eq!(map.statistics().get(UsageCategory::KernelHeap), PAGES);
+
+    // Freeing a frame in the middle of the run needs three runs
+    map.free(base() + 10);
+    assert_eq!(map.get(base() + 10), Some(PageUsage::KernelHeap));
+    assert_eq!(map.leaked_frames(), 1);
+    assert_eq!(map.empty_frames(), 0);
+
+    map.free(base());
+    assert_eq!(map.get(base()), Some(PageUsage::Empty));
+    assert_eq!(map.leaked_frames(), 1);
+    assert_eq!(map.empty_frames(), 1);
+}
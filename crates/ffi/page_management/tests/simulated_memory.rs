@@ -0,0 +1,478 @@
+use page_management::{
+    host::SimulatedMemory,
+    page_table::{
+        audit::Violation,
+        copy_on_write::CopyOnWriteError,
+        identity_page,
+        managed_page_table::{
+            kernel_heap_range, ManagedPageTable, ModificationFlags,
+        },
+        shared_memory::SharedMemory,
+    },
+    physical::{
+        map::PhysicalMemoryMap,
+        page_usage::{PageUsage, UsageCategory},
+    },
+};
+use x86_64::{
+    structures::paging::{
+        page::PageRange, MapperAllSizes, Page, PageSize, PageTableFlags,
+        PhysFrame, Size4KiB,
+    },
+    PhysAddr, VirtAddr,
+};
+
+/// Each page table takes 257 frames for its root and kernel half
+const FRAMES: u64 = 2048;
+
+/// frame hands out the last frames, away from the allocated ones
+const FIXED_FRAMES: u64 = 64;
+
+fn user_page(index: u64) -> Page<Size4KiB> {
+    Page::containing_address(VirtAddr::new(0x1000_0000)) + index
+}
+
+fn frame(index: u64) -> PhysFrame<Size4KiB> {
+    assert!(index < FIXED_FRAMES);
+    PhysFrame::containing_address(PhysAddr::new(0))
+        + (FRAMES - FIXED_FRAMES + index)
+}
+
+fn user_space() -> ModificationFlags {
+    ModificationFlags {
+        user_space: true,
+        ..Default::default()
+    }
+}
+
+fn kernel_heap() -> ModificationFlags {
+    ModificationFlags {
+        kernel_heap: true,
+        ..Default::default()
+    }
+}
+
+fn empty_frames() -> usize {
+    PhysicalMemoryMap::global(|map| map.empty_frames())
+}
+
+fn heap_frames() -> u64 {
+    PhysicalMemoryMap::global(|map| {
+        map.statistics().get(UsageCategory::KernelHeap)
+    })
+}
+
+#[test]
+pub fn test_map_pages() {
+    let memory = SimulatedMemory::new(FRAMES);
+    let mut page_table = memory.create_page_table();
+
+    let result = page_table.modify(user_space(), |manager| unsafe {
+        manager.map_pages(
+            user_page(0),
+            [frame(40), frame(41)].iter().copied(),
+            PageTableFlags::PRESENT | PageTableFlags::WRITABLE,
+            false,
+        )
+    });
+    assert_eq!(result, Ok(()));
+
+    let mapper = unsafe { page_table.mapper() };
+    assert_eq!(
+        mapper.translate_addr(user_page(1).start_address() + 0x10u64),
+        Some(frame(41).start_address() + 0x10u64)
+    );
+    assert_eq!(mapper.translate_addr(user_page(2).start_address()), None);
+}
+
+#[test]
+pub fn test_user_space_requires_flag() {
+    let memory = SimulatedMemory::new(FRAMES);
+    let mut page_table = memory.create_page_table();
+
+    let result = page_table.modify(Default::default(), |manager| unsafe {
+        manager.map_pages(
+            user_page(0),
+            [frame(40)].iter().copied(),
+            PageTableFlags::PRESENT,
+            false,
+        )
+    });
+    assert_eq!(result, Err(()));
+}
+
+#[test]
+pub fn test_unmap_pages_and_release() {
+    let memory = SimulatedMemory::new(FRAMES);
+    let mut page_table = memory.create_page_table();
+    unsafe {
+        page_table.activate();
+    }
+
+    let heap = kernel_heap_range();
+    let pages = PageRange {
+        start: heap.start,
+        end: heap.start + 4,
+    };
+    let empty = empty_frames();
+
+    ManagedPageTable::modify_global(kernel_heap(), |manager| unsafe {
+        manager.map_blank_pages(
+            pages.start,
+            4,
+            PageTableFlags::PRESENT | PageTableFlags::WRITABLE,
+            true,
+            PageUsage::KernelHeap,
+        )
+    })
+    .unwrap();
+
+    // Four pages plus a level 2 and a level 1 table
+    assert_eq!(empty_frames(), empty - 6);
+    assert_eq!(heap_frames(), 4);
+
+    ManagedPageTable::modify_global(kernel_heap(), |manager| unsafe {
+        manager.unmap_pages_and_release(pages, true)
+    })
+    .unwrap();
+
+    // The page tables are kept
+    assert_eq!(empty_frames(), empty - 2);
+    assert_eq!(heap_frames(), 0);
+}
+
+#[test]
+pub fn test_map_blank_pages_fails_when_frames_run_out() {
+    let memory = SimulatedMemory::new(FRAMES);
+    let mut page_table = memory.create_page_table();
+    unsafe {
+        page_table.activate();
+    }
+
+    let heap = kernel_heap_range();
+    let length = empty_frames() + 1;
+
+    let result =
+        ManagedPageTable::modify_global(kernel_heap(), |manager| unsafe {
+            manager.map_blank_pages(
+                heap.start,
+                length,
+                PageTableFlags::PRESENT | PageTableFlags::WRITABLE,
+                true,
+                PageUsage::KernelHeap,
+            )
+        });
+    assert_eq!(result, Err(()));
+
+    // Every frame that was mapped before running out is released again
+    assert_eq!(heap_frames(), 0);
+    let mapper = unsafe { page_table.mapper() };
+    assert_eq!(mapper.translate_addr(heap.start.start_address()), None);
+}
+
+#[test]
+pub fn test_only_user_data_is_shared() {
+    let _memory = SimulatedMemory::new(FRAMES);
+
+    let heap = frame(41);
+    PhysicalMemoryMap::global(|map| {
+        map.set(heap, PageUsage::KernelHeap);
+        assert_eq!(map.acquire_shared(heap), None);
+        assert_eq!(map.get(heap), Some(PageUsage::KernelHeap));
+    });
+}
+
+#[test]
+pub fn test_shared_frame_released_by_last_mapping() {
+    let memory = SimulatedMemory::new(FRAMES);
+    let mut page_table = memory.create_page_table();
+
+    let shared = frame(40);
+    PhysicalMemoryMap::global(|map| {
+        map.set(shared, PageUsage::UserData);
+        assert_eq!(map.acquire_shared(shared), Some(2));
+    });
+
+    page_table.modify(user_space(), |manager| unsafe {
+        for index in 0..2 {
+            manager
+                .map_pages(
+                    user_page(index),
+                    [shared].iter().copied(),
+                    PageTableFlags::PRESENT,
+                    false,
+                )
+                .unwrap();
+        }
+
+        manager
+            .unmap_pages_and_release(
+                PageRange {
+                    start: user_page(0),
+                    end: user_page(1),
+                },
+                false,
+            )
+            .unwrap();
+        assert_eq!(
+            PhysicalMemoryMap::global(|map| map.get(shared)),
+            Some(PageUsage::Shared { refcount: 1 })
+        );
+
+        manager
+            .unmap_pages_and_release(
+                PageRange {
+                    start: user_page(1),
+                    end: user_page(2),
+                },
+                false,
+            )
+            .unwrap();
+        assert_eq!(
+            PhysicalMemoryMap::global(|map| map.get(shared)),
+            Some(PageUsage::Empty)
+        );
+    });
+}
+
+fn frame_contents(frame: PhysFrame) -> &'static [u8] {
+    unsafe {
+        core::slice::from_raw_parts(
+            identity_page(frame).start_address().as_ptr(),
+            Size4KiB::SIZE as usize,
+        )
+    }
+}
+
+fn translate_page(page_table: &mut ManagedPageTable) -> PhysFrame {
+    let address = unsafe { page_table.mapper() }
+        .translate_addr(user_page(0).start_address())
+        .unwrap();
+    PhysFrame::containing_address(address)
+}
+
+#[test]
+pub fn test_copy_on_write_copies_shared_frames() {
+    let memory = SimulatedMemory::new(FRAMES);
+    let mut parent = memory.create_page_table();
+
+    let original = frame(40);
+    PhysicalMemoryMap::global(|map| {
+        map.set(original, PageUsage::UserData);
+    });
+    unsafe {
+        identity_page(original)
+            .start_address()
+            .as_mut_ptr::<u8>()
+            .write_bytes(0x42, Size4KiB::SIZE as usize);
+    }
+
+    parent
+        .modify(user_space(), |manager| unsafe {
+            manager.map_pages(
+                user_page(0),
+                [original].iter().copied(),
+                PageTableFlags::PRESENT | PageTableFlags::WRITABLE,
+                false,
+            )
+        })
+        .unwrap();
+
+    let mut child = parent.fork().unwrap();
+    PhysicalMemoryMap::global(|map| {
+        assert_eq!(map.refcount(original), Some(2));
+    });
+
+    // The parent writes first, so it gets a copy
+    let address = user_page(0).start_address();
+    assert_eq!(parent.handle_copy_on_write_fault(address), Ok(()));
+    let copy = translate_page(&mut parent);
+    assert_ne!(copy, original);
+    assert_eq!(frame_contents(copy), frame_contents(original));
+    PhysicalMemoryMap::global(|map| {
+        assert_eq!(map.get(copy), Some(PageUsage::UserData));
+        assert_eq!(map.refcount(original), Some(1));
+    });
+    assert_eq!(
+        parent.handle_copy_on_write_fault(address),
+        Err(CopyOnWriteError::NotCopyOnWrite),
+        "The copy is writable"
+    );
+
+    // The child is the last holder and keeps the original frame
+    assert_eq!(child.handle_copy_on_write_fault(address), Ok(()));
+    assert_eq!(translate_page(&mut child), original);
+    PhysicalMemoryMap::global(|map| {
+        assert_eq!(map.get(original), Some(PageUsage::UserData));
+    });
+    assert_eq!(
+        child.handle_copy_on_write_fault(address),
+        Err(CopyOnWriteError::NotCopyOnWrite),
+        "The original frame is writable again"
+    );
+}
+
+#[test]
+pub fn test_audit_combines_parent_flags() {
+    let memory = SimulatedMemory::new(FRAMES);
+    let mut page_table = memory.create_page_table();
+
+    page_table.modify(user_space(), |manager| unsafe {
+        manager
+            .map_pages(
+                user_page(0),
+                [frame(40)].iter().copied(),
+                PageTableFlags::PRESENT | PageTableFlags::WRITABLE,
+                false,
+            )
+            .unwrap();
+    });
+
+    let mut violations = Vec::new();
+    page_table.audit(&[], &[], |_, violation| violations.push(violation));
+    assert_eq!(violations, [Violation::WritableAndExecutable]);
+
+    let pages = PageRange {
+        start: user_page(0),
+        end: user_page(1),
+    };
+    assert_eq!(page_table.audit(&[pages.clone()], &[], |_, _| {}), 0);
+
+    // Code may be executed, but it still may not be written
+    assert_eq!(page_table.audit(&[], &[pages], |_, _| {}), 1);
+
+    unsafe {
+        let entry = &mut page_table.page_table_mut()[user_page(0).p4_index()];
+        entry.set_flags(entry.flags() | PageTableFlags::NO_EXECUTE);
+    }
+    assert_eq!(page_table.audit(&[], &[], |_, _| {}), 0);
+}
+
+#[test]
+pub fn test_find_free_pages_in_range() {
+    let memory = SimulatedMemory::new(FRAMES);
+    let mut page_table = memory.create_page_table();
+    unsafe {
+        page_table.activate();
+    }
+
+    let heap = kernel_heap_range();
+
+    let free = ManagedPageTable::modify_global(kernel_heap(), |manager| {
+        unsafe {
+            manager
+                .map_blank_pages(
+                    heap.start,
+                    2,
+                    PageTableFlags::PRESENT,
+                    false,
+                    PageUsage::KernelHeap,
+                )
+                .unwrap();
+        }
+
+        // The last possible start is the only one that fits
+        let fitting = PageRange {
+            start: heap.start,
+            end: heap.start + 6,
+        };
+
+        (
+            manager.find_free_pages_in_range(heap.clone(), 4, 1),
+            manager.find_free_pages_in_range(fitting.clone(), 4, 1),
+            manager.find_free_pages_in_range(fitting, 5, 1),
+        )
+    });
+
+    let expected = PageRange {
+        start: heap.start + 2,
+        end: heap.start + 6,
+    };
+    assert_eq!(free, (Some(expected.clone()), Some(expected), None));
+}
+
+#[test]
+pub fn test_shared_memory_outlives_handle() {
+    let memory = SimulatedMemory::new(FRAMES);
+    let mut first = memory.create_page_table();
+    let mut second = memory.create_page_table();
+    let empty = empty_frames();
+
+    let object = SharedMemory::new(2).unwrap();
+    let frames: Vec<_> = object.frames().collect();
+
+    object
+        .map(&mut first, user_page(0), PageTableFlags::WRITABLE)
+        .unwrap();
+    object
+        .map(&mut second, user_page(8), PageTableFlags::empty())
+        .unwrap();
+    assert_eq!(
+        unsafe { second.mapper() }.translate_addr(user_page(9).start_address()),
+        Some(frames[1].start_address())
+    );
+
+    drop(object);
+    PhysicalMemoryMap::global(|map| {
+        assert_eq!(map.refcount(frames[0]), Some(2));
+    });
+
+    // The frames outlive the handle, but not the mappings
+    first
+        .modify(user_space(), |manager| unsafe {
+            manager.unmap_pages_and_release(
+                PageRange {
+                    start: user_page(0),
+                    end: user_page(2),
+                },
+                false,
+            )
+        })
+        .unwrap();
+    second
+        .modify(user_space(), |manager| unsafe {
+            manager.unmap_pages_and_release(
+                PageRange {
+                    start: user_page(8),
+                    end: user_page(10),
+                },
+                false,
+            )
+        })
+        .unwrap();
+
+    // Only the three page tables of each mapping are left
+    assert_eq!(empty_frames(), empty - 6);
+}
+
+#[test]
+pub fn test_fork_shares_shared_memory() {
+    let memory = SimulatedMemory::new(FRAMES);
+    let mut parent = memory.create_page_table();
+
+    assert!(SharedMemory::new(0).is_none());
+
+    let object = SharedMemory::new(1).unwrap();
+    let frame = object.frames().next().unwrap();
+    object
+        .map(&mut parent, user_page(0), PageTableFlags::WRITABLE)
+        .unwrap();
+
+    let mut child = parent.fork().unwrap();
+    PhysicalMemoryMap::global(|map| {
+        assert_eq!(map.refcount(frame), Some(3));
+    });
+
+    // Writing does not fault, so both keep using the same frame
+    for page_table in [&mut parent, &mut child].iter_mut() {
+        assert_eq!(
+            page_table.handle_copy_on_write_fault(user_page(0).start_address()),
+            Err(CopyOnWriteError::NotCopyOnWrite)
+        );
+        assert_eq!(
+            unsafe { page_table.mapper() }
+                .translate_addr(user_page(0).start_address()),
+            Some(frame.start_address())
+        );
+    }
+}
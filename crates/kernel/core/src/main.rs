@@ -8,19 +8,26 @@ use core::panic::PanicInfo;
 use cpu_local_storage::get_core_id;
 use interrupt_handling::perform_system_call;
 use log::*;
-use page_management::physical::map::PhysicalMemoryMap;
+#[cfg(feature = "dump_page_table")]
+use page_management::page_table::managed_page_table::ManagedPageTable;
+use page_management::{
+    page_table::audit::audit_global, physical::map::PhysicalMemoryMap,
+};
 use parameters::KernelArguments;
 use raw_cpuid::*;
 use serial_io::*;
-use x86_64::instructions::{
-    interrupts,
-    interrupts::{enable_interrupts_and_hlt, int3},
+use x86_64::{
+    instructions::{
+        interrupts,
+        interrupts::{enable_interrupts_and_hlt, int3},
+    },
+    structures::paging::{page::PageRange, Page},
+    VirtAddr,
 };
 
 /// Import the global allocator from the allocators crate.
 ///
 /// This import has a side effect.
-#[allow(unused_imports)]
 use allocators::GLOBAL_ALLOCATOR;
 
 pub fn exit(status: i32) -> ! {
@@ -36,7 +43,7 @@ pub unsafe extern "sysv64" fn _start(args: *mut KernelArguments) -> ! {
 
     assert_ne!(args.physical_memory_map.pages(), 0);
 
-    let _args = args.init();
+    let args = args.init();
 
     interrupts::enable();
 
@@ -44,6 +51,27 @@ pub unsafe extern "sysv64" fn _start(args: *mut KernelArguments) -> ! {
 
     info!("Kernel core id: {:?}", get_core_id());
 
+    #[cfg(feature = "dump_page_table")]
+    ManagedPageTable::read_global().dump();
+    // The loader still identity maps physical memory into user space,
+    // it is needed until it has jumped to the kernel
+    let loader_identity = PhysicalMemoryMap::global(|memory_map| {
+        let frames = memory_map.physical_range();
+        PageRange {
+            start: Page::containing_address(VirtAddr::new(
+                frames.start.start_address().as_u64(),
+            )),
+            end: Page::containing_address(VirtAddr::new(
+                frames.end.start_address().as_u64(),
+            )),
+        }
+    });
+    audit_global(
+        &[loader_identity],
+        &[args.kernel_image.clone()],
+        cfg!(feature = "panic_on_mapping_violation"),
+    );
+
     PhysicalMemoryMap::global(|memory_map| {
         assert_ne!(memory_map.pages(), 0);
         info!(
@@ -53,7 +81,27 @@ pub unsafe extern "sysv64" fn _start(args: *mut KernelArguments) -> ! {
         );
     });
 
+    let statistics = PhysicalMemoryMap::global(|map| map.statistics());
+    #[cfg(feature = "leak_tracker")]
+    let heap = allocators::heap_leak_tracker().snapshot();
     allocation_test();
+    #[cfg(feature = "leak_tracker")]
+    {
+        let tracker = allocators::heap_leak_tracker();
+        let heap_after = tracker.snapshot();
+        tracker.report_leaks(&heap, &heap_after);
+        assert!(tracker.difference(&heap, &heap_after).is_empty());
+    }
+    let statistics_after = PhysicalMemoryMap::global(|map| map.statistics());
+
+    info!("Physical memory usage:\n{}", statistics_after);
+    info!(
+        "Physical memory usage change during allocation test:\n{}",
+        statistics_after.difference(&statistics)
+    );
+    GLOBAL_ALLOCATOR.statistics().report();
+    #[cfg(feature = "debug_allocator")]
+    allocators::check_heap();
 
     int3();
     info!("After breakpoint");
@@ -0,0 +1,81 @@
+use core::ptr::{null_mut, NonNull};
+
+/// The number of size classes each core can cache
+pub const ALLOCATION_CACHE_CLASSES: usize = 8;
+
+/// The number of free objects a magazine can hold
+pub const MAGAZINE_CAPACITY: usize = 32;
+
+/// A stack of free objects of one size class
+pub struct Magazine {
+    objects: [*mut u8; MAGAZINE_CAPACITY],
+    count: usize,
+}
+
+impl Magazine {
+    pub const fn new() -> Self {
+        Magazine {
+            objects: [null_mut(); MAGAZINE_CAPACITY],
+            count: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.count == MAGAZINE_CAPACITY
+    }
+
+    pub fn pop(&mut self) -> Option<NonNull<u8>> {
+        if self.is_empty() {
+            return None;
+        }
+
+        self.count -= 1;
+        NonNull::new(self.objects[self.count])
+    }
+
+    /// Returns the object if the magazine is full
+    pub fn push(&mut self, object: NonNull<u8>) -> Result<(), NonNull<u8>> {
+        if self.is_full() {
+            return Err(object);
+        }
+
+        self.objects[self.count] = object.as_ptr();
+        self.count += 1;
+        Ok(())
+    }
+}
+
+/// The objects are free, so they belong to whoever holds the magazine
+unsafe impl Send for Magazine {}
+
+impl Default for Magazine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Free heap objects cached by a core, one magazine per size class
+///
+/// The allocator only takes the heap lock to refill or drain a magazine.
+#[derive(Default)]
+pub struct AllocationCache {
+    pub magazines: [Magazine; ALLOCATION_CACHE_CLASSES],
+}
+
+const EMPTY: Magazine = Magazine::new();
+
+impl AllocationCache {
+    pub const fn new() -> Self {
+        AllocationCache {
+            magazines: [EMPTY; ALLOCATION_CACHE_CLASSES],
+        }
+    }
+}
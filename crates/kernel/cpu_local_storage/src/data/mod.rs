@@ -1,7 +1,9 @@
+mod allocation_cache;
 mod core_id;
 
-pub use self::core_id::*;
+pub use self::{allocation_cache::*, core_id::*};
 
 pub struct CpuLocalData {
     pub core_id: CoreId,
+    pub allocation_cache: AllocationCache,
 }
@@ -1,4 +1,5 @@
-use local_apic::Registers;
+use local_apic::{Registers, TLB_SHOOTDOWN_INTERRUPT};
+use page_management::page_table::tlb_shootdown;
 use x86_64::structures::idt::InterruptStackFrame;
 
 pub extern "x86-interrupt" fn spurious_interrupt_handler(
@@ -13,3 +14,19 @@ pub extern "x86-interrupt" fn apic_timer_handler(
         Registers::global().end_of_interrupt();
     }
 }
+
+pub extern "x86-interrupt" fn tlb_shootdown_handler(
+    _frame: &mut InterruptStackFrame,
+) {
+    tlb_shootdown::handle_pending();
+
+    unsafe {
+        Registers::global().end_of_interrupt();
+    }
+}
+
+pub fn send_tlb_shootdown() {
+    unsafe {
+        Registers::global().send_ipi_to_others(TLB_SHOOTDOWN_INTERRUPT);
+    }
+}
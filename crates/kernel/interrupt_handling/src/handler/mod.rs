@@ -1,11 +1,23 @@
 use crate::handler::{
-    apic::{apic_timer_handler, spurious_interrupt_handler},
+    apic::{
+        apic_timer_handler, send_tlb_shootdown, spurious_interrupt_handler,
+        tlb_shootdown_handler,
+    },
     pic::{pic_timer_interrupt_handler, InterruptIndex},
 };
-use local_apic::{SPURIOUS_INTERRUPT, TIMER_INTERRUPT};
+use local_apic::{
+    SPURIOUS_INTERRUPT, TIMER_INTERRUPT, TLB_SHOOTDOWN_INTERRUPT,
+};
 use log::*;
-use x86_64::structures::idt::{
-    InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode,
+use page_management::page_table::{
+    copy_on_write::CopyOnWriteError, kernel_stack::KernelStack,
+    managed_page_table::ManagedPageTable, tlb_shootdown,
+};
+use x86_64::{
+    registers::control::Cr2,
+    structures::idt::{
+        InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode,
+    },
 };
 
 pub mod apic;
@@ -81,7 +93,11 @@ pub unsafe fn init() {
         asm_breakpoint_handler as *mut (),
     ));
 
-    IDT.double_fault.set_handler_fn(double_fault_handler);
+    // A kernel stack overflow faults while pushing the page fault frame,
+    // so the double fault handler needs a stack of its own
+    IDT.double_fault
+        .set_handler_fn(double_fault_handler)
+        .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
 
     IDT.page_fault.set_handler_fn(page_fault_handler);
     IDT.general_protection_fault
@@ -127,6 +143,8 @@ pub unsafe fn init() {
         IDT[TIMER_INTERRUPT as usize].set_handler_fn(apic_timer_handler);
         IDT[SPURIOUS_INTERRUPT as usize]
             .set_handler_fn(spurious_interrupt_handler);
+        IDT[TLB_SHOOTDOWN_INTERRUPT as usize]
+            .set_handler_fn(tlb_shootdown_handler);
     }
 
     IDT[0x80].set_handler_fn(core::mem::transmute(
@@ -137,20 +155,55 @@ pub unsafe fn init() {
 
     pic::init();
     local_apic::init();
+
+    tlb_shootdown::register_ipi_sender(send_tlb_shootdown);
+    tlb_shootdown::mark_current_core_online();
 }
 
 extern "x86-interrupt" fn double_fault_handler(
     frame: &mut InterruptStackFrame,
     _code: u64,
 ) -> ! {
+    check_kernel_stack_overflow(frame);
+
     panic!("Double fault\n{:#?}", frame)
 }
 
+fn check_kernel_stack_overflow(frame: &InterruptStackFrame) {
+    let address = Cr2::read();
+
+    if let Some(stack) = KernelStack::from_guard_address(address) {
+        panic!(
+            "Kernel stack overflow at {:?} into guard of {:?}\n{:#?}",
+            address, stack, frame
+        );
+    }
+}
+
 extern "x86-interrupt" fn page_fault_handler(
     frame: &mut InterruptStackFrame,
     code: PageFaultErrorCode,
 ) {
-    panic!("Page fault: {:?}\n{:#?}", code, frame)
+    let address = Cr2::read();
+
+    check_kernel_stack_overflow(frame);
+
+    if code.contains(
+        PageFaultErrorCode::PROTECTION_VIOLATION
+            | PageFaultErrorCode::CAUSED_BY_WRITE,
+    ) {
+        let mut page_table = unsafe { ManagedPageTable::read_global() };
+        match page_table.handle_copy_on_write_fault(address) {
+            Ok(()) => return,
+            Err(CopyOnWriteError::NotCopyOnWrite) => {},
+            Err(error) => panic!(
+                "Copy on write fault at {:?} failed: {:?}\n{:#?}",
+                address, error, frame
+            ),
+        }
+    }
+
+    panic!("Page fault at {:?}: {:?}\n{:#?}", address, code, frame)
 }
 extern "x86-interrupt" fn general_protection_fault_handler(
     frame: &mut InterruptStackFrame,
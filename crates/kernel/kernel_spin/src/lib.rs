@@ -1,18 +1,25 @@
 #![no_std]
 
+#[cfg(any(target_os = "none", target_os = "uefi"))]
 use core::{
     any::type_name,
     sync::atomic::{AtomicU64, Ordering},
 };
+#[cfg(any(target_os = "none", target_os = "uefi"))]
 use cpu_local_storage::{data::CoreId, get_core_id};
+#[cfg(any(target_os = "none", target_os = "uefi"))]
 use x86_64::instructions::interrupts;
 
 /// A Spinlock that disables interrupts while it is locked
 ///
 /// Interrupts are disabled in critical sections to prevent deadlocks
+///
+/// When not built for the kernel or the UEFI loader, there are no interrupts
+/// or cores, so it is a plain spinlock and users can be tested on the host.
 #[derive(Default)]
 pub struct KernelMutex<T> {
     mutex: spin::Mutex<T>,
+    #[cfg(any(target_os = "none", target_os = "uefi"))]
     current_holder_id: AtomicU64,
 }
 
@@ -20,6 +27,7 @@ impl<T> KernelMutex<T> {
     pub const fn new(data: T) -> Self {
         KernelMutex {
             mutex: spin::Mutex::new(data),
+            #[cfg(any(target_os = "none", target_os = "uefi"))]
             current_holder_id: AtomicU64::new(0),
         }
     }
@@ -29,6 +37,7 @@ impl<T> KernelMutex<T> {
         self.mutex.into_inner()
     }
 
+    #[cfg(any(target_os = "none", target_os = "uefi"))]
     pub fn lock<F, R>(&self, function: F) -> R
     where
         F: FnOnce(&mut T) -> R,
@@ -58,11 +67,33 @@ impl<T> KernelMutex<T> {
         })
     }
 
+    #[cfg(not(any(target_os = "none", target_os = "uefi")))]
+    pub fn lock<F, R>(&self, function: F) -> R
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        function(&mut self.mutex.lock())
+    }
+
+    /// The current core holds the lock, so locking it again would deadlock
+    #[cfg(any(target_os = "none", target_os = "uefi"))]
+    pub fn is_held_by_current_core(&self) -> bool {
+        self.holder() == Some(get_core_id())
+    }
+
+    /// There is only one core on the host, so any holder is the current one
+    #[cfg(not(any(target_os = "none", target_os = "uefi")))]
+    pub fn is_held_by_current_core(&self) -> bool {
+        self.mutex.try_lock().is_none()
+    }
+
+    #[cfg(any(target_os = "none", target_os = "uefi"))]
     fn holder(&self) -> Option<CoreId> {
         let raw = self.current_holder_id.load(Ordering::Acquire);
         CoreId::from_optional_full_id(raw)
     }
 
+    #[cfg(any(target_os = "none", target_os = "uefi"))]
     fn set_holder(&self, holder: Option<CoreId>) {
         let raw = CoreId::optional_to_optional_full_id(holder);
         self.current_holder_id.store(raw, Ordering::Release);
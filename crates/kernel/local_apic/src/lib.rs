@@ -1,8 +1,17 @@
 #![no_std]
 
+use core::{
+    mem::size_of,
+    sync::atomic::{spin_loop_hint, AtomicU64, Ordering},
+};
 use log::*;
-use page_management::page_table::identity_base;
-use x86_64::registers::model_specific::Msr;
+use page_management::page_table::mmio::{map_mmio, CacheType};
+use x86_64::{registers::model_specific::Msr, PhysAddr};
+
+const REGISTERS_PHYSICAL_ADDRESS: u64 = 0xFEE00000;
+
+/// The virtual address of the registers, or 0 before they are mapped
+static REGISTERS: AtomicU64 = AtomicU64::new(0);
 
 #[repr(C, align(16))]
 struct Register(u32);
@@ -98,12 +107,31 @@ pub struct Registers {
 }
 
 impl Registers {
-    pub unsafe fn global() -> &'static mut Self {
+    /// Map the registers as uncached device memory
+    ///
+    /// This only maps them once, so it may be called by each core.
+    unsafe fn map() {
+        if REGISTERS.load(Ordering::Acquire) != 0 {
+            return;
+        }
+
         // TODO UEFI may have changed the APIC base address.
         // Parse the ACPI tables to figure it out properly
+        let address = map_mmio(
+            PhysAddr::new(REGISTERS_PHYSICAL_ADDRESS),
+            size_of::<Self>() as u64,
+            CacheType::Uncached,
+        )
+        .expect("Could not map the local APIC registers");
+
+        REGISTERS.store(address.as_u64(), Ordering::Release);
+    }
 
-        &mut *(identity_base().start_address() + 0xFEE00000u64)
-            .as_mut_ptr::<Self>()
+    pub unsafe fn global() -> &'static mut Self {
+        let address = REGISTERS.load(Ordering::Acquire);
+        assert_ne!(address, 0, "The local APIC has not been initialized");
+
+        &mut *(address as *mut Self)
     }
 
     pub fn end_of_interrupt(&mut self) {
@@ -111,6 +139,19 @@ impl Registers {
             self.end_of_interrupt.write(0);
         }
     }
+
+    /// Send a fixed interrupt to all cores except the current one
+    pub unsafe fn send_ipi_to_others(&mut self, vector: u32) {
+        const DELIVERY_PENDING: u32 = 1 << 12;
+        const ALL_EXCLUDING_SELF: u32 = 0b11 << 18;
+
+        self.interrupt_command[1].write(0);
+        self.interrupt_command[0].write(ALL_EXCLUDING_SELF | vector);
+
+        while self.interrupt_command[0].read() & DELIVERY_PENDING != 0 {
+            spin_loop_hint();
+        }
+    }
 }
 
 #[repr(u32)]
@@ -134,10 +175,12 @@ pub enum ApicTimerMode {
 
 pub const SPURIOUS_INTERRUPT: u32 = 0xFF;
 pub const TIMER_INTERRUPT: u32 = 48;
+pub const TLB_SHOOTDOWN_INTERRUPT: u32 = 49;
 
 pub unsafe fn init() {
     let mut apic_base = Msr::new(0x1B);
 
+    Registers::map();
     let lapic = Registers::global();
 
     info!("Local APIC version: {}", lapic.version.read());
@@ -1,13 +1,13 @@
 #![no_std]
 
-use core::{slice::from_raw_parts_mut, time::Duration};
+use core::time::Duration;
 use log::*;
-use page_management::physical::{
-    map::PhysicalMemoryMap, page_usage::PageUsageRawType,
-};
+use page_management::physical::map::PhysicalMemoryMap;
 use uefi::table::{Runtime, SystemTable};
 use x86_64::{
-    instructions::interrupts, structures::paging::Page, PhysAddr, VirtAddr,
+    instructions::interrupts,
+    structures::paging::{page::PageRange, Page},
+    PhysAddr, VirtAddr,
 };
 
 pub type KernelEntrySignature =
@@ -19,12 +19,15 @@ pub struct KernelArguments {
 
     pub physical_memory_map: PhysicalMemoryMap<'static>,
     pub identity_base: Page,
+    /// Where the kernel is mapped, the only executable part of the identity region
+    pub kernel_image: PageRange,
 
     pub rsdp: PhysAddr,
 }
 
 pub struct InitializedKernelArguments {
     pub st: SystemTable<Runtime>,
+    pub kernel_image: PageRange,
 }
 
 impl KernelArguments {
@@ -36,21 +39,17 @@ impl KernelArguments {
             );
         }
 
+        // This has to happen before the kernel maps anything,
+        // so all kernel mappings are global
+        let pcid_enabled = unsafe { page_management::page_table::pcid::init() };
+        let pat_initialized =
+            unsafe { page_management::page_table::mmio::init_pat() };
+
         // TODO self is pretty hacky. The arguments should probably not contain any pointers, but physical addresses
         unsafe {
-            let (buffer, base) = self.physical_memory_map.release();
-            let pointer = buffer.as_mut_ptr();
-
-            let address: VirtAddr = VirtAddr::from_ptr(pointer)
-                + self.identity_base.start_address().as_u64();
-            let new_pointer = address.as_mut_ptr::<PageUsageRawType>();
-
-            let new_buffer = from_raw_parts_mut(new_pointer, buffer.len());
-
-            let physical_memory_map =
-                PhysicalMemoryMap::from_raw_parts(new_buffer, base);
-
-            physical_memory_map.register_global();
+            self.physical_memory_map
+                .relocate(self.identity_base.start_address().as_u64())
+                .register_global();
         }
 
         unsafe {
@@ -68,6 +67,8 @@ impl KernelArguments {
         log::set_max_level(LevelFilter::Trace);
 
         info!("KernelArguments initialized");
+        info!("PCID enabled: {}", pcid_enabled);
+        info!("PAT initialized: {}", pat_initialized);
 
         unsafe {
             interrupt_handling::init();
@@ -78,6 +79,9 @@ impl KernelArguments {
         }
         interrupts::enable();
 
-        InitializedKernelArguments { st: self.st }
+        InitializedKernelArguments {
+            st: self.st,
+            kernel_image: self.kernel_image,
+        }
     }
 }
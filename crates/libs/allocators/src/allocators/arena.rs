@@ -0,0 +1,453 @@
+use crate::{
+    allocators::kernel_heap_pages::KernelHeapPages, traits::Allocator,
+};
+use core::{
+    alloc::{AllocErr, AllocRef, CannotReallocInPlace, Layout},
+    any::type_name,
+    marker::PhantomData,
+    mem::{align_of, size_of},
+    ptr::NonNull,
+};
+use log::*;
+
+/// The size of the chunks an arena takes from its backing allocator
+pub const ARENA_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Allocations larger than this get a chunk of their own
+///
+/// The current chunk keeps serving smaller allocations,
+/// instead of being abandoned for a large one.
+pub const ARENA_LARGE_ALLOCATION: usize = ARENA_CHUNK_SIZE / 4;
+
+/// Stored at the start of each chunk
+struct ChunkHeader {
+    previous: Option<NonNull<ChunkHeader>>,
+    layout: Layout,
+}
+
+/// A position in an arena
+///
+/// Everything allocated after it can be released at once.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct ArenaMark {
+    chunk: Option<NonNull<ChunkHeader>>,
+    position: usize,
+    large: Option<NonNull<ChunkHeader>>,
+}
+
+/// A bump pointer allocator
+///
+/// Memory is carved from chunks allocated from B,
+/// so many short lived objects can be allocated without touching the locked heap.
+/// Freeing only reclaims the most recent allocation,
+/// everything else is released at once by a reset, at the end of a scope or when the arena is dropped.
+pub struct Arena<B = KernelHeapPages>
+where
+    B: Allocator,
+{
+    chunk: Option<NonNull<ChunkHeader>>,
+    /// The next free byte in the current chunk
+    position: usize,
+    /// The end of the current chunk
+    end: usize,
+    /// The chunks of large allocations, the most recent first
+    large: Option<NonNull<ChunkHeader>>,
+    backing: B,
+}
+
+unsafe impl<B> Send for Arena<B> where B: Send + Allocator {}
+
+impl<B> Default for Arena<B>
+where
+    B: Allocator + Default,
+{
+    fn default() -> Self {
+        Self::new(Default::default())
+    }
+}
+
+impl<B> Arena<B>
+where
+    B: Allocator,
+{
+    pub const fn new(backing: B) -> Self {
+        Arena {
+            chunk: None,
+            position: 0,
+            end: 0,
+            large: None,
+            backing,
+        }
+    }
+
+    /// The number of chunks that are currently allocated from the backing allocator
+    pub fn chunks(&self) -> usize {
+        let count = |mut chain: Option<NonNull<ChunkHeader>>| {
+            let mut chunks = 0;
+            while let Some(chunk) = chain {
+                chunks += 1;
+                chain = unsafe { chunk.as_ref().previous };
+            }
+            chunks
+        };
+
+        count(self.chunk) + count(self.large)
+    }
+
+    pub fn mark(&self) -> ArenaMark {
+        ArenaMark {
+            chunk: self.chunk,
+            position: self.position,
+            large: self.large,
+        }
+    }
+
+    /// An AllocRef that can be copied into collections
+    ///
+    /// The arena stays borrowed while any copy of it lives,
+    /// so it can't be reset under the collections.
+    pub fn by_ref(&mut self) -> ArenaRef<'_, B> {
+        ArenaRef {
+            arena: NonNull::from(self),
+            lifetime: PhantomData,
+        }
+    }
+
+    /// Release everything that was allocated after mark
+    ///
+    /// # Safety
+    /// None of the released allocations may be used afterwards.
+    /// The mark has to be taken from this arena and may not have been released already.
+    pub unsafe fn reset_to(&mut self, mark: ArenaMark) {
+        while let Some(chunk) = self.large {
+            if Some(chunk) == mark.large {
+                break;
+            }
+            self.large = self.release_chunk(chunk);
+        }
+
+        while let Some(chunk) = self.chunk {
+            if Some(chunk) == mark.chunk {
+                break;
+            }
+            self.chunk = self.release_chunk(chunk);
+        }
+
+        match self.chunk {
+            Some(chunk) => {
+                self.position = mark.position;
+                self.end =
+                    chunk.as_ptr() as usize + chunk.as_ref().layout.size();
+            },
+            None => {
+                self.position = 0;
+                self.end = 0;
+            },
+        }
+    }
+
+    /// Release all allocations and give all chunks back to the backing allocator
+    ///
+    /// # Safety
+    /// None of the allocations may be used afterwards.
+    pub unsafe fn reset(&mut self) {
+        self.reset_to(ArenaMark {
+            chunk: None,
+            position: 0,
+            large: None,
+        })
+    }
+
+    /// Run function, everything it allocates from the arena is released when it returns
+    ///
+    /// Scopes can be nested, each one only releases its own allocations.
+    pub fn scope<F, R>(&mut self, function: F) -> R
+    where
+        F: FnOnce(&mut Self) -> R,
+    {
+        let mark = self.mark();
+        let result = function(self);
+
+        unsafe {
+            self.reset_to(mark);
+        }
+        result
+    }
+
+    /// Take the next free memory of the current chunk
+    fn bump(&mut self, layout: Layout) -> Option<NonNull<u8>> {
+        if self.chunk.is_none() {
+            return None;
+        }
+
+        let start = self.position.checked_add(layout.align() - 1)?
+            & !(layout.align() - 1);
+        let end = start.checked_add(layout.size())?;
+        if end > self.end {
+            return None;
+        }
+
+        self.position = end;
+        NonNull::new(start as *mut u8)
+    }
+
+    /// Allocate a chunk of at least minimum bytes that can hold layout after its header
+    fn allocate_chunk(
+        &mut self,
+        layout: Layout,
+        minimum: usize,
+        previous: Option<NonNull<ChunkHeader>>,
+    ) -> Result<(NonNull<ChunkHeader>, usize), AllocErr> {
+        let size = size_of::<ChunkHeader>()
+            .checked_add(layout.size())
+            .and_then(|size| size.checked_add(layout.align()))
+            .ok_or(AllocErr)?
+            .max(minimum);
+        let chunk_layout =
+            Layout::from_size_align(size, align_of::<ChunkHeader>())
+                .map_err(|_| AllocErr)?;
+
+        trace!(
+            "{}: Allocating chunk of {} bytes",
+            type_name::<Self>(),
+            size
+        );
+
+        let (memory, _) = self.backing.alloc(chunk_layout)?;
+        let chunk = memory.cast::<ChunkHeader>();
+        unsafe {
+            chunk.as_ptr().write(ChunkHeader {
+                previous,
+                layout: chunk_layout,
+            });
+        }
+
+        Ok((chunk, size))
+    }
+
+    /// Continue bumping in a new chunk
+    ///
+    /// The rest of the current chunk stays unused until it is released.
+    fn next_chunk(&mut self, layout: Layout) -> Result<(), AllocErr> {
+        let (chunk, size) =
+            self.allocate_chunk(layout, ARENA_CHUNK_SIZE, self.chunk)?;
+        let start = chunk.as_ptr() as usize;
+
+        self.chunk = Some(chunk);
+        self.position = start + size_of::<ChunkHeader>();
+        self.end = start + size;
+
+        Ok(())
+    }
+
+    /// Give a large allocation a chunk of its own, next to the current one
+    fn large_chunk(&mut self, layout: Layout) -> Result<NonNull<u8>, AllocErr> {
+        let (chunk, _) = self.allocate_chunk(layout, 0, self.large)?;
+        self.large = Some(chunk);
+
+        let start = chunk.as_ptr() as usize + size_of::<ChunkHeader>();
+        let aligned = (start + layout.align() - 1) & !(layout.align() - 1);
+        NonNull::new(aligned as *mut u8).ok_or(AllocErr)
+    }
+
+    /// Give a chunk back to the backing allocator
+    ///
+    /// Returns the chunk before it.
+    unsafe fn release_chunk(
+        &mut self,
+        chunk: NonNull<ChunkHeader>,
+    ) -> Option<NonNull<ChunkHeader>> {
+        trace!("{}: Releasing chunk", type_name::<Self>());
+
+        let ChunkHeader { previous, layout } = chunk.as_ptr().read();
+        self.backing.dealloc(chunk.cast(), layout);
+        previous
+    }
+
+    /// The allocation is the most recent one, so it ends at the current position
+    fn is_last(&self, ptr: NonNull<u8>, layout: Layout) -> bool {
+        ptr.as_ptr() as usize + layout.size() == self.position
+    }
+}
+
+impl<B> Drop for Arena<B>
+where
+    B: Allocator,
+{
+    fn drop(&mut self) {
+        unsafe {
+            self.reset();
+        }
+    }
+}
+
+impl<B> Allocator for Arena<B>
+where
+    B: Allocator,
+{
+    fn alloc(
+        &mut self,
+        layout: Layout,
+    ) -> Result<(NonNull<u8>, usize), AllocErr> {
+        let ptr = match self.bump(layout) {
+            Some(ptr) => ptr,
+            None if layout.size() > ARENA_LARGE_ALLOCATION => {
+                self.large_chunk(layout)?
+            },
+            None => {
+                self.next_chunk(layout)?;
+                self.bump(layout).ok_or(AllocErr)?
+            },
+        };
+
+        Ok((ptr, layout.size()))
+    }
+
+    unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        if self.is_last(ptr, layout) {
+            self.position = ptr.as_ptr() as usize;
+        }
+    }
+
+    unsafe fn grow_in_place(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        let end = ptr.as_ptr() as usize + new_size;
+
+        if self.is_last(ptr, layout) && end <= self.end {
+            self.position = end;
+            Ok(new_size)
+        } else {
+            Err(CannotReallocInPlace)
+        }
+    }
+
+    unsafe fn shrink_in_place(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        if self.is_last(ptr, layout) {
+            self.position = ptr.as_ptr() as usize + new_size;
+        }
+        Ok(new_size)
+    }
+}
+
+unsafe impl<B> AllocRef for Arena<B>
+where
+    B: Allocator,
+{
+    fn alloc(
+        &mut self,
+        layout: Layout,
+    ) -> Result<(NonNull<u8>, usize), AllocErr> {
+        Allocator::alloc(self, layout)
+    }
+
+    unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        Allocator::dealloc(self, ptr, layout)
+    }
+
+    unsafe fn grow_in_place(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        Allocator::grow_in_place(self, ptr, layout, new_size)
+    }
+
+    unsafe fn shrink_in_place(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        Allocator::shrink_in_place(self, ptr, layout, new_size)
+    }
+}
+
+/// A copyable AllocRef to an arena, created by Arena::by_ref
+///
+/// Like MagicAllocRef, but the arena is borrowed instead of locked,
+/// so it can only be used by the core that owns the arena.
+pub struct ArenaRef<'a, B>
+where
+    B: Allocator,
+{
+    arena: NonNull<Arena<B>>,
+    lifetime: PhantomData<&'a mut Arena<B>>,
+}
+
+impl<'a, B> Clone for ArenaRef<'a, B>
+where
+    B: Allocator,
+{
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'a, B> Copy for ArenaRef<'a, B> where B: Allocator {}
+
+impl<'a, B> ArenaRef<'a, B>
+where
+    B: Allocator,
+{
+    pub fn arena<F, R>(&self, function: F) -> R
+    where
+        F: FnOnce(&mut Arena<B>) -> R,
+    {
+        // The copies never use the arena at the same time,
+        // each call has it to itself until it returns
+        function(unsafe { &mut *self.arena.as_ptr() })
+    }
+}
+
+unsafe impl<'a, B> AllocRef for ArenaRef<'a, B>
+where
+    B: Allocator,
+{
+    fn alloc(
+        &mut self,
+        layout: Layout,
+    ) -> Result<(NonNull<u8>, usize), AllocErr> {
+        self.arena(|a| Allocator::alloc(a, layout))
+    }
+
+    unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        self.arena(|a| Allocator::dealloc(a, ptr, layout))
+    }
+
+    unsafe fn realloc(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<(NonNull<u8>, usize), AllocErr> {
+        self.arena(|a| Allocator::realloc(a, ptr, layout, new_size))
+    }
+
+    unsafe fn grow_in_place(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        self.arena(|a| Allocator::grow_in_place(a, ptr, layout, new_size))
+    }
+
+    unsafe fn shrink_in_place(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        self.arena(|a| Allocator::shrink_in_place(a, ptr, layout, new_size))
+    }
+}
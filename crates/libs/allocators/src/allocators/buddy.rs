@@ -0,0 +1,332 @@
+use crate::traits::{Allocator, OwnerCheck, Reclaim};
+use core::{
+    alloc::{AllocErr, CannotReallocInPlace, Layout},
+    any::type_name,
+    ptr::NonNull,
+};
+use log::*;
+
+const MIN_BLOCK_SHIFT: usize = 10;
+
+/// The size of the smallest blocks
+pub const MIN_BLOCK_SIZE: usize = 1 << MIN_BLOCK_SHIFT;
+
+/// The number of block sizes, each twice the previous one
+pub const ORDERS: usize = 7;
+
+/// The size of the largest blocks, allocations can be at most this large
+pub const MAX_BLOCK_SIZE: usize = MIN_BLOCK_SIZE << (ORDERS - 1);
+
+/// The size of the region that is allocated from the backing allocator
+pub const REGION_SIZE: usize = MAX_BLOCK_SIZE * 16;
+
+const MIN_BLOCKS: usize = REGION_SIZE / MIN_BLOCK_SIZE;
+
+/// Marks the first minimal block of a free block in orders
+const FREE: u8 = 0x80;
+/// Marks minimal blocks that don't start a block
+const NO_BLOCK: u8 = 0xFF;
+
+/// Stored at the start of each free block
+struct FreeBlock {
+    previous: Option<NonNull<FreeBlock>>,
+    next: Option<NonNull<FreeBlock>>,
+}
+
+/// A power of two buddy allocator
+///
+/// It allocates a single region of REGION_SIZE from B when it is first used
+/// and splits it into blocks between MIN_BLOCK_SIZE and MAX_BLOCK_SIZE.
+/// Blocks are aligned to their size, freed blocks are merged with their free buddy.
+pub struct BuddyAllocator<B>
+where
+    B: Allocator,
+{
+    region: Option<NonNull<u8>>,
+    free_lists: [Option<NonNull<FreeBlock>>; ORDERS],
+    /// The order of each block, stored at the index of its first minimal block
+    orders: [u8; MIN_BLOCKS],
+    backing: B,
+}
+
+unsafe impl<B> Send for BuddyAllocator<B> where B: Send + Allocator {}
+
+impl<B> Default for BuddyAllocator<B>
+where
+    B: Allocator + Default,
+{
+    fn default() -> Self {
+        Self::new(Default::default())
+    }
+}
+
+impl<B> BuddyAllocator<B>
+where
+    B: Allocator,
+{
+    pub const fn new(backing: B) -> Self {
+        BuddyAllocator {
+            region: None,
+            free_lists: [None; ORDERS],
+            orders: [NO_BLOCK; MIN_BLOCKS],
+            backing,
+        }
+    }
+
+    /// The layout of the region allocated from the backing allocator
+    pub fn region_layout() -> Layout {
+        Layout::from_size_align(REGION_SIZE, MAX_BLOCK_SIZE).unwrap()
+    }
+
+    /// The order of the blocks serving layout
+    fn order(layout: Layout) -> Option<usize> {
+        let size = layout.size().max(layout.align()).max(MIN_BLOCK_SIZE);
+        if size > MAX_BLOCK_SIZE {
+            return None;
+        }
+
+        Some(
+            size.next_power_of_two().trailing_zeros() as usize
+                - MIN_BLOCK_SHIFT,
+        )
+    }
+
+    fn index(&self, block: NonNull<u8>) -> usize {
+        (block.as_ptr() as usize - self.region.unwrap().as_ptr() as usize)
+            / MIN_BLOCK_SIZE
+    }
+
+    fn block(&self, index: usize) -> NonNull<u8> {
+        unsafe {
+            NonNull::new_unchecked(
+                self.region.unwrap().as_ptr().add(index * MIN_BLOCK_SIZE),
+            )
+        }
+    }
+
+    unsafe fn push_free(&mut self, index: usize, order: usize) {
+        let block = self.block(index).cast::<FreeBlock>();
+        block.as_ptr().write(FreeBlock {
+            previous: None,
+            next: self.free_lists[order],
+        });
+        if let Some(mut next) = self.free_lists[order] {
+            next.as_mut().previous = Some(block);
+        }
+        self.free_lists[order] = Some(block);
+
+        self.orders[index] = order as u8 | FREE;
+    }
+
+    unsafe fn remove_free(&mut self, index: usize, order: usize) {
+        let block = self.block(index).cast::<FreeBlock>();
+        let FreeBlock { previous, next } = block.as_ptr().read();
+
+        match previous {
+            Some(mut previous) => previous.as_mut().next = next,
+            None => self.free_lists[order] = next,
+        }
+        if let Some(mut next) = next {
+            next.as_mut().previous = previous;
+        }
+
+        self.orders[index] = NO_BLOCK;
+    }
+
+    fn initialize_region(&mut self) -> Result<(), AllocErr> {
+        trace!("{}: Allocating region", type_name::<Self>());
+
+        let (region, _) = self.backing.alloc(Self::region_layout())?;
+        self.region = Some(region);
+
+        let max_order = ORDERS - 1;
+        for index in (0..MIN_BLOCKS).step_by(1 << max_order).rev() {
+            unsafe {
+                self.push_free(index, max_order);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// All blocks of the region are free and merged
+    fn is_unused(&self) -> bool {
+        let max_order = ORDERS - 1;
+
+        (0..MIN_BLOCKS)
+            .step_by(1 << max_order)
+            .all(|index| self.orders[index] == max_order as u8 | FREE)
+    }
+}
+
+impl<B> Drop for BuddyAllocator<B>
+where
+    B: Allocator,
+{
+    fn drop(&mut self) {
+        if let Some(region) = self.region.take() {
+            unsafe {
+                self.backing.dealloc(region, Self::region_layout());
+            }
+        }
+    }
+}
+
+impl<B> Allocator for BuddyAllocator<B>
+where
+    B: Allocator,
+{
+    fn alloc(
+        &mut self,
+        layout: Layout,
+    ) -> Result<(NonNull<u8>, usize), AllocErr> {
+        let order = Self::order(layout).ok_or(AllocErr)?;
+
+        if self.region.is_none() {
+            self.initialize_region()?;
+        }
+
+        // Take the smallest free block that is large enough
+        let mut current = (order..ORDERS)
+            .find(|order| self.free_lists[*order].is_some())
+            .ok_or(AllocErr)?;
+        let block = self.free_lists[current].unwrap().cast::<u8>();
+        let index = self.index(block);
+
+        unsafe {
+            self.remove_free(index, current);
+
+            // Split it, freeing the upper halves
+            while current > order {
+                current -= 1;
+                self.push_free(index + (1 << current), current);
+            }
+        }
+
+        self.orders[index] = order as u8;
+
+        trace!(
+            "{}: Allocated {} bytes at {:?}",
+            type_name::<Self>(),
+            MIN_BLOCK_SIZE << order,
+            block
+        );
+
+        Ok((block, MIN_BLOCK_SIZE << order))
+    }
+
+    unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        let mut order = Self::order(layout).unwrap();
+        let mut index = self.index(ptr);
+
+        debug_assert_eq!(self.orders[index], order as u8);
+
+        // Merge with the buddy as long as it is free
+        while order + 1 < ORDERS {
+            let buddy = index ^ (1 << order);
+            if self.orders[buddy] != order as u8 | FREE {
+                break;
+            }
+
+            self.remove_free(buddy, order);
+            self.orders[index] = NO_BLOCK;
+            index = index.min(buddy);
+            order += 1;
+        }
+
+        self.push_free(index, order);
+    }
+
+    unsafe fn grow_in_place(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        let order = Self::order(layout).unwrap();
+        let new_order = Layout::from_size_align(new_size, layout.align())
+            .ok()
+            .and_then(Self::order)
+            .ok_or(CannotReallocInPlace)?;
+        let index = self.index(ptr);
+
+        // The block can only grow into the free buddies following it
+        for current in order..new_order {
+            let buddy = index + (1 << current);
+            if index % (1 << (current + 1)) != 0
+                || self.orders[buddy] != current as u8 | FREE
+            {
+                return Err(CannotReallocInPlace);
+            }
+        }
+        for current in order..new_order {
+            self.remove_free(index + (1 << current), current);
+        }
+
+        self.orders[index] = new_order as u8;
+        Ok(MIN_BLOCK_SIZE << new_order)
+    }
+
+    unsafe fn shrink_in_place(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        let order = Self::order(layout).unwrap();
+        let new_order = Layout::from_size_align(new_size, layout.align())
+            .ok()
+            .and_then(Self::order)
+            .ok_or(CannotReallocInPlace)?;
+        let index = self.index(ptr);
+
+        // Free the upper halves, their buddies are still part of the block
+        for current in new_order..order {
+            self.push_free(index + (1 << current), current);
+        }
+
+        self.orders[index] = new_order as u8;
+        Ok(MIN_BLOCK_SIZE << new_order)
+    }
+}
+
+impl<B> OwnerCheck for BuddyAllocator<B>
+where
+    B: Allocator,
+{
+    fn is_owner(&self, ptr: NonNull<u8>, layout: Layout) -> bool {
+        let region = match self.region {
+            Some(region) => region.as_ptr() as usize,
+            None => return false,
+        };
+        let ptr = ptr.as_ptr() as usize;
+
+        Self::order(layout).is_some()
+            && ptr >= region
+            && ptr < region + REGION_SIZE
+    }
+}
+
+impl<B> Reclaim for BuddyAllocator<B>
+where
+    B: Allocator,
+{
+    /// Give the region back if nothing is allocated from it
+    fn reclaim(&mut self) -> usize {
+        let region = match self.region {
+            Some(region) if self.is_unused() => region,
+            _ => return 0,
+        };
+
+        trace!("{}: Releasing region", type_name::<Self>());
+
+        self.region = None;
+        self.free_lists = [None; ORDERS];
+        self.orders = [NO_BLOCK; MIN_BLOCKS];
+        unsafe {
+            self.backing.dealloc(region, Self::region_layout());
+        }
+
+        REGION_SIZE
+    }
+}
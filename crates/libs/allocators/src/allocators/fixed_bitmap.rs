@@ -1,27 +1,97 @@
 use crate::{
-    traits::{Allocator, OwnerCheck},
-    utils::bitset::BitSet,
+    traits::{Allocator, Occupancy, OwnerCheck},
+    utils::hierarchical_bitset::HierarchicalBitSet,
 };
 use core::{
-    alloc::{AllocErr, Layout},
+    alloc::{AllocErr, CannotReallocInPlace, Layout},
     any::type_name,
     mem::MaybeUninit,
     ptr::NonNull,
 };
 use log::*;
 
-const MAX_ALIGNMENT: usize = 16;
+/// The largest supported block alignment, a page
+pub const MAX_ALIGNMENT: usize = 4096;
+
+/// The largest alignment blocks of size can have
+///
+/// Packed blocks are aligned to the largest power of two dividing their size.
+pub const fn block_alignment(size: usize) -> usize {
+    let alignment = size & size.wrapping_neg();
+    if alignment == 0 || alignment > MAX_ALIGNMENT {
+        MAX_ALIGNMENT
+    } else {
+        alignment
+    }
+}
+
+/// The alignment of the blocks of a FixedBitMap
+pub struct Alignment<const ALIGNMENT: usize>;
+
+/// Implemented by the alignments blocks can have
+pub trait SupportedAlignment {
+    /// A zero sized type with the alignment
+    type Marker: Copy;
+}
+
+macro_rules! supported_alignments {
+    ($($alignment:tt => $marker:ident),* $(,)?) => {
+        $(
+            #[repr(align($alignment))]
+            #[derive(Copy, Clone)]
+            pub struct $marker;
+
+            impl SupportedAlignment for Alignment<{ $alignment }> {
+                type Marker = $marker;
+            }
+        )*
+    };
+}
 
-#[repr(align(16))]
-struct Block<const BLOCK_SIZE: usize>([u8; BLOCK_SIZE]);
+supported_alignments! {
+    1 => Align1,
+    2 => Align2,
+    4 => Align4,
+    8 => Align8,
+    16 => Align16,
+    32 => Align32,
+    64 => Align64,
+    128 => Align128,
+    256 => Align256,
+    512 => Align512,
+    1024 => Align1024,
+    2048 => Align2048,
+    4096 => Align4096,
+}
 
-pub struct FixedBitMap<const BLOCK_SIZE: usize, const CAPACITY: usize> {
-    bitmap: BitSet<{ CAPACITY }>,
-    storage: [Block<{ BLOCK_SIZE }>; CAPACITY],
+struct Block<const BLOCK_SIZE: usize, const ALIGNMENT: usize>(
+    [<Alignment<{ ALIGNMENT }> as SupportedAlignment>::Marker; 0],
+    [u8; BLOCK_SIZE],
+)
+where
+    Alignment<{ ALIGNMENT }>: SupportedAlignment;
+
+/// An allocator of CAPACITY blocks of BLOCK_SIZE bytes, aligned to ALIGNMENT
+///
+/// Allocations fit into a block if neither their size nor their alignment is larger.
+pub struct FixedBitMap<
+    const BLOCK_SIZE: usize,
+    const CAPACITY: usize,
+    const ALIGNMENT: usize,
+> where
+    Alignment<{ ALIGNMENT }>: SupportedAlignment,
+{
+    bitmap: HierarchicalBitSet<{ CAPACITY }>,
+    storage: [Block<{ BLOCK_SIZE }, { ALIGNMENT }>; CAPACITY],
 }
 
-impl<const BLOCK_SIZE: usize, const CAPACITY: usize>
-    FixedBitMap<{ BLOCK_SIZE }, { CAPACITY }>
+impl<
+        const BLOCK_SIZE: usize,
+        const CAPACITY: usize,
+        const ALIGNMENT: usize,
+    > FixedBitMap<{ BLOCK_SIZE }, { CAPACITY }, { ALIGNMENT }>
+where
+    Alignment<{ ALIGNMENT }>: SupportedAlignment,
 {
     #[allow(clippy::uninit_assumed_init)]
     pub fn new() -> Self {
@@ -40,22 +110,32 @@ impl<const BLOCK_SIZE: usize, const CAPACITY: usize>
     }
 }
 
-impl<const BLOCK_SIZE: usize, const CAPACITY: usize> Default
-    for FixedBitMap<{ BLOCK_SIZE }, { CAPACITY }>
+impl<
+        const BLOCK_SIZE: usize,
+        const CAPACITY: usize,
+        const ALIGNMENT: usize,
+    > Default for FixedBitMap<{ BLOCK_SIZE }, { CAPACITY }, { ALIGNMENT }>
+where
+    Alignment<{ ALIGNMENT }>: SupportedAlignment,
 {
     fn default() -> Self {
         Self::new()
     }
 }
 
-impl<const BLOCK_SIZE: usize, const CAPACITY: usize> Allocator
-    for FixedBitMap<{ BLOCK_SIZE }, { CAPACITY }>
+impl<
+        const BLOCK_SIZE: usize,
+        const CAPACITY: usize,
+        const ALIGNMENT: usize,
+    > Allocator for FixedBitMap<{ BLOCK_SIZE }, { CAPACITY }, { ALIGNMENT }>
+where
+    Alignment<{ ALIGNMENT }>: SupportedAlignment,
 {
     fn alloc(
         &mut self,
         layout: Layout,
     ) -> Result<(NonNull<u8>, usize), AllocErr> {
-        if layout.size() > BLOCK_SIZE || layout.align() > MAX_ALIGNMENT {
+        if layout.size() > BLOCK_SIZE || layout.align() > ALIGNMENT {
             return Err(AllocErr);
         }
 
@@ -92,10 +172,10 @@ impl<const BLOCK_SIZE: usize, const CAPACITY: usize> Allocator
         trace!("{}: dealloc {:?} {:?}", type_name::<Self>(), ptr, layout);
 
         debug_assert!(layout.size() <= BLOCK_SIZE);
-        debug_assert!(layout.align() <= MAX_ALIGNMENT);
+        debug_assert!(layout.align() <= ALIGNMENT);
         debug_assert!(self.is_owner(ptr, layout));
 
-        let ptr = ptr.as_ptr() as *const Block<{ BLOCK_SIZE }>;
+        let ptr = ptr.as_ptr() as *const Block<{ BLOCK_SIZE }, { ALIGNMENT }>;
         let index = ptr.offset_from(self.storage.as_ptr()) as usize;
 
         debug_assert!(index < CAPACITY);
@@ -109,13 +189,45 @@ impl<const BLOCK_SIZE: usize, const CAPACITY: usize> Allocator
             self.bitmap.capacity()
         );
     }
+
+    unsafe fn grow_in_place(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        debug_assert!(self.is_owner(ptr, layout));
+
+        // The allocation keeps its slot as long as it fits
+        if new_size <= BLOCK_SIZE {
+            Ok(BLOCK_SIZE)
+        } else {
+            Err(CannotReallocInPlace)
+        }
+    }
+
+    unsafe fn shrink_in_place(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        _new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        debug_assert!(self.is_owner(ptr, layout));
+
+        Ok(BLOCK_SIZE)
+    }
 }
 
-impl<const BLOCK_SIZE: usize, const CAPACITY: usize> OwnerCheck
-    for FixedBitMap<{ BLOCK_SIZE }, { CAPACITY }>
+impl<
+        const BLOCK_SIZE: usize,
+        const CAPACITY: usize,
+        const ALIGNMENT: usize,
+    > OwnerCheck for FixedBitMap<{ BLOCK_SIZE }, { CAPACITY }, { ALIGNMENT }>
+where
+    Alignment<{ ALIGNMENT }>: SupportedAlignment,
 {
     fn is_owner(&self, ptr: NonNull<u8>, layout: Layout) -> bool {
-        if layout.size() > BLOCK_SIZE || layout.align() > MAX_ALIGNMENT {
+        if layout.size() > BLOCK_SIZE || layout.align() > ALIGNMENT {
             return false;
         }
 
@@ -124,3 +236,20 @@ impl<const BLOCK_SIZE: usize, const CAPACITY: usize> OwnerCheck
         ptr >= self.start_pointer() && ptr <= self.end_pointer()
     }
 }
+
+impl<
+        const BLOCK_SIZE: usize,
+        const CAPACITY: usize,
+        const ALIGNMENT: usize,
+    > Occupancy for FixedBitMap<{ BLOCK_SIZE }, { CAPACITY }, { ALIGNMENT }>
+where
+    Alignment<{ ALIGNMENT }>: SupportedAlignment,
+{
+    fn is_unused(&self) -> bool {
+        self.bitmap.is_empty()
+    }
+
+    fn is_full(&self) -> bool {
+        self.bitmap.is_full()
+    }
+}
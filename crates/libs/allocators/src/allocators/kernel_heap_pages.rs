@@ -1,6 +1,6 @@
-use crate::traits::Allocator;
+use crate::traits::{Allocator, Reclaim, SharedReclaim};
 use core::{
-    alloc::{AllocErr, AllocRef, GlobalAlloc, Layout},
+    alloc::{AllocErr, AllocRef, CannotReallocInPlace, GlobalAlloc, Layout},
     ptr::NonNull,
 };
 use log::*;
@@ -21,6 +21,24 @@ use x86_64::{
 #[derive(Default, Copy, Clone, Debug)]
 pub struct KernelHeapPages;
 
+/// The first page of the allocation at ptr
+fn start_page(ptr: NonNull<u8>) -> Page<Size4KiB> {
+    Page::from_start_address(VirtAddr::from_ptr(ptr.as_ptr())).unwrap()
+}
+
+/// The number of pages of an allocation of layout resized to new_size
+fn resized_pages(
+    layout: Layout,
+    new_size: usize,
+) -> Result<usize, CannotReallocInPlace> {
+    let layout = Layout::from_size_align(new_size, layout.align())
+        .map_err(|_| CannotReallocInPlace)?;
+
+    layout_to_page_layout(layout)
+        .map(|(_, pages)| pages)
+        .map_err(|_| CannotReallocInPlace)
+}
+
 fn layout_to_page_layout(layout: Layout) -> Result<(Layout, usize), AllocErr> {
     let page_size = Size4KiB::SIZE as usize;
 
@@ -47,6 +65,22 @@ unsafe impl GlobalAlloc for KernelHeapPages {
             AllocRef::dealloc(&mut KernelHeapPages, ptr, layout);
         }
     }
+
+    unsafe fn realloc(
+        &self,
+        ptr: *mut u8,
+        layout: Layout,
+        new_size: usize,
+    ) -> *mut u8 {
+        match NonNull::new(ptr) {
+            Some(ptr) => {
+                Allocator::realloc(&mut KernelHeapPages, ptr, layout, new_size)
+                    .map(|r| r.0.as_ptr())
+                    .unwrap_or(core::ptr::null_mut())
+            },
+            None => core::ptr::null_mut(),
+        }
+    }
 }
 
 unsafe impl AllocRef for KernelHeapPages {
@@ -96,10 +130,7 @@ unsafe impl AllocRef for KernelHeapPages {
 
     unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
         let (_, pages) = layout_to_page_layout(layout).unwrap();
-        let start = Page::<Size4KiB>::from_start_address(VirtAddr::from_ptr(
-            ptr.as_ptr(),
-        ))
-        .unwrap();
+        let start = start_page(ptr);
         let range = PageRange {
             start,
             end: start + (pages as u64),
@@ -115,6 +146,95 @@ unsafe impl AllocRef for KernelHeapPages {
             },
         );
     }
+
+    unsafe fn grow_in_place(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        let (_, pages) = layout_to_page_layout(layout).unwrap();
+        let new_pages = resized_pages(layout, new_size)?;
+        let page_size = Size4KiB::SIZE as usize;
+
+        if new_pages <= pages {
+            return Ok(pages * page_size);
+        }
+
+        let start = start_page(ptr);
+        let extension = PageRange {
+            start: start + (pages as u64),
+            end: start + (new_pages as u64),
+        };
+
+        // Map the pages following the allocation, if none of them is in use.
+        // Mapping fails without leaving any of them mapped if frames run out.
+        ManagedPageTable::modify_global(
+            ModificationFlags {
+                kernel_heap: true,
+                ..Default::default()
+            },
+            move |manager| {
+                if extension.end > kernel_heap_range().end
+                    || !extension.clone().all(|page| manager.is_free_page(page))
+                {
+                    return Err(CannotReallocInPlace);
+                }
+
+                manager
+                    .map_blank_pages(
+                        extension.start,
+                        new_pages - pages,
+                        PageTableFlags::PRESENT
+                            | PageTableFlags::WRITABLE
+                            | PageTableFlags::NO_EXECUTE,
+                        true,
+                        PageUsage::KernelHeap,
+                    )
+                    .map_err(|_| CannotReallocInPlace)
+            },
+        )?;
+
+        trace!(
+            "KernelHeapPages grew {} pages at {:?} to {}",
+            pages,
+            ptr,
+            new_pages
+        );
+
+        Ok(new_pages * page_size)
+    }
+
+    unsafe fn shrink_in_place(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        let (_, pages) = layout_to_page_layout(layout).unwrap();
+        let new_pages = resized_pages(layout, new_size)?;
+        let page_size = Size4KiB::SIZE as usize;
+
+        if new_pages < pages {
+            let start = start_page(ptr);
+            let range = PageRange {
+                start: start + (new_pages as u64),
+                end: start + (pages as u64),
+            };
+
+            ManagedPageTable::modify_global(
+                ModificationFlags {
+                    kernel_heap: true,
+                    ..Default::default()
+                },
+                move |manager| {
+                    manager.unmap_pages_and_release(range, true).unwrap();
+                },
+            );
+        }
+
+        Ok(new_pages * page_size)
+    }
 }
 
 impl Allocator for KernelHeapPages {
@@ -128,4 +248,35 @@ impl Allocator for KernelHeapPages {
     unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
         AllocRef::dealloc(self, ptr, layout)
     }
+
+    unsafe fn grow_in_place(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        AllocRef::grow_in_place(self, ptr, layout, new_size)
+    }
+
+    unsafe fn shrink_in_place(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        AllocRef::shrink_in_place(self, ptr, layout, new_size)
+    }
+}
+
+/// Pages are unmapped as soon as they are freed, so there is nothing to reclaim
+impl Reclaim for KernelHeapPages {
+    fn reclaim(&mut self) -> usize {
+        0
+    }
+}
+
+impl SharedReclaim for KernelHeapPages {
+    fn reclaim(&self) -> usize {
+        0
+    }
 }
@@ -1,2 +1,4 @@
+pub mod arena;
+pub mod buddy;
 pub mod fixed_bitmap;
 pub mod kernel_heap_pages;
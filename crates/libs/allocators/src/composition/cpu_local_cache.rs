@@ -0,0 +1,231 @@
+use crate::{
+    allocators::fixed_bitmap::block_alignment,
+    composition::locked_global_alloc::LockedGlobalAlloc,
+    traits::{Allocator, Reclaim, SharedReclaim},
+};
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    ptr::{copy_nonoverlapping, null_mut, NonNull},
+};
+#[cfg(not(target_os = "none"))]
+use cpu_local_storage::data::AllocationCache;
+use cpu_local_storage::data::{
+    Magazine, ALLOCATION_CACHE_CLASSES, MAGAZINE_CAPACITY,
+};
+#[cfg(not(target_os = "none"))]
+use kernel_spin::KernelMutex;
+#[cfg(target_os = "none")]
+use x86_64::instructions::interrupts::without_interrupts;
+
+/// Objects moved between a magazine and the allocator at once
+const TRANSFER_SIZE: usize = MAGAZINE_CAPACITY / 2;
+
+/// A composing allocator that caches free objects per core
+///
+/// Each size class has a magazine in the CpuLocalData of every core.
+/// Allocations and frees are served from the magazine of the current core,
+/// the lock of the inner allocator is only taken to refill or drain a magazine.
+///
+/// Size classes have to match the buckets of A:
+/// all sizes up to a class have to be served by the same bucket,
+/// with blocks aligned to the block_alignment of the class.
+/// Requests larger than the last class are passed through.
+///
+/// When not built for the kernel, there is no CpuLocalData,
+/// so the magazines are kept in the allocator as if there was a single core.
+pub struct CpuLocalCache<A> {
+    inner: LockedGlobalAlloc<A>,
+    size_classes: &'static [usize],
+    #[cfg(not(target_os = "none"))]
+    magazines: KernelMutex<AllocationCache>,
+}
+
+impl<A> CpuLocalCache<A> {
+    /// Cache objects of the size classes, in ascending order
+    pub const fn new(
+        inner: LockedGlobalAlloc<A>,
+        size_classes: &'static [usize],
+    ) -> Self {
+        CpuLocalCache {
+            inner,
+            size_classes,
+            #[cfg(not(target_os = "none"))]
+            magazines: KernelMutex::new(AllocationCache::new()),
+        }
+    }
+
+    pub fn inner(&self) -> &LockedGlobalAlloc<A> {
+        &self.inner
+    }
+
+    /// The class serving layout, objects of a class are aligned like the blocks of its bucket
+    fn size_class(&self, layout: Layout) -> Option<usize> {
+        let class = self
+            .size_classes
+            .iter()
+            .take(ALLOCATION_CACHE_CLASSES)
+            .position(|size| layout.size() <= *size)?;
+
+        if layout.align() <= block_alignment(self.size_classes[class]) {
+            Some(class)
+        } else {
+            None
+        }
+    }
+
+    /// The layout cached objects of class are allocated with
+    fn class_layout(&self, class: usize) -> Layout {
+        let size = self.size_classes[class];
+        Layout::from_size_align(size, block_alignment(size)).unwrap()
+    }
+
+    #[cfg(target_os = "none")]
+    fn with_magazine<F, R>(&self, class: usize, function: F) -> R
+    where
+        F: FnOnce(&mut Magazine) -> R,
+    {
+        // An interrupt handler allocating on this core must not see the magazine mid-update
+        without_interrupts(|| {
+            // The heap lock reads the core id while the magazine is borrowed
+            let magazine = cpu_local_storage::write(|data| {
+                &mut data.allocation_cache.magazines[class] as *mut Magazine
+            });
+
+            function(unsafe { &mut *magazine })
+        })
+    }
+
+    #[cfg(not(target_os = "none"))]
+    fn with_magazine<F, R>(&self, class: usize, function: F) -> R
+    where
+        F: FnOnce(&mut Magazine) -> R,
+    {
+        self.magazines
+            .lock(|cache| function(&mut cache.magazines[class]))
+    }
+}
+
+impl<A> CpuLocalCache<A>
+where
+    A: Allocator,
+{
+    fn refill(&self, magazine: &mut Magazine, layout: Layout) {
+        self.inner.lock(|allocator| {
+            for _ in 0..TRANSFER_SIZE {
+                match allocator.alloc(layout) {
+                    Ok((object, _)) => magazine.push(object).unwrap(),
+                    Err(_) => break,
+                }
+            }
+        })
+    }
+
+    fn drain(&self, magazine: &mut Magazine, layout: Layout) {
+        self.inner.lock(|allocator| {
+            for _ in 0..TRANSFER_SIZE {
+                match magazine.pop() {
+                    Some(object) => unsafe {
+                        allocator.dealloc(object, layout)
+                    },
+                    None => break,
+                }
+            }
+        })
+    }
+}
+
+impl<A> SharedReclaim for CpuLocalCache<A>
+where
+    A: Allocator + Reclaim,
+{
+    /// Empty the magazines of the current core, then reclaim the inner allocator
+    ///
+    /// The magazines of other cores can't be touched from here, they keep their objects.
+    fn reclaim(&self) -> usize {
+        let mut released = 0;
+
+        for class in 0..self.size_classes.len().min(ALLOCATION_CACHE_CLASSES) {
+            let class_layout = self.class_layout(class);
+
+            self.with_magazine(class, |magazine| {
+                self.inner.lock(|allocator| {
+                    while let Some(object) = magazine.pop() {
+                        unsafe { allocator.dealloc(object, class_layout) };
+                        released += class_layout.size();
+                    }
+                })
+            });
+        }
+
+        released + self.inner.reclaim()
+    }
+}
+
+unsafe impl<A> GlobalAlloc for CpuLocalCache<A>
+where
+    A: Allocator,
+{
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let class = match self.size_class(layout) {
+            Some(class) => class,
+            None => return self.inner.alloc(layout),
+        };
+        let class_layout = self.class_layout(class);
+
+        self.with_magazine(class, |magazine| {
+            if magazine.is_empty() {
+                self.refill(magazine, class_layout);
+            }
+
+            magazine.pop().map_or(null_mut(), |object| object.as_ptr())
+        })
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let object = match NonNull::new(ptr) {
+            Some(object) => object,
+            None => return,
+        };
+        let class = match self.size_class(layout) {
+            Some(class) => class,
+            None => return self.inner.dealloc(ptr, layout),
+        };
+        let class_layout = self.class_layout(class);
+
+        self.with_magazine(class, |magazine| {
+            if magazine.is_full() {
+                self.drain(magazine, class_layout);
+            }
+
+            magazine.push(object).unwrap();
+        })
+    }
+
+    unsafe fn realloc(
+        &self,
+        ptr: *mut u8,
+        layout: Layout,
+        new_size: usize,
+    ) -> *mut u8 {
+        let new_layout =
+            Layout::from_size_align_unchecked(new_size, layout.align());
+
+        match (self.size_class(layout), self.size_class(new_layout)) {
+            // Cached objects always have the size of their class
+            (Some(class), Some(new_class)) if class == new_class => ptr,
+            (None, None) => self.inner.realloc(ptr, layout, new_size),
+            _ => {
+                let new_ptr = self.alloc(new_layout);
+                if !new_ptr.is_null() {
+                    copy_nonoverlapping(
+                        ptr,
+                        new_ptr,
+                        layout.size().min(new_size),
+                    );
+                    self.dealloc(ptr, layout);
+                }
+                new_ptr
+            },
+        }
+    }
+}
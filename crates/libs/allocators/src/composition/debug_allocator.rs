@@ -0,0 +1,388 @@
+use crate::{
+    traits::{Allocator, OwnerCheck, SharedReclaim},
+    utils::backtrace::Backtrace,
+};
+use core::{
+    alloc::{AllocErr, GlobalAlloc, Layout},
+    mem::{align_of, replace, size_of},
+    ptr::{null_mut, write_bytes, NonNull},
+    slice,
+};
+use kernel_spin::KernelMutex;
+
+/// Bytes of canaries after the header and after each allocation
+pub const REDZONE_SIZE: usize = 16;
+
+/// Freed allocations are only returned to the inner allocator after this many further frees
+pub const QUARANTINE_SIZE: usize = 64;
+
+/// Fills the redzones
+pub const CANARY: u8 = 0xFD;
+/// Fills new allocations
+pub const UNINITIALIZED: u8 = 0xCD;
+/// Fills freed allocations
+pub const POISON: u8 = 0xDD;
+
+const STATE_LIVE: u64 = 0x4C49_5645_A110_CA7E;
+const STATE_FREED: u64 = 0x4652_4545_DEAD_F4EE;
+
+/// Precedes every allocation, followed by the front redzone
+#[repr(C)]
+struct Header {
+    state: u64,
+    layout: Layout,
+    caller: Backtrace,
+    previous: Option<NonNull<Header>>,
+    next: Option<NonNull<Header>>,
+}
+
+/// The layout of the block holding an allocation of layout
+/// and the offset of the allocation in it
+fn block_layout(layout: Layout) -> Option<(Layout, usize)> {
+    let align = layout.align().max(align_of::<Header>());
+    let offset =
+        (size_of::<Header>() + REDZONE_SIZE + align - 1) / align * align;
+    let size = offset
+        .checked_add(layout.size())?
+        .checked_add(REDZONE_SIZE)?;
+
+    Layout::from_size_align(size, align)
+        .ok()
+        .map(|block| (block, offset))
+}
+
+unsafe fn is_filled(start: *const u8, length: usize, value: u8) -> bool {
+    slice::from_raw_parts(start, length)
+        .iter()
+        .all(|byte| *byte == value)
+}
+
+impl Header {
+    fn block_layout(&self) -> (Layout, usize) {
+        block_layout(self.layout).unwrap()
+    }
+
+    fn block(&self) -> *mut u8 {
+        self as *const Header as *mut u8
+    }
+
+    fn allocation(&self) -> *mut u8 {
+        unsafe { self.block().add(self.block_layout().1) }
+    }
+
+    fn corruption(&self, kind: &str) -> ! {
+        panic!(
+            "Heap corruption: {} of allocation of {} bytes at {:p}, allocated by {}",
+            kind,
+            self.layout.size(),
+            self.allocation(),
+            self.caller
+        );
+    }
+
+    /// Panic if a canary was overwritten
+    unsafe fn verify_redzones(&self) {
+        let front = self.block().add(size_of::<Header>());
+        let front_size = self.allocation() as usize - front as usize;
+
+        if !is_filled(front, front_size, CANARY) {
+            self.corruption("underflow");
+        }
+        if !is_filled(
+            self.allocation().add(self.layout.size()),
+            REDZONE_SIZE,
+            CANARY,
+        ) {
+            self.corruption("overflow");
+        }
+    }
+
+    /// Panic if a freed allocation was written to
+    unsafe fn verify_poison(&self) {
+        if self.state != STATE_FREED
+            || !is_filled(self.allocation(), self.layout.size(), POISON)
+        {
+            self.corruption("write after free");
+        }
+        self.verify_redzones();
+    }
+}
+
+struct DebugState {
+    live: Option<NonNull<Header>>,
+    quarantine: [Option<NonNull<Header>>; QUARANTINE_SIZE],
+    next_quarantined: usize,
+}
+
+unsafe impl Send for DebugState {}
+
+impl DebugState {
+    /// Fill a new block and track it
+    unsafe fn insert(
+        &mut self,
+        block: NonNull<u8>,
+        layout: Layout,
+        caller: Backtrace,
+    ) -> NonNull<u8> {
+        let header = block.cast::<Header>();
+        header.as_ptr().write(Header {
+            state: STATE_LIVE,
+            layout,
+            caller,
+            previous: None,
+            next: self.live,
+        });
+        if let Some(mut next) = self.live {
+            next.as_mut().previous = Some(header);
+        }
+        self.live = Some(header);
+
+        let header = header.as_ref();
+        let front = block.as_ptr().add(size_of::<Header>());
+        let allocation = header.allocation();
+
+        write_bytes(front, CANARY, allocation as usize - front as usize);
+        write_bytes(allocation, UNINITIALIZED, layout.size());
+        write_bytes(allocation.add(layout.size()), CANARY, REDZONE_SIZE);
+
+        NonNull::new_unchecked(allocation)
+    }
+
+    /// Poison an allocation and put it into quarantine
+    ///
+    /// Returns the block that left the quarantine, it can be given back to the inner allocator.
+    unsafe fn remove(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+    ) -> Option<(NonNull<u8>, Layout)> {
+        let (_, offset) = block_layout(layout)?;
+        let mut header =
+            NonNull::new_unchecked(ptr.as_ptr().sub(offset)).cast::<Header>();
+        let header_ref = header.as_mut();
+
+        match header_ref.state {
+            STATE_LIVE => {},
+            STATE_FREED => panic!(
+                "Double free of allocation of {} bytes at {:p}, allocated by {}",
+                layout.size(),
+                ptr,
+                header_ref.caller
+            ),
+            _ => panic!(
+                "Free of {:p} with {} bytes, which was not allocated or has a corrupted header",
+                ptr,
+                layout.size()
+            ),
+        }
+
+        if header_ref.layout != layout {
+            panic!(
+                "Allocation of {} bytes at {:p} freed with {:?}, allocated by {}",
+                header_ref.layout.size(),
+                ptr,
+                layout,
+                header_ref.caller
+            );
+        }
+
+        header_ref.verify_redzones();
+
+        match header_ref.previous {
+            Some(mut previous) => previous.as_mut().next = header_ref.next,
+            None => self.live = header_ref.next,
+        }
+        if let Some(mut next) = header_ref.next {
+            next.as_mut().previous = header_ref.previous;
+        }
+
+        header_ref.state = STATE_FREED;
+        header_ref.previous = None;
+        header_ref.next = None;
+        write_bytes(ptr.as_ptr(), POISON, layout.size());
+
+        let evicted =
+            replace(&mut self.quarantine[self.next_quarantined], Some(header));
+        self.next_quarantined = (self.next_quarantined + 1) % QUARANTINE_SIZE;
+
+        evicted.map(|evicted| Self::release(evicted))
+    }
+
+    /// Take the oldest allocation out of the quarantine, before its turn
+    unsafe fn evict(&mut self) -> Option<(NonNull<u8>, Layout)> {
+        let next = self.next_quarantined;
+        let quarantine = &mut self.quarantine;
+        let oldest = (0..QUARANTINE_SIZE)
+            .map(|offset| (next + offset) % QUARANTINE_SIZE)
+            .find_map(|index| quarantine[index].take())?;
+
+        Some(Self::release(oldest))
+    }
+
+    /// Verify a block that leaves the quarantine
+    unsafe fn release(header: NonNull<Header>) -> (NonNull<u8>, Layout) {
+        let header = header.as_ref();
+        header.verify_poison();
+        (
+            NonNull::new_unchecked(header.block()),
+            header.block_layout().0,
+        )
+    }
+
+    unsafe fn check(&self) {
+        let mut chain = self.live;
+        while let Some(header) = chain {
+            let header = header.as_ref();
+            if header.state != STATE_LIVE {
+                header.corruption("header overwrite");
+            }
+            header.verify_redzones();
+            chain = header.next;
+        }
+
+        for header in self.quarantine.iter().flatten() {
+            header.as_ref().verify_poison();
+        }
+    }
+}
+
+/// A composing allocator that detects heap corruption
+///
+/// Each allocation is surrounded by canary redzones and filled with a pattern.
+/// Freed allocations are poisoned and quarantined, so writes after free and double frees
+/// are noticed as long as they are in quarantine.
+/// Redzones are verified on free and by check.
+///
+/// Corruption panics with the size of the allocation and the backtrace of its allocation.
+/// Reallocations always move, so stale pointers to the old allocation hit the poison.
+pub struct DebugAllocator<A> {
+    inner: A,
+    state: KernelMutex<DebugState>,
+}
+
+impl<A> DebugAllocator<A> {
+    pub const fn new(inner: A) -> Self {
+        DebugAllocator {
+            inner,
+            state: KernelMutex::new(DebugState {
+                live: None,
+                quarantine: [None; QUARANTINE_SIZE],
+                next_quarantined: 0,
+            }),
+        }
+    }
+
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// Verify the redzones of all live and the poison of all quarantined allocations
+    pub fn check(&self) {
+        self.state.lock(|state| unsafe { state.check() })
+    }
+}
+
+impl<A> Default for DebugAllocator<A>
+where
+    A: Default,
+{
+    fn default() -> Self {
+        Self::new(Default::default())
+    }
+}
+
+impl<A> Allocator for DebugAllocator<A>
+where
+    A: Allocator,
+{
+    fn alloc(
+        &mut self,
+        layout: Layout,
+    ) -> Result<(NonNull<u8>, usize), AllocErr> {
+        let caller = Backtrace::capture();
+        let (block_layout, _) = block_layout(layout).ok_or(AllocErr)?;
+        let (block, _) = self.inner.alloc(block_layout)?;
+
+        let ptr = self
+            .state
+            .lock(|state| unsafe { state.insert(block, layout, caller) });
+        Ok((ptr, layout.size()))
+    }
+
+    unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        if let Some((block, block_layout)) =
+            self.state.lock(|state| state.remove(ptr, layout))
+        {
+            self.inner.dealloc(block, block_layout);
+        }
+    }
+}
+
+unsafe impl<A> GlobalAlloc for DebugAllocator<A>
+where
+    A: GlobalAlloc,
+{
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let caller = Backtrace::capture();
+        let block = block_layout(layout).and_then(|(block_layout, _)| {
+            NonNull::new(self.inner.alloc(block_layout))
+        });
+
+        match block {
+            Some(block) => self
+                .state
+                .lock(|state| state.insert(block, layout, caller))
+                .as_ptr(),
+            None => null_mut(),
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if let Some(ptr) = NonNull::new(ptr) {
+            if let Some((block, block_layout)) =
+                self.state.lock(|state| state.remove(ptr, layout))
+            {
+                self.inner.dealloc(block.as_ptr(), block_layout);
+            }
+        }
+    }
+}
+
+impl<A> SharedReclaim for DebugAllocator<A>
+where
+    A: GlobalAlloc + SharedReclaim,
+{
+    /// Flush the quarantine, then reclaim the inner allocator
+    ///
+    /// Writes after free to the flushed allocations are no longer detected.
+    fn reclaim(&self) -> usize {
+        let mut released = 0;
+
+        while let Some((block, block_layout)) =
+            self.state.lock(|state| unsafe { state.evict() })
+        {
+            unsafe {
+                self.inner.dealloc(block.as_ptr(), block_layout);
+            }
+            released += block_layout.size();
+        }
+
+        released + self.inner.reclaim()
+    }
+}
+
+impl<A> OwnerCheck for DebugAllocator<A>
+where
+    A: OwnerCheck,
+{
+    fn is_owner(&self, ptr: NonNull<u8>, layout: Layout) -> bool {
+        match block_layout(layout) {
+            Some((block_layout, offset)) => {
+                let block =
+                    unsafe { NonNull::new_unchecked(ptr.as_ptr().sub(offset)) };
+                self.inner.is_owner(block, block_layout)
+            },
+            None => false,
+        }
+    }
+}
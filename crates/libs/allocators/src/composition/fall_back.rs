@@ -1,6 +1,6 @@
-use crate::traits::{Allocator, OwnerCheck};
+use crate::traits::{Allocator, OwnerCheck, Reclaim};
 use core::{
-    alloc::{AllocErr, Layout},
+    alloc::{AllocErr, CannotReallocInPlace, Layout},
     ptr::NonNull,
 };
 
@@ -38,6 +38,32 @@ where
             self.fallback.dealloc(ptr, layout)
         }
     }
+
+    unsafe fn grow_in_place(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        if self.primary.is_owner(ptr, layout) {
+            self.primary.grow_in_place(ptr, layout, new_size)
+        } else {
+            self.fallback.grow_in_place(ptr, layout, new_size)
+        }
+    }
+
+    unsafe fn shrink_in_place(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        if self.primary.is_owner(ptr, layout) {
+            self.primary.shrink_in_place(ptr, layout, new_size)
+        } else {
+            self.fallback.shrink_in_place(ptr, layout, new_size)
+        }
+    }
 }
 
 impl<P, F> OwnerCheck for FallBackAllocator<P, F>
@@ -50,3 +76,13 @@ where
             || self.fallback.is_owner(ptr, layout)
     }
 }
+
+impl<P, F> Reclaim for FallBackAllocator<P, F>
+where
+    P: Reclaim,
+    F: Reclaim,
+{
+    fn reclaim(&mut self) -> usize {
+        self.primary.reclaim() + self.fallback.reclaim()
+    }
+}
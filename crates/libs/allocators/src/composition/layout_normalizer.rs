@@ -15,6 +15,10 @@ impl<A> LayoutNormalizer<A> {
         LayoutNormalizer { inner }
     }
 
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
     pub fn into_inner(self) -> A {
         self.inner
     }
@@ -37,6 +41,12 @@ impl<A> LayoutNormalizer<A> {
             layout
         }
     }
+
+    /// The normalized size of an allocation of layout resized to new_size
+    fn normalize_size(layout: Layout, new_size: usize) -> usize {
+        Layout::from_size_align(new_size, layout.align())
+            .map_or(new_size, |layout| Self::normalize(layout).size())
+    }
 }
 
 impl<A> Allocator for LayoutNormalizer<A>
@@ -53,6 +63,45 @@ where
     unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
         self.inner.dealloc(ptr, Self::normalize(layout))
     }
+
+    unsafe fn grow_in_place(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        self.inner.grow_in_place(
+            ptr,
+            Self::normalize(layout),
+            Self::normalize_size(layout, new_size),
+        )
+    }
+
+    unsafe fn shrink_in_place(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        self.inner.shrink_in_place(
+            ptr,
+            Self::normalize(layout),
+            Self::normalize_size(layout, new_size),
+        )
+    }
+
+    unsafe fn realloc(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<(NonNull<u8>, usize), AllocErr> {
+        self.inner.realloc(
+            ptr,
+            Self::normalize(layout),
+            Self::normalize_size(layout, new_size),
+        )
+    }
 }
 
 unsafe impl<A> AllocRef for LayoutNormalizer<A>
@@ -83,7 +132,11 @@ where
         layout: Layout,
         new_size: usize,
     ) -> Result<(NonNull<u8>, usize), AllocErr> {
-        self.inner.realloc(ptr, Self::normalize(layout), new_size)
+        self.inner.realloc(
+            ptr,
+            Self::normalize(layout),
+            Self::normalize_size(layout, new_size),
+        )
     }
 
     unsafe fn realloc_zeroed(
@@ -92,8 +145,11 @@ where
         layout: Layout,
         new_size: usize,
     ) -> Result<(NonNull<u8>, usize), AllocErr> {
-        self.inner
-            .realloc_zeroed(ptr, Self::normalize(layout), new_size)
+        self.inner.realloc_zeroed(
+            ptr,
+            Self::normalize(layout),
+            Self::normalize_size(layout, new_size),
+        )
     }
 
     unsafe fn grow_in_place(
@@ -102,8 +158,11 @@ where
         layout: Layout,
         new_size: usize,
     ) -> Result<usize, CannotReallocInPlace> {
-        self.inner
-            .grow_in_place(ptr, Self::normalize(layout), new_size)
+        self.inner.grow_in_place(
+            ptr,
+            Self::normalize(layout),
+            Self::normalize_size(layout, new_size),
+        )
     }
 
     unsafe fn grow_in_place_zeroed(
@@ -112,8 +171,11 @@ where
         layout: Layout,
         new_size: usize,
     ) -> Result<usize, CannotReallocInPlace> {
-        self.inner
-            .grow_in_place_zeroed(ptr, Self::normalize(layout), new_size)
+        self.inner.grow_in_place_zeroed(
+            ptr,
+            Self::normalize(layout),
+            Self::normalize_size(layout, new_size),
+        )
     }
 
     unsafe fn shrink_in_place(
@@ -122,8 +184,11 @@ where
         layout: Layout,
         new_size: usize,
     ) -> Result<usize, CannotReallocInPlace> {
-        self.inner
-            .shrink_in_place(ptr, Self::normalize(layout), new_size)
+        self.inner.shrink_in_place(
+            ptr,
+            Self::normalize(layout),
+            Self::normalize_size(layout, new_size),
+        )
     }
 }
 
@@ -149,6 +214,10 @@ where
         layout: Layout,
         new_size: usize,
     ) -> *mut u8 {
-        self.inner.realloc(ptr, Self::normalize(layout), new_size)
+        self.inner.realloc(
+            ptr,
+            Self::normalize(layout),
+            Self::normalize_size(layout, new_size),
+        )
     }
 }
@@ -0,0 +1,340 @@
+use crate::{
+    traits::{Allocator, OwnerCheck, SharedReclaim},
+    utils::backtrace::Backtrace,
+};
+use core::{
+    alloc::{AllocErr, GlobalAlloc, Layout},
+    fmt,
+    ptr::NonNull,
+    time::Duration,
+};
+#[cfg(feature = "leak_tracker")]
+use interrupt_handling::get_pit_duration;
+use kernel_spin::KernelMutex;
+use log::*;
+
+/// Only the leak_tracker feature pulls in the timer,
+/// without it all allocations are recorded at 0
+#[cfg(not(feature = "leak_tracker"))]
+fn get_pit_duration() -> Duration {
+    Duration::default()
+}
+
+/// The number of live allocations that can be tracked at once
+///
+/// Records can't be allocated from the heap they are tracking,
+/// so allocations beyond this are only counted as untracked.
+pub const TRACKED_ALLOCATIONS: usize = 1024;
+
+/// A live allocation
+#[derive(Copy, Clone, Debug)]
+pub struct AllocationRecord {
+    pub address: usize,
+    pub layout: Layout,
+    pub timestamp: Duration,
+    pub caller: Backtrace,
+    /// Allocations are numbered in the order they were made
+    pub sequence: u64,
+}
+
+impl fmt::Display for AllocationRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} bytes at 0x{:X}, allocated at {:?} by {}",
+            self.layout.size(),
+            self.address,
+            self.timestamp,
+            self.caller
+        )
+    }
+}
+
+/// The state of the tracked heap at some point in time
+#[derive(Copy, Clone, PartialEq, Eq, Default, Debug)]
+pub struct Snapshot {
+    sequence: u64,
+    pub live_allocations: usize,
+    pub live_bytes: usize,
+    pub untracked: u64,
+}
+
+/// Allocations made between two snapshots that are still live
+#[derive(Copy, Clone, PartialEq, Eq, Default, Debug)]
+pub struct SnapshotDifference {
+    pub allocations: usize,
+    pub bytes: usize,
+    /// Allocations between the snapshots that could not be tracked
+    pub untracked: u64,
+}
+
+impl SnapshotDifference {
+    /// Everything allocated between the snapshots was freed
+    ///
+    /// This can only be trusted if no allocations were untracked.
+    pub fn is_empty(&self) -> bool {
+        self.allocations == 0
+    }
+}
+
+struct TrackerState {
+    records: [Option<AllocationRecord>; TRACKED_ALLOCATIONS],
+    next_sequence: u64,
+    live_allocations: usize,
+    live_bytes: usize,
+    untracked: u64,
+}
+
+impl TrackerState {
+    fn insert(&mut self, address: usize, layout: Layout, caller: Backtrace) {
+        let sequence = self.next_sequence;
+        self.next_sequence += 1;
+
+        match self.records.iter_mut().find(|record| record.is_none()) {
+            Some(slot) => {
+                *slot = Some(AllocationRecord {
+                    address,
+                    layout,
+                    timestamp: get_pit_duration(),
+                    caller,
+                    sequence,
+                });
+                self.live_allocations += 1;
+                self.live_bytes += layout.size();
+            },
+            None => self.untracked += 1,
+        }
+    }
+
+    fn remove(&mut self, address: usize) {
+        let slot = self.records.iter_mut().find(|record| {
+            record.map_or(false, |record| record.address == address)
+        });
+
+        // Untracked allocations have no record
+        if let Some(slot) = slot {
+            let record = slot.take().unwrap();
+            self.live_allocations -= 1;
+            self.live_bytes -= record.layout.size();
+        }
+    }
+
+    /// Move the record of a resized allocation, it counts as allocated by caller
+    fn resize(
+        &mut self,
+        address: usize,
+        new_address: usize,
+        new_layout: Layout,
+        caller: Backtrace,
+    ) {
+        self.remove(address);
+        self.insert(new_address, new_layout, caller);
+    }
+
+    fn records_between<'this>(
+        &'this self,
+        earlier: &Snapshot,
+        later: &Snapshot,
+    ) -> impl 'this + Iterator<Item = &'this AllocationRecord> {
+        let range = earlier.sequence..later.sequence;
+        self.records
+            .iter()
+            .flatten()
+            .filter(move |record| range.contains(&record.sequence))
+    }
+}
+
+/// A composing allocator that records each live allocation of A
+///
+/// Snapshots of the live allocations can be compared,
+/// to find allocations a subsystem did not free.
+pub struct LeakTracker<A> {
+    inner: A,
+    state: KernelMutex<TrackerState>,
+}
+
+impl<A> LeakTracker<A> {
+    pub const fn new(inner: A) -> Self {
+        LeakTracker {
+            inner,
+            state: KernelMutex::new(TrackerState {
+                records: [None; TRACKED_ALLOCATIONS],
+                next_sequence: 0,
+                live_allocations: 0,
+                live_bytes: 0,
+                untracked: 0,
+            }),
+        }
+    }
+
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        self.state.lock(|state| Snapshot {
+            sequence: state.next_sequence,
+            live_allocations: state.live_allocations,
+            live_bytes: state.live_bytes,
+            untracked: state.untracked,
+        })
+    }
+
+    /// Summarize the allocations made between earlier and later that are still live
+    pub fn difference(
+        &self,
+        earlier: &Snapshot,
+        later: &Snapshot,
+    ) -> SnapshotDifference {
+        self.state.lock(|state| {
+            state.records_between(earlier, later).fold(
+                SnapshotDifference {
+                    untracked: later.untracked - earlier.untracked,
+                    ..Default::default()
+                },
+                |mut difference, record| {
+                    difference.allocations += 1;
+                    difference.bytes += record.layout.size();
+                    difference
+                },
+            )
+        })
+    }
+
+    /// Call function with each allocation made between earlier and later that is still live
+    ///
+    /// The heap is locked while function runs, so it must not allocate.
+    pub fn for_each_leak<F>(
+        &self,
+        earlier: &Snapshot,
+        later: &Snapshot,
+        mut function: F,
+    ) where
+        F: FnMut(&AllocationRecord),
+    {
+        self.state.lock(|state| {
+            for record in state.records_between(earlier, later) {
+                function(record);
+            }
+        })
+    }
+
+    /// Log each allocation made between earlier and later that is still live
+    pub fn report_leaks(&self, earlier: &Snapshot, later: &Snapshot) {
+        self.for_each_leak(earlier, later, |record| {
+            warn!("Leaked allocation: {}", record)
+        });
+    }
+}
+
+impl<A> Default for LeakTracker<A>
+where
+    A: Default,
+{
+    fn default() -> Self {
+        Self::new(Default::default())
+    }
+}
+
+impl<A> Allocator for LeakTracker<A>
+where
+    A: Allocator,
+{
+    fn alloc(
+        &mut self,
+        layout: Layout,
+    ) -> Result<(NonNull<u8>, usize), AllocErr> {
+        let caller = Backtrace::capture();
+        let result = self.inner.alloc(layout)?;
+
+        self.state.lock(|state| {
+            state.insert(result.0.as_ptr() as usize, layout, caller)
+        });
+        Ok(result)
+    }
+
+    unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        self.state.lock(|state| state.remove(ptr.as_ptr() as usize));
+        self.inner.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<(NonNull<u8>, usize), AllocErr> {
+        let caller = Backtrace::capture();
+        let result = self.inner.realloc(ptr, layout, new_size)?;
+
+        let new_layout =
+            Layout::from_size_align_unchecked(new_size, layout.align());
+        self.state.lock(|state| {
+            state.resize(
+                ptr.as_ptr() as usize,
+                result.0.as_ptr() as usize,
+                new_layout,
+                caller,
+            )
+        });
+        Ok(result)
+    }
+}
+
+impl<A> SharedReclaim for LeakTracker<A>
+where
+    A: SharedReclaim,
+{
+    fn reclaim(&self) -> usize {
+        self.inner.reclaim()
+    }
+}
+
+unsafe impl<A> GlobalAlloc for LeakTracker<A>
+where
+    A: GlobalAlloc,
+{
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let caller = Backtrace::capture();
+        let ptr = self.inner.alloc(layout);
+
+        if !ptr.is_null() {
+            self.state
+                .lock(|state| state.insert(ptr as usize, layout, caller));
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.state.lock(|state| state.remove(ptr as usize));
+        self.inner.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(
+        &self,
+        ptr: *mut u8,
+        layout: Layout,
+        new_size: usize,
+    ) -> *mut u8 {
+        let caller = Backtrace::capture();
+        let new_ptr = self.inner.realloc(ptr, layout, new_size);
+
+        if !new_ptr.is_null() {
+            let new_layout =
+                Layout::from_size_align_unchecked(new_size, layout.align());
+            self.state.lock(|state| {
+                state.resize(ptr as usize, new_ptr as usize, new_layout, caller)
+            });
+        }
+        new_ptr
+    }
+}
+
+impl<A> OwnerCheck for LeakTracker<A>
+where
+    A: OwnerCheck,
+{
+    fn is_owner(&self, ptr: NonNull<u8>, layout: Layout) -> bool {
+        self.inner.is_owner(ptr, layout)
+    }
+}
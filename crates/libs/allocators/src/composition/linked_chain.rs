@@ -1,6 +1,6 @@
-use crate::traits::{Allocator, OwnerCheck};
+use crate::traits::{Allocator, Occupancy, OwnerCheck, Reclaim};
 use core::{
-    alloc::{AllocErr, Layout},
+    alloc::{AllocErr, CannotReallocInPlace, Layout},
     any::type_name,
     ptr::{drop_in_place, NonNull},
 };
@@ -8,43 +8,148 @@ use log::*;
 
 struct AllocatorBlock<A> {
     pub allocator: A,
+    pub previous: Option<NonNull<AllocatorBlock<A>>>,
     pub next: Option<NonNull<AllocatorBlock<A>>>,
 }
 
+/// A doubly linked list of blocks
+struct BlockList<A> {
+    head: Option<NonNull<AllocatorBlock<A>>>,
+    length: usize,
+}
+
+impl<A> BlockList<A> {
+    const fn new() -> Self {
+        BlockList {
+            head: None,
+            length: 0,
+        }
+    }
+
+    unsafe fn push(&mut self, mut block: NonNull<AllocatorBlock<A>>) {
+        let block_ref = block.as_mut();
+        block_ref.previous = None;
+        block_ref.next = self.head;
+
+        if let Some(mut head) = self.head {
+            head.as_mut().previous = Some(block);
+        }
+
+        self.head = Some(block);
+        self.length += 1;
+    }
+
+    /// # Safety
+    /// block has to be a member of self
+    unsafe fn remove(&mut self, mut block: NonNull<AllocatorBlock<A>>) {
+        let block_ref = block.as_mut();
+
+        match block_ref.previous {
+            Some(mut previous) => previous.as_mut().next = block_ref.next,
+            None => self.head = block_ref.next,
+        }
+        if let Some(mut next) = block_ref.next {
+            next.as_mut().previous = block_ref.previous;
+        }
+
+        block_ref.previous = None;
+        block_ref.next = None;
+        self.length -= 1;
+    }
+
+    fn pop(&mut self) -> Option<NonNull<AllocatorBlock<A>>> {
+        let head = self.head?;
+        unsafe {
+            self.remove(head);
+        }
+        Some(head)
+    }
+
+    fn find<CB>(&self, mut callback: CB) -> Option<NonNull<AllocatorBlock<A>>>
+    where
+        CB: FnMut(&AllocatorBlock<A>) -> bool,
+    {
+        let mut chain = self.head;
+
+        while let Some(it) = chain {
+            unsafe {
+                let block = it.as_ref();
+                chain = block.next;
+
+                if callback(block) {
+                    return Some(it);
+                }
+            }
+        }
+
+        None
+    }
+
+    fn find_mut<CB>(
+        &mut self,
+        mut callback: CB,
+    ) -> Option<NonNull<AllocatorBlock<A>>>
+    where
+        CB: FnMut(&mut AllocatorBlock<A>) -> bool,
+    {
+        let mut chain = self.head;
+
+        while let Some(mut it) = chain {
+            unsafe {
+                let block = it.as_mut();
+                chain = block.next;
+
+                if callback(block) {
+                    return Some(it);
+                }
+            }
+        }
+
+        None
+    }
+}
+
 /// A composing allocator
 ///
-/// It maintains a linked list of allocators of type A, created from allocator B
+/// It maintains lists of allocators of type A, created from allocator B
+///
+/// Blocks are kept in a partial, a full and an empty list.
+/// Requests are served by the first partial block, so allocations don't have to walk full blocks.
+/// If there is none, a cached empty block is reused or a new A is created with memory
+/// allocated from B.
 ///
-/// When a request is made, it walks its list of allocators until one can fulfill the request.
-/// If no allocator can, a new A is created with memory allocated from B
-pub struct LinkedChain<A, B>
+/// Blocks that become empty are cached, up to CACHED_EMPTY_BLOCKS.
+/// Any further empty blocks are returned to B, so the chain shrinks after bursts.
+pub struct LinkedChain<A, B, const CACHED_EMPTY_BLOCKS: usize>
 where
     B: Allocator,
 {
-    head: Option<NonNull<AllocatorBlock<A>>>,
+    partial: BlockList<A>,
+    full: BlockList<A>,
+    empty: BlockList<A>,
     backing: B,
 }
 
-unsafe impl<A, B> Send for LinkedChain<A, B>
+unsafe impl<A, B, const CACHED_EMPTY_BLOCKS: usize> Send
+    for LinkedChain<A, B, { CACHED_EMPTY_BLOCKS }>
 where
     A: Send,
     B: Send + Allocator,
 {
 }
 
-impl<A, B> Default for LinkedChain<A, B>
+impl<A, B, const CACHED_EMPTY_BLOCKS: usize> Default
+    for LinkedChain<A, B, { CACHED_EMPTY_BLOCKS }>
 where
     B: Allocator + Default,
 {
     fn default() -> Self {
-        LinkedChain {
-            head: None,
-            backing: Default::default(),
-        }
+        Self::new(Default::default())
     }
 }
 
-impl<A, B> LinkedChain<A, B>
+impl<A, B, const CACHED_EMPTY_BLOCKS: usize>
+    LinkedChain<A, B, { CACHED_EMPTY_BLOCKS }>
 where
     B: Allocator,
 {
@@ -57,55 +162,31 @@ where
 
     pub const fn new(backing: B) -> Self {
         LinkedChain {
-            head: None,
+            partial: BlockList::new(),
+            full: BlockList::new(),
+            empty: BlockList::new(),
             backing,
         }
     }
 
-    fn walk_chain_mut<CB, R>(&mut self, mut callback: CB) -> Option<R>
-    where
-        CB: FnMut(&mut AllocatorBlock<A>) -> Option<R>,
-    {
-        let mut chain = self.head;
-
-        while let Some(mut it) = chain {
-            unsafe {
-                let it = it.as_mut();
-                chain = it.next;
-
-                if let Some(result) = callback(it) {
-                    return Some(result);
-                }
-            }
-        }
-
-        None
+    /// The number of blocks that are currently allocated from the backing allocator
+    pub fn blocks(&self) -> usize {
+        self.partial.length + self.full.length + self.empty.length
     }
 
-    fn walk_chain<CB, R>(&self, mut callback: CB) -> Option<R>
-    where
-        CB: FnMut(&AllocatorBlock<A>) -> Option<R>,
-    {
-        let mut chain = self.head;
+    /// Drop a block and give its memory back to the backing allocator
+    unsafe fn release_block(&mut self, block: NonNull<AllocatorBlock<A>>) {
+        trace!("{}: Releasing block", type_name::<Self>());
 
-        while let Some(it) = chain {
-            unsafe {
-                let it = it.as_ref();
-                chain = it.next;
-
-                if let Some(result) = callback(it) {
-                    return Some(result);
-                }
-            }
-        }
-
-        None
+        drop_in_place(block.as_ptr());
+        self.backing.dealloc(block.cast(), Self::block_layout());
     }
 }
 
-impl<A, B> LinkedChain<A, B>
+impl<A, B, const CACHED_EMPTY_BLOCKS: usize>
+    LinkedChain<A, B, { CACHED_EMPTY_BLOCKS }>
 where
-    A: Allocator + OwnerCheck + Default,
+    A: Allocator + OwnerCheck + Occupancy + Default,
     B: Allocator,
 {
     fn allocate_new_block(
@@ -116,100 +197,202 @@ where
         // Allocate a new block from the backing store
         let memory = self.backing.alloc(Self::block_layout())?.0;
 
-        // Write the old head to the new block
         let memory = memory.cast::<AllocatorBlock<A>>();
         unsafe {
             memory.as_ptr().write(AllocatorBlock {
                 allocator: A::default(),
-                next: self.head.take(),
+                previous: None,
+                next: None,
             });
         }
 
-        // Make the new block into the head
-        self.head = Some(memory);
-
         Ok(memory)
     }
+
+    /// The partial or full block that owns the allocation at ptr
+    fn owner(
+        &self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+    ) -> Option<NonNull<AllocatorBlock<A>>> {
+        let owner =
+            |block: &AllocatorBlock<A>| block.allocator.is_owner(ptr, layout);
+
+        self.partial.find(owner).or_else(|| self.full.find(owner))
+    }
+
+    /// Put a block that is not in any list into the list matching its state
+    unsafe fn file_block(&mut self, block: NonNull<AllocatorBlock<A>>) {
+        let allocator = &block.as_ref().allocator;
+
+        if allocator.is_full() {
+            self.full.push(block);
+        } else if !allocator.is_unused() {
+            self.partial.push(block);
+        } else if self.empty.length < CACHED_EMPTY_BLOCKS {
+            self.empty.push(block);
+        } else {
+            self.release_block(block);
+        }
+    }
 }
 
-impl<A, B> Drop for LinkedChain<A, B>
+impl<A, B, const CACHED_EMPTY_BLOCKS: usize> Drop
+    for LinkedChain<A, B, { CACHED_EMPTY_BLOCKS }>
 where
     B: Allocator,
 {
     fn drop(&mut self) {
-        let mut chain = self.head.take();
-
-        while let Some(mut it) = chain {
-            // Save the next pointer
-            unsafe {
-                chain = it.as_mut().next.take();
-            }
-
-            // Drop the value at the location
-            unsafe {
-                drop_in_place(it.as_ptr());
-            }
-
-            // Deallocate the block
+        while let Some(block) = self
+            .partial
+            .pop()
+            .or_else(|| self.full.pop())
+            .or_else(|| self.empty.pop())
+        {
             unsafe {
-                self.backing.dealloc(it.cast(), Self::block_layout());
+                self.release_block(block);
             }
         }
     }
 }
 
-impl<A, B> Allocator for LinkedChain<A, B>
+impl<A, B, const CACHED_EMPTY_BLOCKS: usize> Allocator
+    for LinkedChain<A, B, { CACHED_EMPTY_BLOCKS }>
 where
-    A: Allocator + OwnerCheck + Default,
+    A: Allocator + OwnerCheck + Occupancy + Default,
     B: Allocator,
 {
     fn alloc(
         &mut self,
         layout: Layout,
     ) -> Result<(NonNull<u8>, usize), AllocErr> {
-        // Walk the chain and try to find an allocator that can allocate something
-        if let Some(allocation) =
-            self.walk_chain_mut(|block| block.allocator.alloc(layout).ok())
-        {
+        // For uniform blocks, the first partial block always succeeds
+        let mut allocation = None;
+        let block = self.partial.find_mut(|block| {
+            allocation = block.allocator.alloc(layout).ok();
+            allocation.is_some()
+        });
+
+        if let (Some(block), Some(allocation)) = (block, allocation) {
+            unsafe {
+                if block.as_ref().allocator.is_full() {
+                    self.partial.remove(block);
+                    self.full.push(block);
+                }
+            }
             return Ok(allocation);
         }
 
-        // If no existing allocator could fulfill the request, allocate a new one
-        let mut new_guy = self.allocate_new_block()?;
+        // If no partial block could fulfill the request, use an empty one
+        let mut block = match self.empty.pop() {
+            Some(block) => block,
+            None => self.allocate_new_block()?,
+        };
 
-        // And let the new guy try it
-        unsafe { new_guy.as_mut().allocator.alloc(layout) }
+        unsafe {
+            let result = block.as_mut().allocator.alloc(layout);
+            self.file_block(block);
+            result
+        }
     }
 
     unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
-        let result = self.walk_chain_mut(|block| {
-            if block.allocator.is_owner(ptr, layout) {
-                block.allocator.dealloc(ptr, layout);
-                Some(())
-            } else {
-                None
-            }
-        });
+        let owner =
+            |block: &AllocatorBlock<A>| block.allocator.is_owner(ptr, layout);
 
-        // If this is properly used, only an owned value should be passed to self
-        // and one of our allocators should have recognized it
-        debug_assert!(result.is_some());
+        let mut block = match self.partial.find(owner) {
+            Some(block) => {
+                self.partial.remove(block);
+                block
+            },
+            None => match self.full.find(owner) {
+                Some(block) => {
+                    self.full.remove(block);
+                    block
+                },
+                None => {
+                    // If this is properly used, only an owned value should be passed to self
+                    // and one of our allocators should have recognized it
+                    debug_assert!(false, "Deallocated pointer is not owned");
+                    return;
+                },
+            },
+        };
+
+        block.as_mut().allocator.dealloc(ptr, layout);
+        self.file_block(block);
+    }
+
+    unsafe fn grow_in_place(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        // Resizing in place doesn't change how many allocations a block holds
+        let mut block = self.owner(ptr, layout).ok_or(CannotReallocInPlace)?;
+        block
+            .as_mut()
+            .allocator
+            .grow_in_place(ptr, layout, new_size)
+    }
+
+    unsafe fn shrink_in_place(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        let mut block = self.owner(ptr, layout).ok_or(CannotReallocInPlace)?;
+        block
+            .as_mut()
+            .allocator
+            .shrink_in_place(ptr, layout, new_size)
     }
 }
 
-impl<A, B> OwnerCheck for LinkedChain<A, B>
+impl<A, B, const CACHED_EMPTY_BLOCKS: usize> OwnerCheck
+    for LinkedChain<A, B, { CACHED_EMPTY_BLOCKS }>
 where
-    A: Allocator + OwnerCheck + Default,
+    A: Allocator + OwnerCheck + Occupancy + Default,
     B: Allocator,
 {
     fn is_owner(&self, ptr: NonNull<u8>, layout: Layout) -> bool {
-        self.walk_chain(|block| {
-            if block.allocator.is_owner(ptr, layout) {
-                Some(())
-            } else {
-                None
+        // Empty blocks don't own any allocations
+        self.owner(ptr, layout).is_some()
+    }
+}
+
+impl<A, B, const CACHED_EMPTY_BLOCKS: usize> Occupancy
+    for LinkedChain<A, B, { CACHED_EMPTY_BLOCKS }>
+where
+    B: Allocator,
+{
+    fn is_unused(&self) -> bool {
+        self.partial.length == 0 && self.full.length == 0
+    }
+
+    fn is_full(&self) -> bool {
+        false
+    }
+}
+
+impl<A, B, const CACHED_EMPTY_BLOCKS: usize> Reclaim
+    for LinkedChain<A, B, { CACHED_EMPTY_BLOCKS }>
+where
+    B: Allocator,
+{
+    /// Release the cached empty blocks
+    fn reclaim(&mut self) -> usize {
+        let mut released = 0;
+
+        while let Some(block) = self.empty.pop() {
+            unsafe {
+                self.release_block(block);
             }
-        })
-        .is_some()
+            released += Self::block_layout().size();
+        }
+
+        released
     }
 }
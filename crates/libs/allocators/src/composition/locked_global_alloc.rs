@@ -1,4 +1,4 @@
-use crate::traits::Allocator;
+use crate::traits::{Allocator, Reclaim, SharedReclaim};
 use core::{
     alloc::{GlobalAlloc, Layout},
     ptr::{null_mut, NonNull},
@@ -20,6 +20,13 @@ impl<A> LockedGlobalAlloc<A> {
     pub fn into_inner(self) -> A {
         self.inner.into_inner()
     }
+
+    pub fn lock<F, R>(&self, function: F) -> R
+    where
+        F: FnOnce(&mut A) -> R,
+    {
+        self.inner.lock(function)
+    }
 }
 
 unsafe impl<A> GlobalAlloc for LockedGlobalAlloc<A>
@@ -40,4 +47,30 @@ where
             self.inner.lock(|allocator| allocator.dealloc(ptr, layout))
         }
     }
+
+    unsafe fn realloc(
+        &self,
+        ptr: *mut u8,
+        layout: Layout,
+        new_size: usize,
+    ) -> *mut u8 {
+        match NonNull::new(ptr) {
+            Some(ptr) => self.inner.lock(|allocator| {
+                allocator
+                    .realloc(ptr, layout, new_size)
+                    .map(|(ptr, _)| ptr.as_ptr())
+                    .unwrap_or(null_mut())
+            }),
+            None => null_mut(),
+        }
+    }
+}
+
+impl<A> SharedReclaim for LockedGlobalAlloc<A>
+where
+    A: Reclaim,
+{
+    fn reclaim(&self) -> usize {
+        self.inner.lock(|allocator| allocator.reclaim())
+    }
 }
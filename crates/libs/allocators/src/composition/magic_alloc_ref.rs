@@ -1,6 +1,6 @@
 use crate::traits::{Allocator, OwnerCheck};
 use core::{
-    alloc::{AllocErr, AllocRef, Layout},
+    alloc::{AllocErr, AllocRef, CannotReallocInPlace, Layout},
     ptr::NonNull,
 };
 use kernel_spin::KernelMutex;
@@ -44,6 +44,33 @@ where
     unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
         self.allocator(|a| a.dealloc(ptr, layout))
     }
+
+    unsafe fn realloc(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<(NonNull<u8>, usize), AllocErr> {
+        self.allocator(|a| a.realloc(ptr, layout, new_size))
+    }
+
+    unsafe fn grow_in_place(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        self.allocator(|a| a.grow_in_place(ptr, layout, new_size))
+    }
+
+    unsafe fn shrink_in_place(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        self.allocator(|a| a.shrink_in_place(ptr, layout, new_size))
+    }
 }
 
 impl<'a, A> OwnerCheck for MagicAllocRef<'a, A>
@@ -1,6 +1,11 @@
+pub mod cpu_local_cache;
+pub mod debug_allocator;
 pub mod fall_back;
 pub mod layout_normalizer;
+pub mod leak_tracker;
 pub mod linked_chain;
 pub mod locked_global_alloc;
 pub mod magic_alloc_ref;
+pub mod reclaiming;
 pub mod size_deciding;
+pub mod statistics;
@@ -0,0 +1,234 @@
+use crate::traits::SharedReclaim;
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    mem::transmute,
+    sync::atomic::{spin_loop_hint, AtomicU64, AtomicUsize, Ordering},
+};
+#[cfg(target_os = "none")]
+use cpu_local_storage::{data::CoreId, get_core_id};
+use log::*;
+
+/// The number of callbacks that can be registered at once
+pub const RECLAIM_CALLBACKS: usize = 16;
+
+/// How often a failed request is retried, as long as memory was reclaimed
+const RECLAIM_ROUNDS: usize = 2;
+
+/// Releases memory a subsystem can do without, returns the number of bytes released
+///
+/// It is called when an allocation fails, so it may free but should not allocate.
+pub type ReclaimCallback = fn() -> usize;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY: AtomicUsize = AtomicUsize::new(0);
+
+/// The registered callbacks, stored as addresses so slots can be claimed without a lock
+static CALLBACKS: [AtomicUsize; RECLAIM_CALLBACKS] = [EMPTY; RECLAIM_CALLBACKS];
+
+/// Register a callback that is run before an allocation fails
+///
+/// Fails if all slots are taken.
+pub fn register_reclaim_callback(callback: ReclaimCallback) -> Result<(), ()> {
+    CALLBACKS
+        .iter()
+        .find(|slot| {
+            slot.compare_exchange(
+                0,
+                callback as usize,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            )
+            .is_ok()
+        })
+        .map(|_| ())
+        .ok_or(())
+}
+
+/// Remove a registered callback
+///
+/// Fails if it was not registered.
+pub fn unregister_reclaim_callback(
+    callback: ReclaimCallback,
+) -> Result<(), ()> {
+    CALLBACKS
+        .iter()
+        .find(|slot| {
+            slot.compare_exchange(
+                callback as usize,
+                0,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            )
+            .is_ok()
+        })
+        .map(|_| ())
+        .ok_or(())
+}
+
+/// Run all registered callbacks, returns the number of bytes they released
+fn run_reclaim_callbacks() -> usize {
+    CALLBACKS
+        .iter()
+        .map(|slot| slot.load(Ordering::Acquire))
+        .filter(|callback| *callback != 0)
+        .map(|callback| {
+            let callback: ReclaimCallback = unsafe { transmute(callback) };
+            callback()
+        })
+        .sum()
+}
+
+/// The id of the current core, never 0
+#[cfg(target_os = "none")]
+fn current_core() -> u64 {
+    CoreId::optional_to_optional_full_id(Some(get_core_id()))
+}
+
+/// The host runs everything as one core
+#[cfg(not(target_os = "none"))]
+fn current_core() -> u64 {
+    1
+}
+
+/// A composing allocator that reclaims memory instead of failing
+///
+/// When A can't serve a request, memory held by A and by the registered callbacks
+/// is released and the request is retried.
+/// Only one reclaim runs at a time. Requests failing on other cores during it
+/// wait for it to finish and retry, requests failing on the reclaiming core
+/// fail right away, so callbacks that allocate can't recurse into it.
+pub struct Reclaiming<A> {
+    inner: A,
+    /// The core running a reclaim, 0 if there is none
+    reclaiming: AtomicU64,
+    /// The number of finished reclaims
+    generation: AtomicUsize,
+}
+
+impl<A> Reclaiming<A> {
+    pub const fn new(inner: A) -> Self {
+        Reclaiming {
+            inner,
+            reclaiming: AtomicU64::new(0),
+            generation: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+}
+
+impl<A> Default for Reclaiming<A>
+where
+    A: Default,
+{
+    fn default() -> Self {
+        Self::new(Default::default())
+    }
+}
+
+impl<A> Reclaiming<A>
+where
+    A: SharedReclaim,
+{
+    /// Reclaim A and run the registered callbacks, unless a reclaim is running
+    fn try_reclaim(&self) -> Option<usize> {
+        self.reclaiming
+            .compare_exchange(
+                0,
+                current_core(),
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+            )
+            .ok()?;
+
+        let released = self.inner.reclaim() + run_reclaim_callbacks();
+
+        self.reclaiming.store(0, Ordering::SeqCst);
+        self.generation.fetch_add(1, Ordering::SeqCst);
+
+        debug!("Reclaimed {} bytes", released);
+
+        Some(released)
+    }
+
+    /// Reclaim before retrying a failed request, returns whether retrying is worth it
+    ///
+    /// If another core is reclaiming, this waits for it to finish,
+    /// as the request may succeed with the memory it released.
+    fn reclaim_for_retry(&self) -> bool {
+        let generation = self.generation.load(Ordering::SeqCst);
+
+        if let Some(released) = self.try_reclaim() {
+            return released != 0;
+        }
+
+        // A callback or an interrupt allocated during the reclaim on this core
+        if self.reclaiming.load(Ordering::SeqCst) == current_core() {
+            return false;
+        }
+
+        while self.generation.load(Ordering::SeqCst) == generation {
+            spin_loop_hint();
+        }
+
+        true
+    }
+
+    /// Run allocate until it succeeds or nothing more can be reclaimed
+    fn retry<F>(&self, mut allocate: F) -> *mut u8
+    where
+        F: FnMut() -> *mut u8,
+    {
+        let mut ptr = allocate();
+
+        for _ in 0..RECLAIM_ROUNDS {
+            if !ptr.is_null() || !self.reclaim_for_retry() {
+                break;
+            }
+            ptr = allocate();
+        }
+
+        ptr
+    }
+}
+
+impl<A> SharedReclaim for Reclaiming<A>
+where
+    A: SharedReclaim,
+{
+    /// Reclaim A, then run the registered callbacks
+    ///
+    /// Returns 0 without waiting if a reclaim is already running.
+    fn reclaim(&self) -> usize {
+        self.try_reclaim().unwrap_or(0)
+    }
+}
+
+unsafe impl<A> GlobalAlloc for Reclaiming<A>
+where
+    A: GlobalAlloc + SharedReclaim,
+{
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.retry(|| self.inner.alloc(layout))
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.inner.dealloc(ptr, layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        self.retry(|| self.inner.alloc_zeroed(layout))
+    }
+
+    unsafe fn realloc(
+        &self,
+        ptr: *mut u8,
+        layout: Layout,
+        new_size: usize,
+    ) -> *mut u8 {
+        // A failed realloc leaves the allocation untouched, so it can be retried
+        self.retry(|| self.inner.realloc(ptr, layout, new_size))
+    }
+}
@@ -1,7 +1,7 @@
-use crate::traits::Allocator;
+use crate::traits::{Allocator, Reclaim, SharedReclaim};
 use core::{
-    alloc::{AllocErr, GlobalAlloc, Layout},
-    ptr::NonNull,
+    alloc::{AllocErr, CannotReallocInPlace, GlobalAlloc, Layout},
+    ptr::{copy_nonoverlapping, NonNull},
 };
 
 pub struct SizeDeciding<S, L, const THRESHOLD: usize> {
@@ -13,6 +13,11 @@ impl<S, L, const THRESHOLD: usize> SizeDeciding<S, L, { THRESHOLD }> {
     pub const fn new(small: S, large: L) -> Self {
         SizeDeciding { small, large }
     }
+
+    /// Both sizes are served by the same allocator
+    fn same_side(size: usize, new_size: usize) -> bool {
+        (size <= THRESHOLD) == (new_size <= THRESHOLD)
+    }
 }
 
 impl<S, L, const THRESHOLD: usize> Default for SizeDeciding<S, L, { THRESHOLD }>
@@ -49,6 +54,64 @@ where
             self.large.dealloc(ptr, layout)
         }
     }
+
+    unsafe fn grow_in_place(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        if !Self::same_side(layout.size(), new_size) {
+            Err(CannotReallocInPlace)
+        } else if layout.size() <= THRESHOLD {
+            self.small.grow_in_place(ptr, layout, new_size)
+        } else {
+            self.large.grow_in_place(ptr, layout, new_size)
+        }
+    }
+
+    unsafe fn shrink_in_place(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        if !Self::same_side(layout.size(), new_size) {
+            Err(CannotReallocInPlace)
+        } else if layout.size() <= THRESHOLD {
+            self.small.shrink_in_place(ptr, layout, new_size)
+        } else {
+            self.large.shrink_in_place(ptr, layout, new_size)
+        }
+    }
+
+    unsafe fn realloc(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<(NonNull<u8>, usize), AllocErr> {
+        if Self::same_side(layout.size(), new_size) {
+            return if layout.size() <= THRESHOLD {
+                self.small.realloc(ptr, layout, new_size)
+            } else {
+                self.large.realloc(ptr, layout, new_size)
+            };
+        }
+
+        // Crossing the threshold moves the allocation to the other allocator
+        let new_layout = Layout::from_size_align(new_size, layout.align())
+            .map_err(|_| AllocErr)?;
+        let (new_ptr, size) = Allocator::alloc(self, new_layout)?;
+        copy_nonoverlapping(
+            ptr.as_ptr(),
+            new_ptr.as_ptr(),
+            layout.size().min(new_size),
+        );
+        Allocator::dealloc(self, ptr, layout);
+
+        Ok((new_ptr, size))
+    }
 }
 
 unsafe impl<S, L, const THRESHOLD: usize> GlobalAlloc
@@ -87,10 +150,44 @@ where
         layout: Layout,
         new_size: usize,
     ) -> *mut u8 {
-        if layout.size() <= THRESHOLD {
-            self.small.realloc(ptr, layout, new_size)
-        } else {
-            self.large.realloc(ptr, layout, new_size)
+        if Self::same_side(layout.size(), new_size) {
+            return if layout.size() <= THRESHOLD {
+                self.small.realloc(ptr, layout, new_size)
+            } else {
+                self.large.realloc(ptr, layout, new_size)
+            };
         }
+
+        // Crossing the threshold moves the allocation to the other allocator
+        let new_layout =
+            Layout::from_size_align_unchecked(new_size, layout.align());
+        let new_ptr = GlobalAlloc::alloc(self, new_layout);
+        if !new_ptr.is_null() {
+            copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size));
+            GlobalAlloc::dealloc(self, ptr, layout);
+        }
+        new_ptr
+    }
+}
+
+impl<S, L, const THRESHOLD: usize> Reclaim for SizeDeciding<S, L, { THRESHOLD }>
+where
+    S: Reclaim,
+    L: Reclaim,
+{
+    fn reclaim(&mut self) -> usize {
+        Reclaim::reclaim(&mut self.small) + Reclaim::reclaim(&mut self.large)
+    }
+}
+
+impl<S, L, const THRESHOLD: usize> SharedReclaim
+    for SizeDeciding<S, L, { THRESHOLD }>
+where
+    S: SharedReclaim,
+    L: SharedReclaim,
+{
+    fn reclaim(&self) -> usize {
+        SharedReclaim::reclaim(&self.small)
+            + SharedReclaim::reclaim(&self.large)
     }
 }
@@ -0,0 +1,304 @@
+use crate::traits::{Allocator, OwnerCheck};
+use core::{
+    alloc::{AllocErr, CannotReallocInPlace, GlobalAlloc, Layout},
+    any::type_name,
+    fmt,
+    ptr::NonNull,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use log::*;
+
+/// Allocation sizes are grouped by powers of two, starting at 8 bytes
+///
+/// The last bucket counts all larger allocations.
+pub const HISTOGRAM_BUCKETS: usize = 12;
+const HISTOGRAM_MIN_SHIFT: usize = 3;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const ZERO: AtomicUsize = AtomicUsize::new(0);
+
+/// The histogram bucket of an allocation of size
+fn histogram_bucket(size: usize) -> usize {
+    let bits = 0usize.count_zeros() - (size.max(1) - 1).leading_zeros();
+    (bits as usize)
+        .saturating_sub(HISTOGRAM_MIN_SHIFT)
+        .min(HISTOGRAM_BUCKETS - 1)
+}
+
+/// The largest size counted in bucket, or None for the last bucket
+pub fn histogram_bucket_limit(bucket: usize) -> Option<usize> {
+    if bucket + 1 < HISTOGRAM_BUCKETS {
+        Some(1 << (bucket + HISTOGRAM_MIN_SHIFT))
+    } else {
+        None
+    }
+}
+
+/// A composing allocator that counts the requests passed to A
+///
+/// All counters are atomic, so it can be used on either side of a lock.
+pub struct Statistics<A> {
+    inner: A,
+    allocations: AtomicUsize,
+    frees: AtomicUsize,
+    failures: AtomicUsize,
+    live_bytes: AtomicUsize,
+    peak_bytes: AtomicUsize,
+    histogram: [AtomicUsize; HISTOGRAM_BUCKETS],
+}
+
+impl<A> Statistics<A> {
+    pub const fn new(inner: A) -> Self {
+        Statistics {
+            inner,
+            allocations: ZERO,
+            frees: ZERO,
+            failures: ZERO,
+            live_bytes: ZERO,
+            peak_bytes: ZERO,
+            histogram: [ZERO; HISTOGRAM_BUCKETS],
+        }
+    }
+
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> A {
+        self.inner
+    }
+
+    fn record_allocation(&self, size: usize) {
+        self.allocations.fetch_add(1, Ordering::Relaxed);
+        self.histogram[histogram_bucket(size)].fetch_add(1, Ordering::Relaxed);
+
+        let live = self.live_bytes.fetch_add(size, Ordering::Relaxed) + size;
+        let mut peak = self.peak_bytes.load(Ordering::Relaxed);
+        while live > peak {
+            match self.peak_bytes.compare_exchange_weak(
+                peak,
+                live,
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => break,
+                Err(current) => peak = current,
+            }
+        }
+    }
+
+    fn record_free(&self, size: usize) {
+        self.frees.fetch_add(1, Ordering::Relaxed);
+        self.live_bytes.fetch_sub(size, Ordering::Relaxed);
+    }
+
+    fn record_failure(&self) {
+        self.failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A resized allocation is counted as a free of the old allocation
+    /// and an allocation of the new one
+    fn record_resize(&self, size: usize, new_size: usize) {
+        self.record_free(size);
+        self.record_allocation(new_size);
+    }
+
+    fn record_result<T>(&self, size: usize, success: bool, result: T) -> T {
+        if success {
+            self.record_allocation(size);
+        } else {
+            self.record_failure();
+        }
+        result
+    }
+
+    pub fn snapshot(&self) -> AllocationStatistics {
+        let mut histogram = [0; HISTOGRAM_BUCKETS];
+        for (count, counter) in histogram.iter_mut().zip(self.histogram.iter())
+        {
+            *count = counter.load(Ordering::Relaxed);
+        }
+
+        AllocationStatistics {
+            allocations: self.allocations.load(Ordering::Relaxed),
+            frees: self.frees.load(Ordering::Relaxed),
+            failures: self.failures.load(Ordering::Relaxed),
+            live_bytes: self.live_bytes.load(Ordering::Relaxed),
+            peak_bytes: self.peak_bytes.load(Ordering::Relaxed),
+            histogram,
+        }
+    }
+
+    /// Log the current statistics, labeled with the type of A
+    pub fn report(&self) {
+        info!(
+            "Allocation statistics of {}:\n{}",
+            type_name::<A>(),
+            self.snapshot()
+        );
+    }
+}
+
+impl<A> Default for Statistics<A>
+where
+    A: Default,
+{
+    fn default() -> Self {
+        Self::new(Default::default())
+    }
+}
+
+impl<A> Allocator for Statistics<A>
+where
+    A: Allocator,
+{
+    fn alloc(
+        &mut self,
+        layout: Layout,
+    ) -> Result<(NonNull<u8>, usize), AllocErr> {
+        let result = self.inner.alloc(layout);
+        self.record_result(layout.size(), result.is_ok(), result)
+    }
+
+    unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        self.inner.dealloc(ptr, layout);
+        self.record_free(layout.size());
+    }
+
+    unsafe fn grow_in_place(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        let result = self.inner.grow_in_place(ptr, layout, new_size);
+        // Allocators that can't resize in place are not failing
+        if result.is_ok() {
+            self.record_resize(layout.size(), new_size);
+        }
+        result
+    }
+
+    unsafe fn shrink_in_place(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        let result = self.inner.shrink_in_place(ptr, layout, new_size);
+        // Allocators that can't resize in place are not failing
+        if result.is_ok() {
+            self.record_resize(layout.size(), new_size);
+        }
+        result
+    }
+
+    unsafe fn realloc(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<(NonNull<u8>, usize), AllocErr> {
+        let result = self.inner.realloc(ptr, layout, new_size);
+        match result {
+            Ok(_) => self.record_resize(layout.size(), new_size),
+            Err(_) => self.record_failure(),
+        }
+        result
+    }
+}
+
+unsafe impl<A> GlobalAlloc for Statistics<A>
+where
+    A: GlobalAlloc,
+{
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.inner.alloc(layout);
+        self.record_result(layout.size(), !ptr.is_null(), ptr)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.inner.dealloc(ptr, layout);
+        self.record_free(layout.size());
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.inner.alloc_zeroed(layout);
+        self.record_result(layout.size(), !ptr.is_null(), ptr)
+    }
+
+    unsafe fn realloc(
+        &self,
+        ptr: *mut u8,
+        layout: Layout,
+        new_size: usize,
+    ) -> *mut u8 {
+        let new_ptr = self.inner.realloc(ptr, layout, new_size);
+        if new_ptr.is_null() {
+            self.record_failure();
+        } else {
+            self.record_resize(layout.size(), new_size);
+        }
+        new_ptr
+    }
+}
+
+impl<A> OwnerCheck for Statistics<A>
+where
+    A: OwnerCheck,
+{
+    fn is_owner(&self, ptr: NonNull<u8>, layout: Layout) -> bool {
+        self.inner.is_owner(ptr, layout)
+    }
+}
+
+/// The counters of a Statistics allocator at some point in time
+///
+/// The Display implementation prints a human readable report.
+#[derive(Copy, Clone, PartialEq, Eq, Default, Debug)]
+pub struct AllocationStatistics {
+    pub allocations: usize,
+    pub frees: usize,
+    pub failures: usize,
+    pub live_bytes: usize,
+    pub peak_bytes: usize,
+    pub histogram: [usize; HISTOGRAM_BUCKETS],
+}
+
+impl AllocationStatistics {
+    pub fn live_allocations(&self) -> usize {
+        self.allocations - self.frees
+    }
+}
+
+impl fmt::Display for AllocationStatistics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "allocations: {}, frees: {}, failures: {}",
+            self.allocations, self.frees, self.failures
+        )?;
+        writeln!(
+            f,
+            "live: {} bytes in {} allocations, peak: {} bytes",
+            self.live_bytes,
+            self.live_allocations(),
+            self.peak_bytes
+        )?;
+
+        for (bucket, count) in self.histogram.iter().enumerate() {
+            if *count == 0 {
+                continue;
+            }
+
+            match histogram_bucket_limit(bucket) {
+                Some(limit) => writeln!(f, "  <= {:>6}: {}", limit, count)?,
+                None => {
+                    writeln!(f, "   > {:>6}: {}", 1 << (bucket + 2), count)?
+                },
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,54 @@
+use crate::GLOBAL_ALLOCATOR;
+use alloc::boxed::Box;
+use core::{
+    alloc::{AllocErr, GlobalAlloc, Layout},
+    mem::size_of,
+    ptr::NonNull,
+};
+
+/// Allocate from the kernel heap without calling the allocation error handler
+///
+/// Memory was already reclaimed when this fails,
+/// so the caller can only degrade, like shrinking a cache or rejecting a request.
+/// Zero sized layouts can't be allocated.
+pub fn try_alloc(layout: Layout) -> Result<NonNull<u8>, AllocErr> {
+    if layout.size() == 0 {
+        return Err(AllocErr);
+    }
+
+    NonNull::new(unsafe { GLOBAL_ALLOCATOR.alloc(layout) }).ok_or(AllocErr)
+}
+
+/// Like try_alloc, with the memory filled with zeros
+pub fn try_alloc_zeroed(layout: Layout) -> Result<NonNull<u8>, AllocErr> {
+    if layout.size() == 0 {
+        return Err(AllocErr);
+    }
+
+    NonNull::new(unsafe { GLOBAL_ALLOCATOR.alloc_zeroed(layout) })
+        .ok_or(AllocErr)
+}
+
+/// Give memory from try_alloc back to the kernel heap
+///
+/// # Safety
+/// ptr has to be allocated by try_alloc or try_alloc_zeroed with layout
+pub unsafe fn dealloc(ptr: NonNull<u8>, layout: Layout) {
+    GLOBAL_ALLOCATOR.dealloc(ptr.as_ptr(), layout)
+}
+
+/// Move value into a Box, value is given back if there is not enough memory
+pub fn try_box<T>(value: T) -> Result<Box<T>, T> {
+    if size_of::<T>() == 0 {
+        return Ok(Box::new(value));
+    }
+
+    match try_alloc(Layout::new::<T>()) {
+        Ok(memory) => unsafe {
+            let memory = memory.cast::<T>();
+            memory.as_ptr().write(value);
+            Ok(Box::from_raw(memory.as_ptr()))
+        },
+        Err(_) => Err(value),
+    }
+}
@@ -7,94 +7,198 @@
 #![feature(leading_trailing_ones)]
 #![feature(alloc_layout_extra)]
 #![feature(alloc_error_handler)]
+#![feature(const_in_array_repeat_expressions)]
+#![cfg_attr(target_os = "none", feature(global_asm))]
+
+extern crate alloc;
 
 use crate::{
     allocators::{
-        fixed_bitmap::FixedBitMap, kernel_heap_pages::KernelHeapPages,
+        buddy::{BuddyAllocator, MAX_BLOCK_SIZE},
+        fixed_bitmap::{block_alignment, FixedBitMap},
+        kernel_heap_pages::KernelHeapPages,
     },
     composition::{
+        cpu_local_cache::CpuLocalCache, fall_back::FallBackAllocator,
         layout_normalizer::LayoutNormalizer, linked_chain::LinkedChain,
-        locked_global_alloc::LockedGlobalAlloc, size_deciding::SizeDeciding,
+        locked_global_alloc::LockedGlobalAlloc, reclaiming::Reclaiming,
+        size_deciding::SizeDeciding, statistics::Statistics,
     },
+    traits::SharedReclaim,
 };
 use core::alloc::Layout;
 use x86_64::structures::paging::{PageSize, Size4KiB};
 
+#[macro_use]
+mod macros;
+
 pub mod allocators;
 pub mod composition;
+pub mod fallible;
 pub mod traits;
 pub mod utils;
 
+/// Memory was already reclaimed by the kernel heap when this is called
 #[alloc_error_handler]
 pub fn alloc_err(l: Layout) -> ! {
-    panic!("Allocation error: {:?}", l);
+    GLOBAL_ALLOCATOR.statistics().report();
+    panic!("Out of memory: allocation of {:?} failed", l);
 }
 
-/// Allocations larger than this are allocated as pages
+/// Allocations larger than this are made by the LargeAllocator
 const SIZE_PAGE_FALLBACK: usize = 512;
 
+/// Empty blocks each bucket keeps instead of returning them to the heap
+const CACHED_EMPTY_BLOCKS: usize = 1;
+
+/// Blocks of a bucket are aligned as far as their size allows,
+/// so aligned objects don't have to be padded to a page
 pub type Bucket<const SIZE: usize, const PAGES: usize> = LinkedChain<
-    FixedBitMap<{ SIZE }, { (Size4KiB::SIZE as usize) * PAGES / SIZE }>,
+    FixedBitMap<
+        { SIZE },
+        { (Size4KiB::SIZE as usize) * PAGES / SIZE },
+        { block_alignment(SIZE) },
+    >,
     KernelHeapPages,
+    { CACHED_EMPTY_BLOCKS },
 >;
 
 pub type DecidingBucket<A, const SIZE: usize, const PAGES: usize> =
     SizeDeciding<Bucket<{ SIZE }, { PAGES }>, A, { SIZE }>;
 
-pub type KernelAllocator = LayoutNormalizer<
-    SizeDeciding<
-        LockedGlobalAlloc<
-            // Bucket size 16
-            DecidingBucket<
-                // Bucket size 32
-                DecidingBucket<
-                    // Bucket size 64
-                    DecidingBucket<
-                        // Bucket size 128
-                        DecidingBucket<
-                            // Bucket of size 256
-                            DecidingBucket<
-                                // Fallback bucket, has to cover all sizes up to SIZE_PAGE_FALLBACK
-                                Bucket<{ SIZE_PAGE_FALLBACK }, { 1 }>,
-                                { 256 },
-                                { 1 },
-                            >,
-                            { 128 },
-                            { 1 },
-                        >,
-                        { 64 },
-                        { 1 },
-                    >,
-                    { 32 },
-                    { 1 },
-                >,
-                { 16 },
-                { 1 },
-            >,
-        >,
-        KernelHeapPages,
-        { SIZE_PAGE_FALLBACK },
+/// Allocations above the buckets
+///
+/// Mid-size allocations are served by a buddy allocator, falling back to pages once it is full.
+/// Allocations larger than its blocks are allocated as pages.
+pub type LargeAllocator = SizeDeciding<
+    LockedGlobalAlloc<
+        FallBackAllocator<BuddyAllocator<KernelHeapPages>, KernelHeapPages>,
     >,
+    KernelHeapPages,
+    { MAX_BLOCK_SIZE },
 >;
 
-#[global_allocator]
-pub static GLOBAL_ALLOCATOR: KernelAllocator =
-    LayoutNormalizer::new(SizeDeciding::new(
-        LockedGlobalAlloc::new(SizeDeciding::new(
-            LinkedChain::new(KernelHeapPages),
-            SizeDeciding::new(
-                LinkedChain::new(KernelHeapPages),
-                SizeDeciding::new(
-                    LinkedChain::new(KernelHeapPages),
-                    SizeDeciding::new(
-                        LinkedChain::new(KernelHeapPages),
-                        SizeDeciding::new(
-                            LinkedChain::new(KernelHeapPages),
-                            LinkedChain::new(KernelHeapPages),
-                        ),
-                    ),
-                ),
-            ),
+pub const fn large_allocator() -> LargeAllocator {
+    SizeDeciding::new(
+        LockedGlobalAlloc::new(FallBackAllocator::new(
+            BuddyAllocator::new(KernelHeapPages),
+            KernelHeapPages,
         )),
         KernelHeapPages,
-    ));
+    )
+}
+
+/// The allocator below the reclaiming layer,
+/// wrapped in a LeakTracker if the leak_tracker feature is enabled
+#[cfg(feature = "leak_tracker")]
+pub type Tracked<A> = composition::leak_tracker::LeakTracker<A>;
+#[cfg(not(feature = "leak_tracker"))]
+pub type Tracked<A> = A;
+
+#[cfg(feature = "leak_tracker")]
+pub const fn tracked<A>(inner: A) -> Tracked<A> {
+    composition::leak_tracker::LeakTracker::new(inner)
+}
+#[cfg(not(feature = "leak_tracker"))]
+pub const fn tracked<A>(inner: A) -> Tracked<A> {
+    inner
+}
+
+/// The allocator below the leak tracker,
+/// wrapped in a DebugAllocator if the debug_allocator feature is enabled
+#[cfg(feature = "debug_allocator")]
+pub type Checked<A> = composition::debug_allocator::DebugAllocator<A>;
+#[cfg(not(feature = "debug_allocator"))]
+pub type Checked<A> = A;
+
+#[cfg(feature = "debug_allocator")]
+pub const fn checked<A>(inner: A) -> Checked<A> {
+    composition::debug_allocator::DebugAllocator::new(inner)
+}
+#[cfg(not(feature = "debug_allocator"))]
+pub const fn checked<A>(inner: A) -> Checked<A> {
+    inner
+}
+
+kernel_buckets! {
+    pub type KernelBuckets;
+    pub const fn kernel_buckets;
+    pub const KERNEL_BUCKET_SIZES;
+    buckets: [16 => 1, 32 => 1, 64 => 1, 128 => 1, 256 => 1],
+    // Has to cover all sizes up to SIZE_PAGE_FALLBACK
+    fallback: SIZE_PAGE_FALLBACK => 1,
+}
+
+/// The buckets with their free objects cached per core,
+/// larger allocations are made by the LargeAllocator
+pub type Cached = SizeDeciding<
+    CpuLocalCache<KernelBuckets>,
+    LargeAllocator,
+    { SIZE_PAGE_FALLBACK },
+>;
+
+pub type CheckedLayer = Checked<Cached>;
+pub type TrackedLayer = Tracked<CheckedLayer>;
+/// Failed requests are retried after reclaiming cached memory
+pub type ReclaimingLayer = Reclaiming<TrackedLayer>;
+/// Requests are counted below the layout normalizer
+pub type StatisticsLayer = Statistics<ReclaimingLayer>;
+
+pub type KernelAllocator = LayoutNormalizer<StatisticsLayer>;
+
+#[global_allocator]
+pub static GLOBAL_ALLOCATOR: KernelAllocator = LayoutNormalizer::new(
+    Statistics::new(Reclaiming::new(tracked(checked(SizeDeciding::new(
+        CpuLocalCache::new(
+            LockedGlobalAlloc::new(kernel_buckets()),
+            KERNEL_BUCKET_SIZES,
+        ),
+        large_allocator(),
+    ))))),
+);
+
+impl KernelAllocator {
+    pub fn statistics(&self) -> &StatisticsLayer {
+        self.inner()
+    }
+
+    pub fn reclaiming(&self) -> &ReclaimingLayer {
+        self.statistics().inner()
+    }
+
+    /// The LeakTracker if the leak_tracker feature is enabled
+    pub fn tracked(&self) -> &TrackedLayer {
+        self.reclaiming().inner()
+    }
+
+    /// The DebugAllocator if the debug_allocator feature is enabled
+    #[cfg(feature = "leak_tracker")]
+    pub fn checked(&self) -> &CheckedLayer {
+        self.tracked().inner()
+    }
+    #[cfg(not(feature = "leak_tracker"))]
+    pub fn checked(&self) -> &CheckedLayer {
+        self.tracked()
+    }
+}
+
+/// Verify the redzones and quarantine of the kernel heap
+///
+/// Panics if any corruption is found.
+#[cfg(feature = "debug_allocator")]
+pub fn check_heap() {
+    GLOBAL_ALLOCATOR.checked().check()
+}
+
+/// The tracker of all kernel heap allocations
+#[cfg(feature = "leak_tracker")]
+pub fn heap_leak_tracker() -> &'static TrackedLayer {
+    GLOBAL_ALLOCATOR.tracked()
+}
+
+/// Release the memory cached by the kernel heap and by the registered reclaim callbacks
+///
+/// Returns the number of bytes that were released.
+pub fn reclaim() -> usize {
+    GLOBAL_ALLOCATOR.reclaiming().reclaim()
+}
@@ -0,0 +1,69 @@
+/// Declare the type and the initializer of a nest of kernel heap buckets
+///
+/// Buckets are listed from the smallest to the largest size class as `size => pages`.
+/// Each bucket serves allocations up to its size from slabs of pages,
+/// its blocks are aligned to the largest power of two dividing the size.
+/// The fallback bucket serves everything up to its size that no other bucket took.
+/// The sizes of all buckets, including the fallback, are declared as a constant.
+///
+/// ```ignore
+/// kernel_buckets! {
+///     pub type KernelBuckets;
+///     pub const fn kernel_buckets;
+///     pub const KERNEL_BUCKET_SIZES;
+///     buckets: [16 => 1, 32 => 1],
+///     fallback: 64 => 1,
+/// }
+/// ```
+#[macro_export]
+macro_rules! kernel_buckets {
+    (@type [] $fallback:expr => $fallback_pages:expr) => {
+        $crate::Bucket<{ $fallback }, { $fallback_pages }>
+    };
+    (@type [$size:expr => $pages:expr $(, $rest:expr => $rest_pages:expr)*]
+        $fallback:expr => $fallback_pages:expr) => {
+        $crate::DecidingBucket<
+            $crate::kernel_buckets!(
+                @type [$($rest => $rest_pages),*] $fallback => $fallback_pages
+            ),
+            { $size },
+            { $pages },
+        >
+    };
+    (@init []) => {
+        $crate::composition::linked_chain::LinkedChain::new(
+            $crate::allocators::kernel_heap_pages::KernelHeapPages,
+        )
+    };
+    (@init [$size:expr $(, $rest:expr)*]) => {
+        $crate::composition::size_deciding::SizeDeciding::new(
+            $crate::composition::linked_chain::LinkedChain::new(
+                $crate::allocators::kernel_heap_pages::KernelHeapPages,
+            ),
+            $crate::kernel_buckets!(@init [$($rest),*]),
+        )
+    };
+    (
+        $(#[$type_meta:meta])*
+        $type_vis:vis type $type:ident;
+        $(#[$init_meta:meta])*
+        $init_vis:vis const fn $init:ident;
+        $(#[$sizes_meta:meta])*
+        $sizes_vis:vis const $sizes:ident;
+        buckets: [$($size:expr => $pages:expr),* $(,)?],
+        fallback: $fallback:expr => $fallback_pages:expr $(,)?
+    ) => {
+        $(#[$type_meta])*
+        $type_vis type $type = $crate::kernel_buckets!(
+            @type [$($size => $pages),*] $fallback => $fallback_pages
+        );
+
+        $(#[$init_meta])*
+        $init_vis const fn $init() -> $type {
+            $crate::kernel_buckets!(@init [$($size),*])
+        }
+
+        $(#[$sizes_meta])*
+        $sizes_vis const $sizes: &[usize] = &[$($size,)* $fallback];
+    };
+}
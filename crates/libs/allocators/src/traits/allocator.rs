@@ -1,6 +1,6 @@
 use core::{
-    alloc::{AllocErr, Layout},
-    ptr::NonNull,
+    alloc::{AllocErr, CannotReallocInPlace, Layout},
+    ptr::{copy_nonoverlapping, NonNull},
 };
 
 /// An owning allocator
@@ -13,4 +13,62 @@ pub trait Allocator {
     ) -> Result<(NonNull<u8>, usize), AllocErr>;
 
     unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout);
+
+    /// Grow the allocation at ptr to new_size without moving it
+    ///
+    /// Returns the usable size of the allocation,
+    /// it is then deallocated with new_size and the alignment of layout.
+    unsafe fn grow_in_place(
+        &mut self,
+        _ptr: NonNull<u8>,
+        _layout: Layout,
+        _new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        Err(CannotReallocInPlace)
+    }
+
+    /// Shrink the allocation at ptr to new_size without moving it
+    ///
+    /// Returns the usable size of the allocation,
+    /// it is then deallocated with new_size and the alignment of layout.
+    unsafe fn shrink_in_place(
+        &mut self,
+        _ptr: NonNull<u8>,
+        _layout: Layout,
+        _new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        Err(CannotReallocInPlace)
+    }
+
+    /// Resize the allocation at ptr to new_size
+    ///
+    /// It is resized in place if possible, otherwise it is moved to a new allocation.
+    /// If this fails, the old allocation is left untouched.
+    unsafe fn realloc(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<(NonNull<u8>, usize), AllocErr> {
+        let in_place = if new_size >= layout.size() {
+            self.grow_in_place(ptr, layout, new_size)
+        } else {
+            self.shrink_in_place(ptr, layout, new_size)
+        };
+        if let Ok(size) = in_place {
+            return Ok((ptr, size));
+        }
+
+        let new_layout = Layout::from_size_align(new_size, layout.align())
+            .map_err(|_| AllocErr)?;
+        let (new_ptr, size) = self.alloc(new_layout)?;
+        copy_nonoverlapping(
+            ptr.as_ptr(),
+            new_ptr.as_ptr(),
+            layout.size().min(new_size),
+        );
+        self.dealloc(ptr, layout);
+
+        Ok((new_ptr, size))
+    }
 }
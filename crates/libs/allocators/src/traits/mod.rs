@@ -6,3 +6,28 @@ use core::{alloc::Layout, ptr::NonNull};
 pub trait OwnerCheck {
     fn is_owner(&self, ptr: NonNull<u8>, layout: Layout) -> bool;
 }
+
+/// Allocators that know how much of their capacity is in use
+pub trait Occupancy {
+    /// There are no live allocations
+    fn is_unused(&self) -> bool;
+
+    /// No further allocation can succeed
+    fn is_full(&self) -> bool;
+}
+
+/// Allocators that hold memory they don't use, like cached objects or empty slabs
+pub trait Reclaim {
+    /// Give unused memory back to the backing allocator
+    ///
+    /// Returns the number of bytes that were released.
+    fn reclaim(&mut self) -> usize;
+}
+
+/// Reclaim for allocators that are shared, like a GlobalAlloc
+pub trait SharedReclaim {
+    /// Give unused memory back to the backing allocator
+    ///
+    /// Returns the number of bytes that were released.
+    fn reclaim(&self) -> usize;
+}
@@ -0,0 +1,133 @@
+use core::{fmt, mem::size_of};
+use page_management::page_table::kernel_stack::KernelStack;
+use x86_64::VirtAddr;
+
+/// The number of return addresses kept per backtrace
+pub const BACKTRACE_DEPTH: usize = 8;
+
+#[cfg(target_os = "none")]
+global_asm!(
+    "
+    .intel_syntax noprefix
+    .section .text
+
+    // Returns the frame pointer of the caller
+    .align 16
+    .global asm_frame_pointer
+    asm_frame_pointer:
+        mov rax, rbp
+        ret
+
+    .att_syntax prefix
+    "
+);
+
+#[cfg(target_os = "none")]
+extern "sysv64" {
+    fn asm_frame_pointer() -> usize;
+}
+
+#[cfg(target_os = "none")]
+#[inline(always)]
+fn frame_pointer() -> usize {
+    unsafe { asm_frame_pointer() }
+}
+
+/// Host builds don't walk the stack
+#[cfg(not(target_os = "none"))]
+#[inline(always)]
+fn frame_pointer() -> usize {
+    0
+}
+
+/// The bounds of the kernel stack containing address
+fn stack_bounds(address: usize) -> Option<(usize, usize)> {
+    let address = VirtAddr::try_new(address as u64).ok()?;
+    let pages = KernelStack::containing_address(address)?.pages();
+
+    Some((
+        pages.start.start_address().as_u64() as usize,
+        pages.end.start_address().as_u64() as usize,
+    ))
+}
+
+/// The innermost return addresses of a call stack
+///
+/// The stack is walked along the frame pointers,
+/// which the kernel is only built with when heap debugging is enabled.
+/// The walk stays within the kernel stack it starts on,
+/// backtraces captured on any other stack are empty.
+/// The first addresses usually belong to the callers inside the allocator stack.
+#[derive(Copy, Clone, PartialEq, Eq, Default, Debug)]
+pub struct Backtrace {
+    addresses: [usize; BACKTRACE_DEPTH],
+}
+
+impl Backtrace {
+    #[inline(never)]
+    pub fn capture() -> Self {
+        let mut backtrace = Backtrace::default();
+
+        let mut frame = frame_pointer();
+        let (bottom, top) = match stack_bounds(frame) {
+            Some(bounds) => bounds,
+            None => return backtrace,
+        };
+
+        for address in backtrace.addresses.iter_mut() {
+            // Never read the guard pages below or whatever is above the stack
+            if frame % 8 != 0
+                || frame < bottom
+                || frame + 2 * size_of::<usize>() > top
+            {
+                break;
+            }
+
+            // A frame starts with the caller's frame pointer, followed by the return address
+            let (next, return_address) = unsafe {
+                let frame = frame as *const usize;
+                (frame.read(), frame.add(1).read())
+            };
+
+            if return_address == 0 {
+                break;
+            }
+            *address = return_address;
+
+            // The stack grows down, so callers always have higher frame addresses
+            if next <= frame {
+                break;
+            }
+            frame = next;
+        }
+
+        backtrace
+    }
+
+    pub fn addresses<'this>(
+        &'this self,
+    ) -> impl 'this + Iterator<Item = usize> {
+        self.addresses
+            .iter()
+            .copied()
+            .take_while(|address| *address != 0)
+    }
+
+    /// The innermost return address, if any was captured
+    pub fn caller(&self) -> Option<usize> {
+        self.addresses().next()
+    }
+}
+
+impl fmt::Display for Backtrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[")?;
+        for (index, address) in self.addresses().enumerate() {
+            if index > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "0x{:X}", address)?;
+        }
+        write!(f, "]")
+    }
+}
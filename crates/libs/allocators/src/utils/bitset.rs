@@ -1,7 +1,7 @@
 use core::mem::MaybeUninit;
 
-type RawType = usize;
-const RAW_TYPE_BITS: usize = core::mem::size_of::<RawType>() * 8;
+pub(crate) type RawType = usize;
+pub(crate) const RAW_TYPE_BITS: usize = core::mem::size_of::<RawType>() * 8;
 
 #[derive(Copy, Clone)]
 #[repr(transparent)]
@@ -53,6 +53,15 @@ impl<const SIZE: usize> BitSet<{ SIZE }> {
         (self.0).0.iter().all(|v| *v == 0)
     }
 
+    /// The raw groups of bits, bits beyond the capacity are never set
+    pub(crate) fn groups(&self) -> &[RawType] {
+        &(self.0).0
+    }
+
+    pub(crate) fn groups_mut(&mut self) -> &mut [RawType] {
+        &mut (self.0).0
+    }
+
     fn get_group_for(&self, index: usize) -> Option<RawType> {
         if index < self.capacity() {
             Some(unsafe { *(self.0).0.get_unchecked(index / RAW_TYPE_BITS) })
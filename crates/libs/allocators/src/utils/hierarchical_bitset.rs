@@ -0,0 +1,268 @@
+use crate::utils::bitset::{BitSet, RawType, RAW_TYPE_BITS};
+use core::ops::Range;
+
+/// The first bit at or after start that is set, after inverting the groups if invert is set
+fn find_from(groups: &[RawType], start: usize, invert: bool) -> Option<usize> {
+    let mut group_index = start / RAW_TYPE_BITS;
+    let mut mask: RawType = !0 << (start % RAW_TYPE_BITS);
+
+    while let Some(group) = groups.get(group_index) {
+        let group = if invert { !group } else { *group } & mask;
+
+        if group != 0 {
+            return Some(
+                group_index * RAW_TYPE_BITS + group.trailing_zeros() as usize,
+            );
+        }
+
+        group_index += 1;
+        mask = !0;
+    }
+
+    None
+}
+
+/// Like find_from, but the groups are skipped along their summary
+///
+/// A bit of summary is set if its group has a bit that is searched for,
+/// after inverting it the same way.
+fn find_from_summary(
+    groups: &[RawType],
+    summary: &[RawType],
+    start: usize,
+    invert: bool,
+) -> Option<usize> {
+    let group = start / RAW_TYPE_BITS;
+
+    // The rest of the group containing start
+    let first = &groups[..(group + 1).min(groups.len())];
+    if let Some(index) = find_from(first, start, invert) {
+        return Some(index);
+    }
+
+    let next = find_from(summary, group + 1, invert)?;
+    find_from(groups, next * RAW_TYPE_BITS, invert)
+}
+
+/// The bits of group that are inside range, range has to end after the start of group
+fn range_mask(group: usize, range: &Range<usize>) -> RawType {
+    let ones = |bits: usize| -> RawType {
+        if bits >= RAW_TYPE_BITS {
+            !0
+        } else {
+            (1 << bits) - 1
+        }
+    };
+    let start = group * RAW_TYPE_BITS;
+
+    ones(range.end - start) & !ones(range.start.saturating_sub(start))
+}
+
+/// A bitset with two summary levels, for fast searches in large capacities
+///
+/// Every group of bits has a bit in a summary of full groups and in a summary of occupied groups.
+/// Each of these summaries is summarized again, by which of its groups are full or occupied.
+/// The first group that is not full and the first occupied group are kept up to date,
+/// so finding the first set or unset bit doesn't scan.
+/// Only updates that fill or empty that group search for the next one,
+/// which skips along the second level, one bit of it covers 4096 bits.
+#[derive(Copy, Clone)]
+pub struct HierarchicalBitSet<const SIZE: usize> {
+    bits: BitSet<{ SIZE }>,
+    full: BitSet<{ (SIZE + RAW_TYPE_BITS - 1) / RAW_TYPE_BITS }>,
+    occupied: BitSet<{ (SIZE + RAW_TYPE_BITS - 1) / RAW_TYPE_BITS }>,
+    full_summary: BitSet<
+        {
+            (SIZE + RAW_TYPE_BITS * RAW_TYPE_BITS - 1)
+                / (RAW_TYPE_BITS * RAW_TYPE_BITS)
+        },
+    >,
+    occupied_summary: BitSet<
+        {
+            (SIZE + RAW_TYPE_BITS * RAW_TYPE_BITS - 1)
+                / (RAW_TYPE_BITS * RAW_TYPE_BITS)
+        },
+    >,
+    /// All groups before it are full
+    first_not_full: usize,
+    /// All groups before it are empty
+    first_occupied: usize,
+    len: usize,
+}
+
+impl<const SIZE: usize> Default for HierarchicalBitSet<{ SIZE }> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const SIZE: usize> HierarchicalBitSet<{ SIZE }> {
+    const GROUPS: usize = (SIZE + RAW_TYPE_BITS - 1) / RAW_TYPE_BITS;
+
+    pub fn new() -> Self {
+        HierarchicalBitSet {
+            bits: BitSet::new(),
+            full: BitSet::new(),
+            occupied: BitSet::new(),
+            full_summary: BitSet::new(),
+            occupied_summary: BitSet::new(),
+            first_not_full: 0,
+            first_occupied: Self::GROUPS,
+            len: 0,
+        }
+    }
+
+    #[inline(always)]
+    pub const fn capacity(&self) -> usize {
+        SIZE
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len == SIZE
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn contains(&self, index: usize) -> Option<bool> {
+        self.bits.contains(index)
+    }
+
+    /// Replace the bits of group and update the summaries
+    fn update_group<F>(&mut self, group: usize, function: F)
+    where
+        F: FnOnce(RawType) -> RawType,
+    {
+        let old = self.bits.groups()[group];
+        let new = function(old);
+        self.bits.groups_mut()[group] = new;
+        self.len =
+            self.len + new.count_ones() as usize - old.count_ones() as usize;
+
+        let is_full = new == range_mask(group, &(0..SIZE));
+        self.full.set(group, is_full);
+        self.occupied.set(group, new != 0);
+
+        let summary = group / RAW_TYPE_BITS;
+        let full = self.full.groups()[summary];
+        let occupied = self.occupied.groups()[summary];
+        self.full_summary
+            .set(summary, full == range_mask(summary, &(0..Self::GROUPS)));
+        self.occupied_summary.set(summary, occupied != 0);
+
+        if !is_full {
+            self.first_not_full = self.first_not_full.min(group);
+        } else if group == self.first_not_full {
+            self.first_not_full = find_from_summary(
+                self.full.groups(),
+                self.full_summary.groups(),
+                group,
+                true,
+            )
+            .unwrap_or(Self::GROUPS)
+            .min(Self::GROUPS);
+        }
+
+        if new != 0 {
+            self.first_occupied = self.first_occupied.min(group);
+        } else if group == self.first_occupied {
+            self.first_occupied = find_from_summary(
+                self.occupied.groups(),
+                self.occupied_summary.groups(),
+                group,
+                false,
+            )
+            .unwrap_or(Self::GROUPS);
+        }
+    }
+
+    pub fn insert(&mut self, index: usize) -> Option<()> {
+        if index >= SIZE {
+            return None;
+        }
+
+        self.update_group(index / RAW_TYPE_BITS, |group| {
+            group | (1 << (index % RAW_TYPE_BITS))
+        });
+        Some(())
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<()> {
+        if index >= SIZE {
+            return None;
+        }
+
+        self.update_group(index / RAW_TYPE_BITS, |group| {
+            group & !(1 << (index % RAW_TYPE_BITS))
+        });
+        Some(())
+    }
+
+    pub fn set(&mut self, index: usize, value: bool) -> Option<()> {
+        if value {
+            self.insert(index)
+        } else {
+            self.remove(index)
+        }
+    }
+
+    /// Set or clear all bits in range
+    ///
+    /// Fails without changing anything if range does not fit into the capacity.
+    pub fn set_range(
+        &mut self,
+        range: Range<usize>,
+        value: bool,
+    ) -> Option<()> {
+        if range.start > range.end || range.end > SIZE {
+            return None;
+        }
+
+        let groups = range.start / RAW_TYPE_BITS
+            ..(range.end + RAW_TYPE_BITS - 1) / RAW_TYPE_BITS;
+        for group in groups {
+            let mask = range_mask(group, &range);
+            self.update_group(group, |bits| {
+                if value {
+                    bits | mask
+                } else {
+                    bits & !mask
+                }
+            });
+        }
+
+        Some(())
+    }
+
+    pub fn insert_range(&mut self, range: Range<usize>) -> Option<()> {
+        self.set_range(range, true)
+    }
+
+    pub fn remove_range(&mut self, range: Range<usize>) -> Option<()> {
+        self.set_range(range, false)
+    }
+
+    pub fn find_first_unset(&self) -> Option<usize> {
+        let group = self.first_not_full;
+        let bits = self.bits.groups().get(group)?;
+        let index = group * RAW_TYPE_BITS + bits.trailing_ones() as usize;
+
+        if index < SIZE {
+            Some(index)
+        } else {
+            None
+        }
+    }
+
+    pub fn find_first_set(&self) -> Option<usize> {
+        let group = self.first_occupied;
+        let bits = self.bits.groups().get(group)?;
+
+        Some(group * RAW_TYPE_BITS + bits.trailing_zeros() as usize)
+    }
+}
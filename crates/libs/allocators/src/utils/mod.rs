@@ -1 +1,3 @@
+pub mod backtrace;
 pub mod bitset;
+pub mod hierarchical_bitset;
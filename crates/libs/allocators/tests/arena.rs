@@ -0,0 +1,130 @@
+#![feature(allocator_api)]
+
+mod common;
+
+use allocators::{
+    allocators::arena::{Arena, ARENA_CHUNK_SIZE},
+    traits::Allocator,
+};
+use common::HostBacking;
+use core::alloc::Layout;
+
+fn layout(size: usize, align: usize) -> Layout {
+    Layout::from_size_align(size, align).unwrap()
+}
+
+#[test]
+pub fn test_allocations_are_bumped() {
+    let mut arena = Arena::new(HostBacking);
+
+    let (first, _) = arena.alloc(layout(3, 1)).unwrap();
+    let (second, _) = arena.alloc(layout(8, 8)).unwrap();
+    let (third, _) = arena.alloc(layout(1, 1)).unwrap();
+
+    assert_eq!(second.as_ptr() as usize % 8, 0);
+    assert!(second.as_ptr() as usize >= first.as_ptr() as usize + 3);
+    assert_eq!(third.as_ptr() as usize, second.as_ptr() as usize + 8);
+    assert_eq!(arena.chunks(), 1);
+
+    // Only the most recent allocation is reclaimed
+    unsafe {
+        arena.dealloc(third, layout(1, 1));
+    }
+    assert_eq!(arena.alloc(layout(1, 1)).unwrap().0, third);
+
+    // Large allocations get a chunk of their own
+    arena.alloc(layout(ARENA_CHUNK_SIZE * 2, 4096)).unwrap();
+    assert_eq!(arena.chunks(), 2);
+
+    unsafe {
+        arena.reset();
+    }
+    assert_eq!(arena.chunks(), 0);
+}
+
+#[test]
+pub fn test_large_allocations_keep_the_current_chunk() {
+    let mut arena = Arena::new(HostBacking);
+    let (first, _) = arena.alloc(layout(16, 8)).unwrap();
+
+    arena.scope(|arena| {
+        // Doesn't fit into the rest of the current chunk
+        arena.alloc(layout(ARENA_CHUNK_SIZE, 8)).unwrap();
+        assert_eq!(arena.chunks(), 2);
+
+        // Smaller allocations continue after the ones before the large one
+        assert_eq!(arena.alloc(layout(16, 8)).unwrap().0.as_ptr(), unsafe {
+            first.as_ptr().add(16)
+        });
+    });
+
+    // The scope released the large chunk
+    assert_eq!(arena.chunks(), 1);
+}
+
+#[test]
+pub fn test_nested_scopes() {
+    let mut arena = Arena::new(HostBacking);
+    let outer = arena.alloc(layout(16, 8)).unwrap().0;
+
+    arena.scope(|arena| {
+        let inner = arena.alloc(layout(16, 8)).unwrap().0;
+
+        arena.scope(|arena| {
+            for _ in 0..3 {
+                arena.alloc(layout(ARENA_CHUNK_SIZE, 8)).unwrap();
+            }
+            assert_eq!(arena.chunks(), 4);
+        });
+
+        // The nested scope released its chunks and the memory after inner
+        assert_eq!(arena.chunks(), 1);
+        assert_eq!(arena.alloc(layout(16, 8)).unwrap().0.as_ptr(), unsafe {
+            inner.as_ptr().add(16)
+        });
+    });
+
+    assert_eq!(arena.alloc(layout(16, 8)).unwrap().0.as_ptr(), unsafe {
+        outer.as_ptr().add(16)
+    });
+}
+
+#[test]
+pub fn test_last_allocation_grows_in_place() {
+    let mut arena = Arena::new(HostBacking);
+
+    let (ptr, _) = arena.alloc(layout(16, 8)).unwrap();
+    unsafe {
+        let (grown, size) = arena.realloc(ptr, layout(16, 8), 64).unwrap();
+        assert_eq!((grown, size), (ptr, 64));
+
+        let (other, _) = arena.alloc(layout(8, 8)).unwrap();
+        assert_eq!(other.as_ptr(), ptr.as_ptr().add(64));
+
+        assert!(arena.grow_in_place(ptr, layout(64, 8), 128).is_err());
+    }
+}
+
+#[test]
+pub fn test_copies_of_by_ref_share_the_arena() {
+    let mut arena = Arena::new(HostBacking);
+    let mut first = arena.by_ref();
+    let mut second = first;
+
+    let (a, _) =
+        core::alloc::AllocRef::alloc(&mut first, layout(16, 8)).unwrap();
+    let (b, _) =
+        core::alloc::AllocRef::alloc(&mut second, layout(16, 8)).unwrap();
+    assert_eq!(b.as_ptr(), unsafe { a.as_ptr().add(16) });
+
+    unsafe {
+        core::alloc::AllocRef::dealloc(&mut first, b, layout(16, 8));
+    }
+    assert_eq!(
+        core::alloc::AllocRef::alloc(&mut second, layout(16, 8))
+            .unwrap()
+            .0,
+        b
+    );
+    assert_eq!(arena.chunks(), 1);
+}
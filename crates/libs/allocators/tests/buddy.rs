@@ -0,0 +1,167 @@
+mod common;
+
+use allocators::{
+    allocators::buddy::{
+        BuddyAllocator, MAX_BLOCK_SIZE, MIN_BLOCK_SIZE, REGION_SIZE,
+    },
+    traits::{Allocator, OwnerCheck, Reclaim},
+};
+use common::HostBacking;
+use core::{alloc::Layout, ptr::NonNull};
+
+fn layout(size: usize) -> Layout {
+    Layout::from_size_align(size, 8).unwrap()
+}
+
+#[test]
+pub fn test_blocks_are_aligned_and_disjoint() {
+    let mut buddy = BuddyAllocator::new(HostBacking);
+
+    let sizes = [600, 1024, 3000, 4096, 5000, 20000, MAX_BLOCK_SIZE, 1];
+    let mut allocations = Vec::new();
+
+    for size in sizes.iter() {
+        let (ptr, block_size) = buddy.alloc(layout(*size)).unwrap();
+        let address = ptr.as_ptr() as usize;
+
+        assert!(block_size >= *size);
+        assert!(block_size.is_power_of_two());
+        assert_eq!(address % block_size, 0);
+        assert!(buddy.is_owner(ptr, layout(*size)));
+
+        for (other, other_size) in allocations.iter() {
+            assert!(
+                address + block_size <= *other || other + other_size <= address
+            );
+        }
+        allocations.push((address, block_size));
+    }
+
+    for (size, (address, _)) in sizes.iter().zip(allocations) {
+        unsafe {
+            buddy.dealloc(
+                NonNull::new(address as *mut u8).unwrap(),
+                layout(*size),
+            );
+        }
+    }
+}
+
+#[test]
+pub fn test_free_blocks_merge() {
+    let mut buddy = BuddyAllocator::new(HostBacking);
+    let small = layout(MIN_BLOCK_SIZE);
+    let large = layout(MAX_BLOCK_SIZE);
+
+    let allocations: Vec<_> = (0..REGION_SIZE / MIN_BLOCK_SIZE)
+        .map(|_| buddy.alloc(small).unwrap().0)
+        .collect();
+    assert!(buddy.alloc(small).is_err());
+
+    for ptr in allocations {
+        unsafe {
+            buddy.dealloc(ptr, small);
+        }
+    }
+
+    // Only fully merged blocks can serve the largest allocations
+    let allocations: Vec<_> = (0..REGION_SIZE / MAX_BLOCK_SIZE)
+        .map(|_| buddy.alloc(large).unwrap().0)
+        .collect();
+    assert!(buddy.alloc(small).is_err());
+
+    for ptr in allocations {
+        unsafe {
+            buddy.dealloc(ptr, large);
+        }
+    }
+}
+
+#[test]
+pub fn test_too_large_allocations_fail() {
+    let mut buddy = BuddyAllocator::new(HostBacking);
+
+    assert!(buddy.alloc(layout(MAX_BLOCK_SIZE + 1)).is_err());
+    assert!(buddy
+        .alloc(Layout::from_size_align(16, MAX_BLOCK_SIZE * 2).unwrap())
+        .is_err());
+}
+
+#[test]
+pub fn test_blocks_resize_in_place() {
+    let mut buddy = BuddyAllocator::new(HostBacking);
+
+    let (ptr, size) = buddy.alloc(layout(MIN_BLOCK_SIZE)).unwrap();
+    assert_eq!(size, MIN_BLOCK_SIZE);
+
+    unsafe {
+        // The following buddies are free, so the block can take them
+        let size = buddy
+            .grow_in_place(ptr, layout(MIN_BLOCK_SIZE), MIN_BLOCK_SIZE * 3)
+            .unwrap();
+        assert_eq!(size, MIN_BLOCK_SIZE * 4);
+
+        // The upper half is handed out again after shrinking
+        let size = buddy
+            .shrink_in_place(
+                ptr,
+                layout(MIN_BLOCK_SIZE * 3),
+                MIN_BLOCK_SIZE * 2,
+            )
+            .unwrap();
+        assert_eq!(size, MIN_BLOCK_SIZE * 2);
+
+        let (next, _) = buddy.alloc(layout(MIN_BLOCK_SIZE * 2)).unwrap();
+        assert_eq!(next.as_ptr(), ptr.as_ptr().add(MIN_BLOCK_SIZE * 2));
+
+        // Now the buddy is taken
+        assert!(buddy
+            .grow_in_place(ptr, layout(MIN_BLOCK_SIZE * 2), MIN_BLOCK_SIZE * 4)
+            .is_err());
+
+        let (moved, _) = buddy
+            .realloc(ptr, layout(MIN_BLOCK_SIZE * 2), MIN_BLOCK_SIZE * 4)
+            .unwrap();
+        assert_ne!(moved, ptr);
+
+        buddy.dealloc(next, layout(MIN_BLOCK_SIZE * 2));
+        buddy.dealloc(moved, layout(MIN_BLOCK_SIZE * 4));
+    }
+
+    // Everything merged back into the largest blocks
+    let allocations: Vec<_> = (0..REGION_SIZE / MAX_BLOCK_SIZE)
+        .map(|_| buddy.alloc(layout(MAX_BLOCK_SIZE)).unwrap().0)
+        .collect();
+    for ptr in allocations {
+        unsafe {
+            buddy.dealloc(ptr, layout(MAX_BLOCK_SIZE));
+        }
+    }
+}
+
+#[test]
+pub fn test_unused_region_is_reclaimed() {
+    let mut buddy = BuddyAllocator::new(HostBacking);
+    assert_eq!(buddy.reclaim(), 0);
+
+    let (first, _) = buddy.alloc(layout(MIN_BLOCK_SIZE)).unwrap();
+    let (second, _) = buddy.alloc(layout(5000)).unwrap();
+
+    unsafe {
+        buddy.dealloc(first, layout(MIN_BLOCK_SIZE));
+    }
+    assert_eq!(buddy.reclaim(), 0);
+    assert!(buddy.is_owner(second, layout(5000)));
+
+    unsafe {
+        buddy.dealloc(second, layout(5000));
+    }
+    assert_eq!(buddy.reclaim(), REGION_SIZE);
+    assert!(!buddy.is_owner(second, layout(5000)));
+
+    // The region is allocated again on demand
+    let (ptr, _) = buddy.alloc(layout(MAX_BLOCK_SIZE)).unwrap();
+    unsafe {
+        buddy.dealloc(ptr, layout(MAX_BLOCK_SIZE));
+    }
+}
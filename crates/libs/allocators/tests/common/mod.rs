@@ -0,0 +1,25 @@
+use allocators::traits::Allocator;
+use core::{
+    alloc::{AllocErr, Layout},
+    ptr::NonNull,
+};
+use std::alloc::{GlobalAlloc, System};
+
+/// Backs allocators under test with the host allocator
+#[derive(Default)]
+pub struct HostBacking;
+
+impl Allocator for HostBacking {
+    fn alloc(
+        &mut self,
+        layout: Layout,
+    ) -> Result<(NonNull<u8>, usize), AllocErr> {
+        let ptr =
+            NonNull::new(unsafe { System.alloc(layout) }).ok_or(AllocErr)?;
+        Ok((ptr, layout.size()))
+    }
+
+    unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        System.dealloc(ptr.as_ptr(), layout)
+    }
+}
@@ -0,0 +1,163 @@
+mod common;
+
+use allocators::{
+    composition::{
+        cpu_local_cache::CpuLocalCache, locked_global_alloc::LockedGlobalAlloc,
+    },
+    traits::Allocator,
+};
+use common::HostBacking;
+use core::{
+    alloc::{AllocErr, GlobalAlloc, Layout},
+    ptr::NonNull,
+};
+use cpu_local_storage::data::MAGAZINE_CAPACITY;
+
+/// Objects a refill or drain moves at once
+const TRANSFER_SIZE: usize = MAGAZINE_CAPACITY / 2;
+
+/// Both classes have blocks aligned to 16 bytes
+const SIZE_CLASSES: &[usize] = &[16, 48];
+
+/// Counts the requests reaching the allocator below the magazines
+#[derive(Default)]
+struct Counting {
+    inner: HostBacking,
+    allocated: usize,
+    freed: usize,
+    last_layout: Option<Layout>,
+}
+
+impl Allocator for Counting {
+    fn alloc(
+        &mut self,
+        layout: Layout,
+    ) -> Result<(NonNull<u8>, usize), AllocErr> {
+        self.allocated += 1;
+        self.last_layout = Some(layout);
+        self.inner.alloc(layout)
+    }
+
+    unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        self.freed += 1;
+        self.inner.dealloc(ptr, layout)
+    }
+}
+
+fn cache() -> CpuLocalCache<Counting> {
+    CpuLocalCache::new(
+        LockedGlobalAlloc::new(Counting::default()),
+        SIZE_CLASSES,
+    )
+}
+
+fn layout(size: usize, align: usize) -> Layout {
+    Layout::from_size_align(size, align).unwrap()
+}
+
+fn counts(cache: &CpuLocalCache<Counting>) -> (usize, usize) {
+    cache.inner().lock(|inner| (inner.allocated, inner.freed))
+}
+
+fn last_layout(cache: &CpuLocalCache<Counting>) -> Option<Layout> {
+    cache.inner().lock(|inner| inner.last_layout)
+}
+
+#[test]
+pub fn test_empty_magazines_are_refilled() {
+    let cache = cache();
+    let layout = layout(16, 8);
+
+    let objects: Vec<_> = (0..TRANSFER_SIZE)
+        .map(|_| unsafe { cache.alloc(layout) })
+        .collect();
+    assert!(objects.iter().all(|object| !object.is_null()));
+
+    // One refill served all of them
+    assert_eq!(counts(&cache), (TRANSFER_SIZE, 0));
+
+    let next = unsafe { cache.alloc(layout) };
+    assert!(!next.is_null());
+    assert_eq!(counts(&cache), (2 * TRANSFER_SIZE, 0));
+
+    // Frees stay in the magazine while it has room
+    for object in objects.into_iter().chain(Some(next)) {
+        unsafe { cache.dealloc(object, layout) };
+    }
+    assert_eq!(counts(&cache), (2 * TRANSFER_SIZE, 0));
+}
+
+#[test]
+pub fn test_full_magazines_are_drained() {
+    let cache = cache();
+    let layout = layout(16, 8);
+
+    // Three refills, leaving TRANSFER_SIZE - 1 objects in the magazine
+    let objects: Vec<_> = (0..=MAGAZINE_CAPACITY)
+        .map(|_| unsafe { cache.alloc(layout) })
+        .collect();
+    assert_eq!(counts(&cache), (3 * TRANSFER_SIZE, 0));
+
+    let (filling, rest) = objects.split_at(TRANSFER_SIZE + 1);
+    for object in filling {
+        unsafe { cache.dealloc(*object, layout) };
+    }
+    assert_eq!(counts(&cache).1, 0);
+
+    // The magazine is at MAGAZINE_CAPACITY, so the next free drains it
+    unsafe { cache.dealloc(rest[0], layout) };
+    assert_eq!(counts(&cache).1, TRANSFER_SIZE);
+
+    for object in &rest[1..] {
+        unsafe { cache.dealloc(*object, layout) };
+    }
+}
+
+#[test]
+pub fn test_classes_are_chosen_by_block_alignment() {
+    let cache = cache();
+
+    // Fits the 16 byte class and its alignment
+    let small = unsafe { cache.alloc(layout(8, 16)) };
+    assert_eq!(last_layout(&cache), Some(layout(16, 16)));
+
+    // Fits the 48 byte class
+    let medium = unsafe { cache.alloc(layout(40, 16)) };
+    assert_eq!(last_layout(&cache), Some(layout(48, 16)));
+    assert_eq!(counts(&cache), (2 * TRANSFER_SIZE, 0));
+
+    // Blocks of the 48 byte class are only aligned to 16 bytes, so it is passed through
+    let aligned = unsafe { cache.alloc(layout(40, 32)) };
+    assert_eq!(last_layout(&cache), Some(layout(40, 32)));
+    assert_eq!(counts(&cache), (2 * TRANSFER_SIZE + 1, 0));
+    assert_eq!(aligned as usize % 32, 0);
+
+    unsafe {
+        cache.dealloc(aligned, layout(40, 32));
+        assert_eq!(counts(&cache).1, 1);
+
+        cache.dealloc(medium, layout(40, 16));
+        cache.dealloc(small, layout(8, 16));
+    }
+    assert_eq!(counts(&cache).1, 1);
+}
+
+#[test]
+pub fn test_realloc_within_a_class_keeps_the_object() {
+    let cache = cache();
+    let old_layout = layout(20, 8);
+
+    let ptr = unsafe { cache.alloc(old_layout) };
+    unsafe { ptr.write_bytes(0x42, 20) };
+
+    let grown = unsafe { cache.realloc(ptr, old_layout, 48) };
+    assert_eq!(grown, ptr);
+
+    // Shrinking into the smaller class moves the contents
+    let shrunk = unsafe { cache.realloc(grown, layout(48, 8), 12) };
+    assert_ne!(shrunk, grown);
+    let contents = unsafe { core::slice::from_raw_parts(shrunk, 12) };
+    assert!(contents.iter().all(|byte| *byte == 0x42));
+
+    unsafe { cache.dealloc(shrunk, layout(12, 8)) };
+}
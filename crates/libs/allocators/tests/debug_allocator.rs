@@ -0,0 +1,154 @@
+mod common;
+
+use allocators::{
+    composition::debug_allocator::{
+        DebugAllocator, POISON, QUARANTINE_SIZE, UNINITIALIZED,
+    },
+    traits::Allocator,
+};
+use common::HostBacking;
+use core::{
+    alloc::{AllocErr, Layout},
+    ptr::NonNull,
+    slice,
+};
+
+const SIZE: usize = 24;
+
+/// Counts the blocks the debug allocator gives back
+#[derive(Default)]
+struct Counting {
+    inner: HostBacking,
+    freed: usize,
+}
+
+impl Allocator for Counting {
+    fn alloc(
+        &mut self,
+        layout: Layout,
+    ) -> Result<(NonNull<u8>, usize), AllocErr> {
+        self.inner.alloc(layout)
+    }
+
+    unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        self.freed += 1;
+        self.inner.dealloc(ptr, layout)
+    }
+}
+
+fn layout() -> Layout {
+    Layout::from_size_align(SIZE, 8).unwrap()
+}
+
+fn is_filled(ptr: NonNull<u8>, value: u8) -> bool {
+    unsafe { slice::from_raw_parts(ptr.as_ptr(), SIZE) }
+        .iter()
+        .all(|byte| *byte == value)
+}
+
+#[test]
+pub fn test_allocations_are_filled_and_poisoned() {
+    let mut allocator = DebugAllocator::new(Counting::default());
+
+    let (ptr, size) = allocator.alloc(layout()).unwrap();
+    assert_eq!(size, SIZE);
+    assert!(is_filled(ptr, UNINITIALIZED));
+
+    unsafe {
+        ptr.as_ptr().write_bytes(0x42, SIZE);
+        allocator.dealloc(ptr, layout());
+    }
+
+    // The block is still quarantined, so its poison can be inspected
+    assert!(is_filled(ptr, POISON));
+    assert_eq!(allocator.inner().freed, 0);
+    allocator.check();
+}
+
+#[test]
+#[should_panic(expected = "overflow")]
+pub fn test_overflow_is_detected_on_free() {
+    let mut allocator = DebugAllocator::new(Counting::default());
+    let (ptr, _) = allocator.alloc(layout()).unwrap();
+
+    unsafe {
+        ptr.as_ptr().add(SIZE).write(0);
+        allocator.dealloc(ptr, layout());
+    }
+}
+
+#[test]
+#[should_panic(expected = "underflow")]
+pub fn test_underflow_is_detected_by_check() {
+    let mut allocator = DebugAllocator::new(Counting::default());
+    let (ptr, _) = allocator.alloc(layout()).unwrap();
+
+    unsafe {
+        ptr.as_ptr().sub(1).write(0);
+    }
+    allocator.check();
+}
+
+#[test]
+#[should_panic(expected = "write after free")]
+pub fn test_write_after_free_is_detected_by_check() {
+    let mut allocator = DebugAllocator::new(Counting::default());
+    let (ptr, _) = allocator.alloc(layout()).unwrap();
+
+    unsafe {
+        allocator.dealloc(ptr, layout());
+        ptr.as_ptr().write(0);
+    }
+    allocator.check();
+}
+
+#[test]
+#[should_panic(expected = "Double free")]
+pub fn test_double_free_is_detected() {
+    let mut allocator = DebugAllocator::new(Counting::default());
+    let (ptr, _) = allocator.alloc(layout()).unwrap();
+
+    unsafe {
+        allocator.dealloc(ptr, layout());
+        allocator.dealloc(ptr, layout());
+    }
+}
+
+#[test]
+pub fn test_quarantine_evicts_oldest() {
+    let mut allocator = DebugAllocator::new(Counting::default());
+    let allocations: Vec<_> = (0..QUARANTINE_SIZE + 2)
+        .map(|_| allocator.alloc(layout()).unwrap().0)
+        .collect();
+
+    for (index, ptr) in allocations.iter().enumerate() {
+        unsafe {
+            allocator.dealloc(*ptr, layout());
+        }
+
+        let evicted = (index + 1).saturating_sub(QUARANTINE_SIZE);
+        assert_eq!(allocator.inner().freed, evicted);
+    }
+
+    // Only the oldest allocations left the quarantine
+    assert!(allocations[2..].iter().all(|ptr| is_filled(*ptr, POISON)));
+    allocator.check();
+}
+
+#[test]
+#[should_panic(expected = "write after free")]
+pub fn test_write_after_free_is_detected_on_eviction() {
+    let mut allocator = DebugAllocator::new(Counting::default());
+    let allocations: Vec<_> = (0..=QUARANTINE_SIZE)
+        .map(|_| allocator.alloc(layout()).unwrap().0)
+        .collect();
+
+    unsafe {
+        allocator.dealloc(allocations[0], layout());
+        allocations[0].as_ptr().add(SIZE - 1).write(0);
+
+        for ptr in &allocations[1..] {
+            allocator.dealloc(*ptr, layout());
+        }
+    }
+}
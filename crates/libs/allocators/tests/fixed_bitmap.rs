@@ -0,0 +1,39 @@
+use allocators::{
+    allocators::fixed_bitmap::{block_alignment, FixedBitMap, MAX_ALIGNMENT},
+    traits::{Allocator, OwnerCheck},
+};
+use core::alloc::Layout;
+
+#[test]
+pub fn test_blocks_are_aligned() {
+    let mut bitmap = FixedBitMap::<{ 64 }, { 8 }, { 64 }>::new();
+    let cache_line = Layout::from_size_align(64, 64).unwrap();
+
+    let allocations: Vec<_> = (0..8)
+        .map(|_| bitmap.alloc(cache_line).unwrap().0)
+        .collect();
+    for ptr in allocations.iter() {
+        assert_eq!(ptr.as_ptr() as usize % 64, 0);
+        assert!(bitmap.is_owner(*ptr, cache_line));
+    }
+    assert!(bitmap.alloc(cache_line).is_err());
+
+    for ptr in allocations {
+        unsafe {
+            bitmap.dealloc(ptr, cache_line);
+        }
+    }
+
+    // Blocks can't be aligned further than their alignment
+    let over_aligned = Layout::from_size_align(64, 128).unwrap();
+    assert!(bitmap.alloc(over_aligned).is_err());
+}
+
+#[test]
+pub fn test_block_alignment() {
+    assert_eq!(block_alignment(16), 16);
+    assert_eq!(block_alignment(48), 16);
+    assert_eq!(block_alignment(64), 64);
+    assert_eq!(block_alignment(4096), MAX_ALIGNMENT);
+    assert_eq!(block_alignment(8192), MAX_ALIGNMENT);
+}
@@ -0,0 +1,114 @@
+use allocators::utils::hierarchical_bitset::HierarchicalBitSet;
+
+/// A small deterministic generator, so failures can be reproduced
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, bound: usize) -> usize {
+        (self.next() % bound as u64) as usize
+    }
+}
+
+/// Apply random operations to a set and a plain model and compare them after each one
+fn check_against_model<const CAPACITY: usize>(seed: u64, operations: usize) {
+    let mut random = XorShift(seed);
+    let mut set = HierarchicalBitSet::<{ CAPACITY }>::new();
+    let mut model = vec![false; CAPACITY];
+
+    for _ in 0..operations {
+        match random.below(6) {
+            0 | 1 => {
+                let index = random.below(CAPACITY);
+                assert!(set.insert(index).is_some());
+                model[index] = true;
+            },
+            2 | 3 => {
+                let index = random.below(CAPACITY);
+                assert!(set.remove(index).is_some());
+                model[index] = false;
+            },
+            operation => {
+                let start = random.below(CAPACITY + 1);
+                let end = start + random.below(CAPACITY + 1 - start);
+                let value = operation == 4;
+
+                assert!(set.set_range(start..end, value).is_some());
+                for bit in &mut model[start..end] {
+                    *bit = value;
+                }
+            },
+        }
+
+        assert_eq!(set.len(), model.iter().filter(|bit| **bit).count());
+        assert_eq!(set.find_first_unset(), model.iter().position(|bit| !*bit));
+        assert_eq!(set.find_first_set(), model.iter().position(|bit| *bit));
+        assert_eq!(set.is_full(), model.iter().all(|bit| *bit));
+        assert_eq!(set.is_empty(), model.iter().all(|bit| !*bit));
+    }
+
+    for (index, bit) in model.iter().enumerate() {
+        assert_eq!(set.contains(index), Some(*bit));
+    }
+}
+
+#[test]
+pub fn test_matches_model() {
+    check_against_model::<{ 1000 }>(0x2545_F491_4F6C_DD1D, 20_000);
+}
+
+#[test]
+pub fn test_matches_model_with_several_summary_groups() {
+    check_against_model::<{ 64 * 64 * 2 + 3 }>(0x9E37_79B9_7F4A_7C15, 5_000);
+}
+
+#[test]
+pub fn test_first_unset_while_filling() {
+    const CAPACITY: usize = 64 * 64 + 100;
+
+    let mut set = HierarchicalBitSet::<{ CAPACITY }>::new();
+    assert_eq!(set.find_first_set(), None);
+
+    for i in 0..CAPACITY {
+        assert_eq!(set.find_first_unset(), Some(i));
+        assert!(set.insert(i).is_some());
+        assert_eq!(set.find_first_set(), Some(0));
+    }
+    assert_eq!(set.find_first_unset(), None);
+    assert!(set.is_full());
+
+    assert!(set.remove_range(100..CAPACITY - 1).is_some());
+    assert_eq!(set.find_first_unset(), Some(100));
+    assert!(set.remove_range(0..100).is_some());
+    assert_eq!(set.find_first_set(), Some(CAPACITY - 1));
+
+    assert!(set.insert(CAPACITY).is_none());
+    assert!(set.insert_range(0..CAPACITY + 1).is_none());
+}
+
+#[test]
+pub fn test_searches_skip_along_the_second_level() {
+    const CAPACITY: usize = 64 * 64 * 8 + 5;
+
+    let mut set = HierarchicalBitSet::<{ CAPACITY }>::new();
+    assert!(set.insert_range(0..CAPACITY).is_some());
+
+    // Only the last bit is free, the first groups get filled and emptied last
+    assert!(set.remove(CAPACITY - 1).is_some());
+    assert!(set.remove_range(0..64 * 64 + 1).is_some());
+    assert!(set.insert_range(0..64 * 64 + 1).is_some());
+    assert_eq!(set.find_first_unset(), Some(CAPACITY - 1));
+
+    // Only the last bit is set
+    assert!(set.remove_range(0..CAPACITY).is_some());
+    assert!(set.insert(CAPACITY - 1).is_some());
+    assert!(set.insert_range(0..64 * 64 + 1).is_some());
+    assert!(set.remove_range(0..64 * 64 + 1).is_some());
+    assert_eq!(set.find_first_set(), Some(CAPACITY - 1));
+}
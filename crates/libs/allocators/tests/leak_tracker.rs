@@ -0,0 +1,147 @@
+mod common;
+
+use allocators::{
+    composition::leak_tracker::{
+        LeakTracker, Snapshot, SnapshotDifference, TRACKED_ALLOCATIONS,
+    },
+    traits::Allocator,
+};
+use common::HostBacking;
+use core::{alloc::Layout, ptr::NonNull};
+
+fn layout(size: usize) -> Layout {
+    Layout::from_size_align(size, 8).unwrap()
+}
+
+fn leaks(
+    tracker: &LeakTracker<HostBacking>,
+    earlier: &Snapshot,
+    later: &Snapshot,
+) -> Vec<(usize, usize)> {
+    let mut leaks = Vec::new();
+    tracker.for_each_leak(earlier, later, |record| {
+        leaks.push((record.address, record.layout.size()))
+    });
+    leaks.sort();
+    leaks
+}
+
+fn free_all(
+    tracker: &mut LeakTracker<HostBacking>,
+    allocations: Vec<(NonNull<u8>, usize)>,
+) {
+    for (ptr, size) in allocations {
+        unsafe { tracker.dealloc(ptr, layout(size)) };
+    }
+}
+
+#[test]
+pub fn test_allocations_are_recorded() {
+    let mut tracker = LeakTracker::new(HostBacking);
+    let empty = tracker.snapshot();
+
+    let (first, _) = tracker.alloc(layout(16)).unwrap();
+    let (second, _) = tracker.alloc(layout(32)).unwrap();
+
+    let snapshot = tracker.snapshot();
+    assert_eq!(snapshot.live_allocations, 2);
+    assert_eq!(snapshot.live_bytes, 48);
+    assert_eq!(snapshot.untracked, 0);
+
+    let mut expected = vec![
+        (first.as_ptr() as usize, 16),
+        (second.as_ptr() as usize, 32),
+    ];
+    expected.sort();
+    assert_eq!(leaks(&tracker, &empty, &snapshot), expected);
+
+    free_all(&mut tracker, vec![(first, 16), (second, 32)]);
+}
+
+#[test]
+pub fn test_freed_allocations_are_removed() {
+    let mut tracker = LeakTracker::new(HostBacking);
+    let empty = tracker.snapshot();
+
+    let (ptr, _) = tracker.alloc(layout(16)).unwrap();
+    free_all(&mut tracker, vec![(ptr, 16)]);
+
+    let snapshot = tracker.snapshot();
+    assert_eq!(snapshot.live_allocations, 0);
+    assert_eq!(snapshot.live_bytes, 0);
+
+    let difference = tracker.difference(&empty, &snapshot);
+    assert!(difference.is_empty());
+    assert!(leaks(&tracker, &empty, &snapshot).is_empty());
+}
+
+#[test]
+pub fn test_difference_only_counts_allocations_between_snapshots() {
+    let mut tracker = LeakTracker::new(HostBacking);
+
+    let (before, _) = tracker.alloc(layout(8)).unwrap();
+    let earlier = tracker.snapshot();
+
+    let (freed, _) = tracker.alloc(layout(16)).unwrap();
+    let (leaked, _) = tracker.alloc(layout(64)).unwrap();
+    free_all(&mut tracker, vec![(freed, 16)]);
+    let later = tracker.snapshot();
+
+    let (after, _) = tracker.alloc(layout(8)).unwrap();
+
+    assert_eq!(
+        tracker.difference(&earlier, &later),
+        SnapshotDifference {
+            allocations: 1,
+            bytes: 64,
+            untracked: 0,
+        }
+    );
+    assert_eq!(
+        leaks(&tracker, &earlier, &later),
+        vec![(leaked.as_ptr() as usize, 64)]
+    );
+
+    free_all(&mut tracker, vec![(before, 8), (leaked, 64), (after, 8)]);
+}
+
+#[test]
+pub fn test_allocations_beyond_capacity_are_untracked() {
+    let mut tracker = LeakTracker::new(HostBacking);
+    let empty = tracker.snapshot();
+
+    let mut allocations: Vec<_> = (0..TRACKED_ALLOCATIONS + 3)
+        .map(|_| (tracker.alloc(layout(8)).unwrap().0, 8))
+        .collect();
+
+    let full = tracker.snapshot();
+    assert_eq!(full.live_allocations, TRACKED_ALLOCATIONS);
+    assert_eq!(full.live_bytes, TRACKED_ALLOCATIONS * 8);
+    assert_eq!(full.untracked, 3);
+    assert_eq!(
+        tracker.difference(&empty, &full),
+        SnapshotDifference {
+            allocations: TRACKED_ALLOCATIONS,
+            bytes: TRACKED_ALLOCATIONS * 8,
+            untracked: 3,
+        }
+    );
+
+    // Freeing an untracked allocation changes nothing
+    let untracked = allocations.pop().unwrap();
+    free_all(&mut tracker, vec![untracked]);
+    assert_eq!(tracker.snapshot().live_allocations, TRACKED_ALLOCATIONS);
+
+    // Freeing a tracked allocation makes room for the next one
+    let tracked = allocations.remove(0);
+    free_all(&mut tracker, vec![tracked]);
+    assert_eq!(tracker.snapshot().live_allocations, TRACKED_ALLOCATIONS - 1);
+
+    allocations.push((tracker.alloc(layout(8)).unwrap().0, 8));
+    let refilled = tracker.snapshot();
+    assert_eq!(refilled.live_allocations, TRACKED_ALLOCATIONS);
+    assert_eq!(refilled.untracked, 3);
+
+    free_all(&mut tracker, allocations);
+    assert_eq!(tracker.snapshot().live_allocations, 0);
+}
@@ -0,0 +1,118 @@
+mod common;
+
+use allocators::{
+    allocators::fixed_bitmap::FixedBitMap,
+    composition::linked_chain::LinkedChain,
+    traits::{Allocator, Occupancy, Reclaim},
+};
+use common::HostBacking;
+use core::{alloc::Layout, ptr::NonNull};
+
+const CAPACITY: usize = 8;
+
+type Chain<const CACHED: usize> = LinkedChain<
+    FixedBitMap<{ 16 }, { CAPACITY }, { 16 }>,
+    HostBacking,
+    { CACHED },
+>;
+
+fn fill<const CACHED: usize>(
+    chain: &mut Chain<{ CACHED }>,
+    count: usize,
+) -> Vec<NonNull<u8>> {
+    (0..count)
+        .map(|_| chain.alloc(Layout::new::<u64>()).unwrap().0)
+        .collect()
+}
+
+fn free<const CACHED: usize>(
+    chain: &mut Chain<{ CACHED }>,
+    allocations: Vec<NonNull<u8>>,
+) {
+    for ptr in allocations {
+        unsafe {
+            chain.dealloc(ptr, Layout::new::<u64>());
+        }
+    }
+}
+
+#[test]
+pub fn test_empty_blocks_are_released() {
+    let mut chain = Chain::<{ 1 }>::default();
+
+    let allocations = fill(&mut chain, CAPACITY * 4);
+    assert_eq!(chain.blocks(), 4);
+
+    free(&mut chain, allocations);
+    assert!(chain.is_unused());
+    assert_eq!(chain.blocks(), 1);
+}
+
+#[test]
+pub fn test_cached_block_is_reused() {
+    let mut chain = Chain::<{ 2 }>::default();
+
+    let allocations = fill(&mut chain, CAPACITY * 2);
+    free(&mut chain, allocations);
+    assert_eq!(chain.blocks(), 2);
+
+    let allocations = fill(&mut chain, CAPACITY * 3);
+    assert_eq!(chain.blocks(), 3);
+
+    free(&mut chain, allocations);
+    assert_eq!(chain.blocks(), 2);
+}
+
+#[test]
+pub fn test_partial_blocks_are_preferred() {
+    let mut chain = Chain::<{ 0 }>::default();
+
+    let mut allocations = fill(&mut chain, CAPACITY * 2);
+    assert_eq!(chain.blocks(), 2);
+
+    // Free one slot in the first block, the next allocation has to reuse it
+    let freed = allocations.remove(0);
+    unsafe {
+        chain.dealloc(freed, Layout::new::<u64>());
+    }
+    allocations.push(chain.alloc(Layout::new::<u64>()).unwrap().0);
+    assert_eq!(chain.blocks(), 2);
+
+    free(&mut chain, allocations);
+    assert_eq!(chain.blocks(), 0);
+}
+
+#[test]
+pub fn test_allocations_keep_their_slot() {
+    let mut chain = Chain::<{ 0 }>::default();
+
+    let ptr = chain.alloc(Layout::new::<u32>()).unwrap().0;
+    unsafe {
+        let (grown, size) =
+            chain.realloc(ptr, Layout::new::<u32>(), 16).unwrap();
+        assert_eq!((grown, size), (ptr, 16));
+
+        assert!(chain
+            .grow_in_place(ptr, Layout::from_size_align(16, 4).unwrap(), 17)
+            .is_err());
+
+        chain.dealloc(ptr, Layout::from_size_align(16, 4).unwrap());
+    }
+    assert_eq!(chain.blocks(), 0);
+}
+
+#[test]
+pub fn test_reclaim_releases_cached_blocks() {
+    let mut chain = Chain::<{ 2 }>::default();
+
+    let allocations = fill(&mut chain, CAPACITY * 3);
+    let kept = allocations[0];
+    free(&mut chain, allocations[1..].to_vec());
+    assert_eq!(chain.blocks(), 3);
+
+    assert_eq!(chain.reclaim(), Chain::<{ 2 }>::block_layout().size() * 2);
+    assert_eq!(chain.blocks(), 1);
+    assert_eq!(chain.reclaim(), 0);
+
+    free(&mut chain, vec![kept]);
+}
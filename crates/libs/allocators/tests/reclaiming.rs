@@ -0,0 +1,80 @@
+use allocators::{
+    composition::reclaiming::{
+        register_reclaim_callback, unregister_reclaim_callback, Reclaiming,
+    },
+    traits::SharedReclaim,
+};
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    ptr::null_mut,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use std::alloc::System;
+
+/// Bytes that can still be allocated from the Budgeted allocator
+static BUDGET: AtomicUsize = AtomicUsize::new(0);
+/// Bytes the callback gives back, as if a subsystem dropped its cache
+static CACHED: AtomicUsize = AtomicUsize::new(0);
+
+/// Fails once its budget is used up
+struct Budgeted;
+
+unsafe impl GlobalAlloc for Budgeted {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let budget = BUDGET.load(Ordering::SeqCst);
+        if budget < layout.size() {
+            return null_mut();
+        }
+
+        BUDGET.store(budget - layout.size(), Ordering::SeqCst);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        BUDGET.fetch_add(layout.size(), Ordering::SeqCst);
+        System.dealloc(ptr, layout)
+    }
+}
+
+impl SharedReclaim for Budgeted {
+    fn reclaim(&self) -> usize {
+        0
+    }
+}
+
+fn drop_cache() -> usize {
+    let released = CACHED.swap(0, Ordering::SeqCst);
+    BUDGET.fetch_add(released, Ordering::SeqCst);
+    released
+}
+
+#[test]
+pub fn test_failed_allocations_reclaim() {
+    let allocator = Reclaiming::new(Budgeted);
+    let layout = Layout::from_size_align(256, 8).unwrap();
+
+    BUDGET.store(0, Ordering::SeqCst);
+    CACHED.store(1024, Ordering::SeqCst);
+
+    // Nothing to reclaim without a callback
+    assert!(unsafe { allocator.alloc(layout) }.is_null());
+
+    register_reclaim_callback(drop_cache).unwrap();
+
+    let ptr = unsafe { allocator.alloc(layout) };
+    assert!(!ptr.is_null());
+    assert_eq!(CACHED.load(Ordering::SeqCst), 0);
+    assert_eq!(BUDGET.load(Ordering::SeqCst), 1024 - 256);
+
+    unregister_reclaim_callback(drop_cache).unwrap();
+    assert!(unregister_reclaim_callback(drop_cache).is_err());
+
+    BUDGET.store(0, Ordering::SeqCst);
+    CACHED.store(1024, Ordering::SeqCst);
+    assert!(unsafe { allocator.alloc(layout) }.is_null());
+    assert_eq!(allocator.reclaim(), 0);
+
+    unsafe {
+        allocator.dealloc(ptr, layout);
+    }
+}
@@ -0,0 +1,48 @@
+mod common;
+
+use allocators::{
+    allocators::fixed_bitmap::FixedBitMap,
+    composition::size_deciding::SizeDeciding, traits::Allocator,
+};
+use common::HostBacking;
+use core::alloc::Layout;
+
+type Small = FixedBitMap<{ 32 }, { 4 }, { 16 }>;
+
+fn layout(size: usize) -> Layout {
+    Layout::from_size_align(size, 8).unwrap()
+}
+
+#[test]
+pub fn test_realloc_crosses_threshold() {
+    let mut allocator = SizeDeciding::<Small, HostBacking, { 32 }>::new(
+        Small::new(),
+        HostBacking,
+    );
+
+    unsafe {
+        let (ptr, _) = allocator.alloc(layout(8)).unwrap();
+        ptr.as_ptr().write_bytes(0xAB, 8);
+
+        // Growing within the small allocator keeps the slot
+        let (grown, _) = allocator.realloc(ptr, layout(8), 32).unwrap();
+        assert_eq!(grown, ptr);
+
+        // Crossing the threshold moves the allocation and its contents
+        let (large, size) = allocator.realloc(ptr, layout(32), 100).unwrap();
+        assert_ne!(large, ptr);
+        assert!(size >= 100);
+        assert!(core::slice::from_raw_parts(large.as_ptr(), 8)
+            .iter()
+            .all(|byte| *byte == 0xAB));
+
+        // Shrinking back reuses the freed slot
+        let (small, _) = allocator.realloc(large, layout(100), 16).unwrap();
+        assert_eq!(small, ptr);
+        assert!(core::slice::from_raw_parts(small.as_ptr(), 8)
+            .iter()
+            .all(|byte| *byte == 0xAB));
+
+        allocator.dealloc(small, layout(16));
+    }
+}
@@ -1,24 +1,39 @@
+use crate::error::LoadError;
 use core::ops::Range;
 use goblin::elf::{program_header::PT_LOAD, Elf};
 use x86_64::VirtAddr;
 
-pub fn elf_address_range(elf: &Elf) -> Range<VirtAddr> {
-    elf.program_headers
+/// The address range covered by the loadable segments
+///
+/// Fails if a segment does not fit into the binary or the address space,
+/// so their ranges can be computed without overflowing afterwards.
+pub fn elf_address_range(elf: &Elf) -> Result<Range<VirtAddr>, LoadError> {
+    let mut range: Option<Range<VirtAddr>> = None;
+
+    for (index, header) in elf
+        .program_headers
         .iter()
-        .filter(|header| header.p_type == PT_LOAD)
-        .fold(None, |acc: Option<Range<VirtAddr>>, it| {
-            acc.map(|acc| {
-                let vm_range: Range<usize> = it.vm_range();
-                (acc.start.min(VirtAddr::new(vm_range.start as u64)))
-                    ..(acc.end.max(VirtAddr::new(vm_range.end as u64)))
-            })
-            .or_else(|| {
-                Some({
-                    let range = it.vm_range();
-                    VirtAddr::new(range.start as u64)
-                        ..VirtAddr::new(range.end as u64)
-                })
-            })
-        })
-        .unwrap_or(VirtAddr::new(0)..VirtAddr::new(0))
+        .enumerate()
+        .filter(|(_, header)| header.p_type == PT_LOAD)
+    {
+        let invalid = || LoadError::InvalidSegment(index);
+
+        let start = VirtAddr::try_new(header.p_vaddr).map_err(|_| invalid())?;
+        let end = header
+            .p_vaddr
+            .checked_add(header.p_memsz)
+            .and_then(|end| VirtAddr::try_new(end).ok())
+            .ok_or_else(invalid)?;
+        header
+            .p_offset
+            .checked_add(header.p_filesz)
+            .ok_or_else(invalid)?;
+
+        range = Some(match range {
+            Some(range) => range.start.min(start)..range.end.max(end),
+            None => start..end,
+        });
+    }
+
+    Ok(range.unwrap_or(VirtAddr::new(0)..VirtAddr::new(0)))
 }
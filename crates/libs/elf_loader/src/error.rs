@@ -0,0 +1,72 @@
+use core::fmt;
+use goblin::elf::{header::EM_X86_64, reloc::r_to_str};
+
+/// Why a binary could not be loaded
+#[derive(Debug)]
+pub enum LoadError {
+    /// The binary is not a valid ELF file
+    Parse(goblin::error::Error),
+    /// Only 64 bit binaries are supported
+    Not64Bit,
+    /// The binary is not position independent, so it can't be relocated
+    NotRelocatable,
+    /// The loadable segment at this program header index does not fit into the binary
+    /// or the address space
+    InvalidSegment(usize),
+    /// The entry point is outside of the loadable segments
+    InvalidEntry(u64),
+    /// The pages for the binary could not be allocated
+    OutOfMemory,
+    /// A relocation at this offset would write outside of the loadable segments
+    RelocationOutOfBounds(u64),
+    /// The relocation type is not supported
+    UnsupportedRelocation(u32),
+    /// A relocation results in this non-canonical address
+    NonCanonicalRelocation(u64),
+}
+
+impl From<goblin::error::Error> for LoadError {
+    fn from(error: goblin::error::Error) -> Self {
+        LoadError::Parse(error)
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Parse(error) => write!(f, "Invalid ELF file: {}", error),
+            LoadError::Not64Bit => write!(f, "ELF file is 32 bit"),
+            LoadError::NotRelocatable => {
+                write!(f, "ELF file is not position independent")
+            },
+            LoadError::InvalidSegment(index) => {
+                write!(f, "Segment {} is out of bounds", index)
+            },
+            LoadError::InvalidEntry(entry) => write!(
+                f,
+                "Entry point 0x{:X} is outside of the segments",
+                entry
+            ),
+            LoadError::OutOfMemory => {
+                write!(f, "Could not allocate pages for the binary")
+            },
+            LoadError::RelocationOutOfBounds(offset) => {
+                write!(
+                    f,
+                    "Relocation at offset 0x{:X} is out of bounds",
+                    offset
+                )
+            },
+            LoadError::UnsupportedRelocation(relocation) => write!(
+                f,
+                "Unsupported relocation type: {}",
+                r_to_str(*relocation, EM_X86_64)
+            ),
+            LoadError::NonCanonicalRelocation(address) => write!(
+                f,
+                "Relocation results in non-canonical address 0x{:X}",
+                address
+            ),
+        }
+    }
+}
@@ -1,17 +1,24 @@
 #![no_std]
 
 pub(crate) mod analysis;
+pub mod error;
 pub mod loaded_object;
 pub mod parameters;
 pub(crate) mod relocations;
 
 use crate::{
-    analysis::elf_address_range, loaded_object::LoadedObject,
-    parameters::LoadParameters, relocations::apply_relocations,
+    analysis::elf_address_range,
+    error::LoadError,
+    loaded_object::LoadedObject,
+    parameters::{LoadParameters, PagePermissions},
+    relocations::apply_relocations,
 };
 use core::{ops::Range, slice::from_raw_parts_mut};
 use goblin::elf::{program_header::PT_LOAD, Elf};
-use x86_64::VirtAddr;
+use x86_64::{
+    structures::paging::{page::PageRange, Page},
+    VirtAddr,
+};
 
 fn range_size(r: &Range<usize>) -> usize {
     r.end - r.start
@@ -22,11 +29,12 @@ fn load_sections(
     binary: &[u8],
     buffer: &mut [u8],
     elf_base: VirtAddr,
-) {
-    for header in elf
+) -> Result<(), LoadError> {
+    for (index, header) in elf
         .program_headers
         .iter()
-        .filter(|header| header.p_type == PT_LOAD)
+        .enumerate()
+        .filter(|(_, header)| header.p_type == PT_LOAD)
     {
         let memory_range: Range<usize> = header.vm_range();
         let file_range: Range<usize> = header.file_range();
@@ -40,70 +48,132 @@ fn load_sections(
         // How big is this section in the binary?
         let file_size = range_size(&file_range);
 
-        assert!(file_size <= memory_size);
+        if file_size > memory_size {
+            return Err(LoadError::InvalidSegment(index));
+        }
+
+        let destination =
+            buffer.get_mut(memory_base..(memory_base + file_size));
+        let source = binary.get(file_range);
+        match (destination, source) {
+            (Some(destination), Some(source)) => {
+                destination.copy_from_slice(source)
+            },
+            _ => return Err(LoadError::InvalidSegment(index)),
+        }
+    }
+
+    Ok(())
+}
 
-        (&mut buffer[memory_base..(memory_base + file_size)])
-            .copy_from_slice(&binary[file_range]);
+/// Copy the segments of elf into buffer and relocate them to load_base
+fn fill_buffer(
+    elf: &Elf,
+    binary: &[u8],
+    buffer: &mut [u8],
+    elf_base: VirtAddr,
+    load_base: VirtAddr,
+) -> Result<(), LoadError> {
+    for it in buffer.iter_mut() {
+        *it = 0;
     }
+
+    load_sections(elf, binary, buffer, elf_base)?;
+    apply_relocations(elf, buffer, elf_base, load_base)
 }
 
-pub fn load<P>(binary: &[u8], mut parameters: P) -> LoadedObject
+/// Set the permissions of each loadable segment at its relocated address
+fn set_segment_permissions<P>(
+    elf: &Elf,
+    parameters: &mut P,
+    elf_base: VirtAddr,
+    load_base: VirtAddr,
+) where
+    P: LoadParameters,
+{
+    for header in elf
+        .program_headers
+        .iter()
+        .filter(|header| header.p_type == PT_LOAD && header.p_memsz != 0)
+    {
+        let start = load_base + (header.p_vaddr - elf_base.as_u64());
+        let end = start + header.p_memsz;
+
+        parameters.set_permissions(
+            PageRange {
+                start: Page::containing_address(start),
+                end: Page::containing_address(end - 1u64) + 1,
+            },
+            PagePermissions {
+                read: header.is_read(),
+                write: header.is_write(),
+                execute: header.is_executable(),
+            },
+        );
+    }
+}
+
+/// Load a position independent 64 bit ELF binary
+///
+/// If loading fails after the pages were allocated, they are deallocated again.
+/// Otherwise, the permissions of its segments are set at the relocated addresses.
+pub fn load<P>(
+    binary: &[u8],
+    mut parameters: P,
+) -> Result<LoadedObject, LoadError>
 where
     P: LoadParameters,
 {
-    match Elf::parse(binary) {
-        Ok(elf) => {
-            if !elf.is_64 {
-                panic!("Elf is 32 bit");
-            }
-            if !elf.is_lib {
-                panic!("Elf is not relocatable");
-            }
-
-            let elf_address_range = elf_address_range(&elf);
-            let (memory, relocation_location) = parameters
-                .allocate_pages(
-                    (elf_address_range.end - elf_address_range.start) as usize,
-                )
-                .unwrap();
-
-            let mut buffer = unsafe {
-                from_raw_parts_mut(
-                    memory.start.start_address().as_mut_ptr::<u8>(),
-                    (memory.end.start_address() - memory.start.start_address())
-                        as usize,
-                )
-            };
-
-            for it in buffer.iter_mut() {
-                *it = 0;
-            }
-
-            load_sections(&elf, binary, &mut buffer, elf_address_range.start);
-
-            let load_base = relocation_location.start.start_address();
-
-            apply_relocations(
-                &elf,
-                &mut buffer,
-                elf_address_range.start,
-                load_base,
-            );
-
-            let entry = {
-                let entry = VirtAddr::new(elf.entry) - elf_address_range.start;
-                assert!(
-                    entry < elf_address_range.end - elf_address_range.start
-                );
-                load_base + entry
-            };
-
-            LoadedObject {
-                memory,
-                relocation_location,
-                entry,
-            }
-        },
-        Err(e) => unimplemented!("Elf parse error: {:?}", e),
+    let elf = Elf::parse(binary)?;
+
+    if !elf.is_64 {
+        return Err(LoadError::Not64Bit);
+    }
+    if !elf.is_lib {
+        return Err(LoadError::NotRelocatable);
+    }
+
+    let elf_address_range = elf_address_range(&elf)?;
+
+    let entry = VirtAddr::try_new(elf.entry)
+        .ok()
+        .filter(|entry| elf_address_range.contains(entry))
+        .ok_or(LoadError::InvalidEntry(elf.entry))?
+        - elf_address_range.start;
+
+    let (memory, relocation_location) = parameters
+        .allocate_pages(
+            (elf_address_range.end - elf_address_range.start) as usize,
+        )
+        .ok_or(LoadError::OutOfMemory)?;
+
+    let buffer = unsafe {
+        from_raw_parts_mut(
+            memory.start.start_address().as_mut_ptr::<u8>(),
+            (memory.end.start_address() - memory.start.start_address())
+                as usize,
+        )
+    };
+
+    let load_base = relocation_location.start.start_address();
+
+    if let Err(error) =
+        fill_buffer(&elf, binary, buffer, elf_address_range.start, load_base)
+    {
+        parameters.deallocate_pages(memory);
+        return Err(error);
     }
+
+    set_segment_permissions(
+        &elf,
+        &mut parameters,
+        elf_address_range.start,
+        load_base,
+    );
+
+    Ok(LoadedObject {
+        memory,
+        relocation_location,
+        entry: load_base + entry,
+    })
 }
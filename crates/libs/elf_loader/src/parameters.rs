@@ -22,6 +22,9 @@ pub trait LoadParameters {
     fn deallocate_pages(&mut self, pages: PageRange<Size4KiB>);
 
     /// Set the permissions for a page range
+    ///
+    /// The range is given at the location the binary was relocated to.
+    /// Pages shared by segments are passed once for each of them.
     fn set_permissions(
         &mut self,
         pages: PageRange<Size4KiB>,
@@ -1,7 +1,7 @@
+use crate::error::LoadError;
 use byteorder::{ByteOrder, LittleEndian};
-use core::num::Wrapping;
+use core::{mem::size_of, num::Wrapping};
 use goblin::elf::{
-    header::EM_X86_64,
     reloc::{Reloc, R_X86_64_RELATIVE},
     Elf,
 };
@@ -17,33 +17,43 @@ impl Relocation {
         Relocation { data }
     }
 
+    /// The bytes of program the relocation writes to
+    fn position<'program>(
+        &self,
+        program: &'program mut [u8],
+        size: usize,
+    ) -> Result<&'program mut [u8], LoadError> {
+        let offset = self.data.r_offset as usize;
+
+        offset
+            .checked_add(size)
+            .and_then(move |end| program.get_mut(offset..end))
+            .ok_or(LoadError::RelocationOutOfBounds(self.data.r_offset))
+    }
+
     pub fn apply(
         &self,
         _elf_base: VirtAddr,
         load_base: VirtAddr,
         program: &mut [u8],
-    ) {
-        let offset = self.data.r_offset as usize;
-        if offset >= program.len() {
-            unimplemented!("Offset out of bounds")
-        }
-
-        let position = &mut program[offset..];
-
+    ) -> Result<(), LoadError> {
         match self.data.r_type {
             R_X86_64_RELATIVE => {
-                let value = VirtAddr::try_new(
-                    (Wrapping(load_base.as_u64())
-                        + Wrapping(self.data.r_addend.unwrap() as u64))
-                    .0,
-                )
-                .unwrap();
+                let position = self.position(program, size_of::<u64>())?;
+
+                // Relocations without an explicit addend store it at their position
+                let addend = self.data.r_addend.map_or_else(
+                    || LittleEndian::read_u64(position),
+                    |addend| addend as u64,
+                );
+                let value = (Wrapping(load_base.as_u64()) + Wrapping(addend)).0;
+                let value = VirtAddr::try_new(value)
+                    .map_err(|_| LoadError::NonCanonicalRelocation(value))?;
+
                 LittleEndian::write_u64(position, value.as_u64());
+                Ok(())
             },
-            unknown => panic!(
-                "Unknown relocation type: {}",
-                goblin::elf::reloc::r_to_str(unknown, EM_X86_64),
-            ),
+            unknown => Err(LoadError::UnsupportedRelocation(unknown)),
         }
     }
 }
@@ -61,8 +71,10 @@ pub fn apply_relocations(
     mut buffer: &mut [u8],
     elf_base: VirtAddr,
     load_base: VirtAddr,
-) {
+) -> Result<(), LoadError> {
     for relocation in relocations(elf) {
-        relocation.apply(elf_base, load_base, &mut buffer);
+        relocation.apply(elf_base, load_base, &mut buffer)?;
     }
+
+    Ok(())
 }
@@ -0,0 +1,307 @@
+use byteorder::{ByteOrder, LittleEndian};
+use elf_loader::{
+    error::LoadError,
+    load,
+    parameters::{LoadParameters, PagePermissions},
+};
+use goblin::elf::{
+    dynamic::{
+        DT_NULL, DT_REL, DT_RELA, DT_RELAENT, DT_RELASZ, DT_RELENT, DT_RELSZ,
+    },
+    header::{EM_386, EM_X86_64, ET_DYN, ET_EXEC},
+    program_header::{PF_R, PF_W, PT_DYNAMIC, PT_LOAD},
+    reloc::{R_X86_64_64, R_X86_64_RELATIVE},
+};
+use std::alloc::{alloc, dealloc, Layout};
+use x86_64::{
+    structures::paging::{page::PageRange, Page, Size4KiB},
+    VirtAddr,
+};
+
+const PAGE_SIZE: usize = 4096;
+
+/// The binaries are a single loadable segment of this size, mapped at address 0
+const BINARY_SIZE: usize = 0x1000;
+const PROGRAM_HEADERS: usize = 0x40;
+const DYNAMIC: usize = 0x100;
+/// Room for two relocations with addends
+const RELA: usize = 0x180;
+/// Room for four relocations without addends
+const REL: usize = 0x1C0;
+/// The relocations write here, it is also the entry point
+const DATA: usize = 0x200;
+
+/// Where the loaded binaries are relocated to
+const LOAD_BASE: u64 = 0x7000_0000_0000;
+
+struct Relocation {
+    offset: u64,
+    kind: u32,
+    /// Relocations without an addend take it from their target
+    addend: Option<i64>,
+}
+
+/// A position independent 64 bit ELF binary with the given relocations
+fn binary(kind: u16, relocations: &[Relocation]) -> Vec<u8> {
+    let mut bytes = vec![0; BINARY_SIZE];
+
+    bytes[0..7].copy_from_slice(&[0x7F, b'E', b'L', b'F', 2, 1, 1]);
+    LittleEndian::write_u16(&mut bytes[16..], kind);
+    LittleEndian::write_u16(&mut bytes[18..], EM_X86_64);
+    LittleEndian::write_u32(&mut bytes[20..], 1);
+    LittleEndian::write_u64(&mut bytes[24..], DATA as u64);
+    LittleEndian::write_u64(&mut bytes[32..], PROGRAM_HEADERS as u64);
+    LittleEndian::write_u16(&mut bytes[52..], 64);
+    LittleEndian::write_u16(&mut bytes[54..], 56);
+    LittleEndian::write_u16(&mut bytes[56..], 2);
+    LittleEndian::write_u16(&mut bytes[58..], 64);
+
+    let segments = [
+        (PT_LOAD, 0, BINARY_SIZE),
+        (PT_DYNAMIC, DYNAMIC, RELA - DYNAMIC),
+    ];
+    for (index, (kind, start, size)) in segments.iter().enumerate() {
+        let header = &mut bytes[PROGRAM_HEADERS + index * 56..];
+        LittleEndian::write_u32(&mut header[0..], *kind);
+        LittleEndian::write_u32(&mut header[4..], PF_R | PF_W);
+        LittleEndian::write_u64(&mut header[8..], *start as u64);
+        LittleEndian::write_u64(&mut header[16..], *start as u64);
+        LittleEndian::write_u64(&mut header[24..], *start as u64);
+        LittleEndian::write_u64(&mut header[32..], *size as u64);
+        LittleEndian::write_u64(&mut header[40..], *size as u64);
+        LittleEndian::write_u64(&mut header[48..], PAGE_SIZE as u64);
+    }
+
+    let (mut rela, mut rel) = (0, 0);
+    for relocation in relocations {
+        let info = u64::from(relocation.kind);
+
+        match relocation.addend {
+            Some(addend) => {
+                let entry = &mut bytes[RELA + rela * 24..];
+                LittleEndian::write_u64(&mut entry[0..], relocation.offset);
+                LittleEndian::write_u64(&mut entry[8..], info);
+                LittleEndian::write_i64(&mut entry[16..], addend);
+                rela += 1;
+            },
+            None => {
+                let entry = &mut bytes[REL + rel * 16..];
+                LittleEndian::write_u64(&mut entry[0..], relocation.offset);
+                LittleEndian::write_u64(&mut entry[8..], info);
+                rel += 1;
+            },
+        }
+    }
+    assert!(RELA + rela * 24 <= REL && REL + rel * 16 <= DATA);
+
+    let dynamic = [
+        (DT_RELA, RELA),
+        (DT_RELASZ, rela * 24),
+        (DT_RELAENT, 24),
+        (DT_REL, REL),
+        (DT_RELSZ, rel * 16),
+        (DT_RELENT, 16),
+        (DT_NULL, 0),
+    ];
+    for (index, (tag, value)) in dynamic.iter().enumerate() {
+        let entry = &mut bytes[DYNAMIC + index * 16..];
+        LittleEndian::write_u64(&mut entry[0..], *tag);
+        LittleEndian::write_u64(&mut entry[8..], *value as u64);
+    }
+
+    bytes
+}
+
+/// A 32 bit ELF binary without any segments
+fn binary_32() -> Vec<u8> {
+    let mut bytes = vec![0; 0x100];
+
+    bytes[0..7].copy_from_slice(&[0x7F, b'E', b'L', b'F', 1, 1, 1]);
+    LittleEndian::write_u16(&mut bytes[16..], ET_DYN);
+    LittleEndian::write_u16(&mut bytes[18..], EM_386);
+    LittleEndian::write_u32(&mut bytes[20..], 1);
+    LittleEndian::write_u16(&mut bytes[40..], 52);
+    LittleEndian::write_u16(&mut bytes[42..], 32);
+    LittleEndian::write_u16(&mut bytes[46..], 40);
+
+    bytes
+}
+
+fn page_layout(pages: usize) -> Layout {
+    Layout::from_size_align(pages * PAGE_SIZE, PAGE_SIZE).unwrap()
+}
+
+/// Loads binaries into host memory and relocates them to LOAD_BASE
+#[derive(Default)]
+struct HostPages {
+    allocated: usize,
+    deallocated: usize,
+    permissions: Vec<(PageRange<Size4KiB>, PagePermissions)>,
+}
+
+impl LoadParameters for &mut HostPages {
+    fn allocate_pages(
+        &mut self,
+        pages: usize,
+    ) -> Option<(PageRange<Size4KiB>, PageRange<Size4KiB>)> {
+        let memory = unsafe { alloc(page_layout(pages)) };
+        let memory =
+            Page::from_start_address(VirtAddr::from_ptr(memory)).ok()?;
+        let relocation =
+            Page::from_start_address(VirtAddr::new(LOAD_BASE)).unwrap();
+        self.allocated += 1;
+
+        Some((
+            PageRange {
+                start: memory,
+                end: memory + pages as u64,
+            },
+            PageRange {
+                start: relocation,
+                end: relocation + pages as u64,
+            },
+        ))
+    }
+
+    fn deallocate_pages(&mut self, pages: PageRange<Size4KiB>) {
+        let count = (pages.end - pages.start) as usize;
+        unsafe {
+            dealloc(
+                pages.start.start_address().as_mut_ptr(),
+                page_layout(count),
+            )
+        };
+        self.deallocated += 1;
+    }
+
+    fn set_permissions(
+        &mut self,
+        pages: PageRange<Size4KiB>,
+        permissions: PagePermissions,
+    ) {
+        self.permissions.push((pages, permissions));
+    }
+}
+
+#[test]
+pub fn test_relocations_are_applied() {
+    let mut binary = binary(
+        ET_DYN,
+        &[
+            Relocation {
+                offset: DATA as u64,
+                kind: R_X86_64_RELATIVE,
+                addend: Some(0x10),
+            },
+            Relocation {
+                offset: DATA as u64 + 8,
+                kind: R_X86_64_RELATIVE,
+                addend: None,
+            },
+        ],
+    );
+    LittleEndian::write_u64(&mut binary[DATA + 8..], 0x20);
+
+    let mut parameters = HostPages::default();
+    let loaded = load(&binary, &mut parameters).unwrap();
+    assert_eq!(loaded.entry, VirtAddr::new(LOAD_BASE + DATA as u64));
+
+    let data = unsafe {
+        std::slice::from_raw_parts(
+            (loaded.memory.start.start_address() + DATA as u64).as_ptr::<u8>(),
+            16,
+        )
+    };
+    assert_eq!(LittleEndian::read_u64(&data[0..]), LOAD_BASE + 0x10);
+    assert_eq!(LittleEndian::read_u64(&data[8..]), LOAD_BASE + 0x20);
+
+    // The segment keeps its permissions at the relocated address
+    let segment = Page::from_start_address(VirtAddr::new(LOAD_BASE)).unwrap();
+    assert_eq!(
+        parameters.permissions,
+        [(
+            PageRange {
+                start: segment,
+                end: segment + 1,
+            },
+            PagePermissions {
+                read: true,
+                write: true,
+                execute: false,
+            }
+        )]
+    );
+
+    (&mut parameters).deallocate_pages(loaded.memory);
+}
+
+#[test]
+pub fn test_truncated_binaries_fail_to_parse() {
+    let binary = binary(ET_DYN, &[]);
+    let mut parameters = HostPages::default();
+
+    let error = load(&binary[..32], &mut parameters).err().unwrap();
+    assert!(matches!(error, LoadError::Parse(_)));
+    assert_eq!(parameters.allocated, 0);
+}
+
+#[test]
+pub fn test_32_bit_binaries_are_rejected() {
+    let mut parameters = HostPages::default();
+
+    let error = load(&binary_32(), &mut parameters).err().unwrap();
+    assert!(matches!(error, LoadError::Not64Bit));
+    assert_eq!(parameters.allocated, 0);
+}
+
+#[test]
+pub fn test_position_dependent_binaries_are_rejected() {
+    let mut parameters = HostPages::default();
+
+    let error = load(&binary(ET_EXEC, &[]), &mut parameters).err().unwrap();
+    assert!(matches!(error, LoadError::NotRelocatable));
+    assert_eq!(parameters.allocated, 0);
+}
+
+#[test]
+pub fn test_out_of_bounds_relocations_are_rejected() {
+    // Far beyond the pages allocated for the binary
+    let offset = 1 << 40;
+    let binary = binary(
+        ET_DYN,
+        &[Relocation {
+            offset,
+            kind: R_X86_64_RELATIVE,
+            addend: Some(0),
+        }],
+    );
+    let mut parameters = HostPages::default();
+
+    let error = load(&binary, &mut parameters).err().unwrap();
+    assert!(matches!(
+        error,
+        LoadError::RelocationOutOfBounds(at) if at == offset
+    ));
+    assert_eq!(parameters.allocated, 1);
+    assert_eq!(parameters.deallocated, 1);
+}
+
+#[test]
+pub fn test_unknown_relocations_are_rejected() {
+    let binary = binary(
+        ET_DYN,
+        &[Relocation {
+            offset: DATA as u64,
+            kind: R_X86_64_64,
+            addend: Some(0),
+        }],
+    );
+    let mut parameters = HostPages::default();
+
+    let error = load(&binary, &mut parameters).err().unwrap();
+    assert!(matches!(
+        error,
+        LoadError::UnsupportedRelocation(kind) if kind == R_X86_64_64
+    ));
+    assert_eq!(parameters.deallocated, 1);
+}
@@ -12,21 +12,24 @@ pub mod read_kernel;
 
 use crate::{memory_map::exit_boot_services, read_kernel::read_kernel};
 use acpi::{RootSystemDescriptionPointer2, RSDP2_GUID};
-use alloc::boxed::Box;
+use alloc::{boxed::Box, vec::Vec};
 use call_with_stack::call_with_stack;
 use core::mem::MaybeUninit;
 use cpu_local_storage::data::{CoreId, CpuLocalData};
-use elf_loader::parameters::AdHocLoadParameters;
+use elf_loader::parameters::{AdHocLoadParameters, PagePermissions};
 use log::*;
 use page_management::{
     page_table::{
         identity_page,
+        kernel_stack::{KernelStack, KERNEL_STACK_PAGES},
         managed_page_table::{
             ManagedPageTable, ModificationFlags, IDENTITY_BASE,
-            KERNEL_STACK_BASE,
         },
     },
-    physical::{map::PhysicalMemoryMap, page_usage::PageUsage},
+    physical::{
+        map::PhysicalMemoryMap,
+        page_usage::{PageUsage, UsageCategory},
+    },
 };
 use parameters::{KernelArguments, KernelEntrySignature};
 use uefi::{
@@ -42,8 +45,6 @@ use x86_64::{
     PhysAddr, VirtAddr,
 };
 
-const STACK_SIZE_PAGES: usize = 256;
-
 fn uefi_frame_allocator<'lt>(
     bt: &'lt BootServices,
 ) -> impl 'lt + Fn() -> Option<UnusedPhysFrame> {
@@ -58,8 +59,41 @@ fn uefi_frame_allocator<'lt>(
     }
 }
 
+/// The flags of a kernel page, combining the permissions of the segments containing it
+///
+/// Returns None for pages outside of the segments.
+fn kernel_page_flags(
+    page: Page<Size4KiB>,
+    kernel_segments: &[(PageRange<Size4KiB>, PagePermissions)],
+) -> Option<PageTableFlags> {
+    let mut flags = None;
+
+    for (_, permissions) in kernel_segments
+        .iter()
+        .filter(|(pages, _)| pages.start <= page && page < pages.end)
+    {
+        let flags = flags.get_or_insert(
+            PageTableFlags::PRESENT | PageTableFlags::NO_EXECUTE,
+        );
+        if permissions.write {
+            flags.insert(PageTableFlags::WRITABLE);
+        }
+        if permissions.execute {
+            flags.remove(PageTableFlags::NO_EXECUTE);
+        }
+    }
+
+    flags
+}
+
+/// Create the kernel page table
+///
+/// Physical memory is identity mapped at desired_identity_base and at 0 for the loader.
+/// The high mapping is not executable, except for the executable segments of the kernel.
 unsafe fn setup_page_table<A>(
     desired_identity_base: Page<Size4KiB>,
+    kernel_image: PageRange<Size4KiB>,
+    kernel_segments: &[(PageRange<Size4KiB>, PagePermissions)],
     mut allocate: A,
 ) -> ManagedPageTable
 where
@@ -134,6 +168,7 @@ where
             identity: true,
             kernel_stack: false,
             kernel_heap: false,
+            mmio: false,
         },
         |manager| {
             // Map all physical pages to their identity position:
@@ -153,7 +188,9 @@ where
                 .map_pages_external_frame_allocator(
                     desired_identity_base,
                     physical_range,
-                    PageTableFlags::PRESENT | PageTableFlags::WRITABLE,
+                    PageTableFlags::PRESENT
+                        | PageTableFlags::WRITABLE
+                        | PageTableFlags::NO_EXECUTE,
                     false,
                     &mut allocate,
                 )
@@ -161,6 +198,14 @@ where
         },
     );
 
+    // The kernel runs from its identity mapping, with the permissions of its segments
+    let mut mapper = kernel_page_table.mapper();
+    for page in kernel_image {
+        if let Some(flags) = kernel_page_flags(page, kernel_segments) {
+            mapper.update_flags(page, flags).unwrap().ignore();
+        }
+    }
+
     kernel_page_table
 }
 
@@ -195,6 +240,7 @@ fn efi_main(image: Handle, st: SystemTable<Boot>) -> Status {
 
         let cpu_local_data = Box::new(CpuLocalData {
             core_id: CoreId::from_optional_full_id(1).unwrap(),
+            allocation_cache: Default::default(),
         });
         let cpu_local_data = Box::leak(cpu_local_data);
         unsafe { cpu_local_storage::init_raw(cpu_local_data as *mut _) };
@@ -218,6 +264,7 @@ fn efi_main(image: Handle, st: SystemTable<Boot>) -> Status {
         physical_memory_map.register_global();
     }
 
+    let mut kernel_segments = Vec::new();
     let kernel = {
         let kernel_data = read_kernel(&st);
         info!("Kernel loaded: {} bytes", kernel_data.len());
@@ -260,46 +307,53 @@ fn efi_main(image: Handle, st: SystemTable<Boot>) -> Status {
                         },
                     ))
                 },
-                deallocate: |_pages| unimplemented!(),
-                set_permissions: |_pages, _permissions| unimplemented!(),
+                deallocate: |pages| {
+                    st.boot_services()
+                        .free_pages(
+                            pages.start.start_address().as_u64(),
+                            (pages.end - pages.start) as usize,
+                        )
+                        .expect_success("Failed to free the kernel pages");
+                },
+                // The kernel page table does not exist yet, so they are applied later
+                set_permissions: |pages, permissions| {
+                    kernel_segments.push((pages, permissions))
+                },
             },
         )
+        .unwrap_or_else(|error| panic!("Failed to load the kernel: {}", error))
     };
 
     info!("Kernel entry: {:x?}", kernel.entry.as_ptr::<()>());
 
     // Create page table
     let mut page_table = unsafe {
-        setup_page_table(desired_identity_base, |_| {
-            uefi_frame_allocator(st.boot_services())()
-        })
+        setup_page_table(
+            desired_identity_base,
+            kernel.relocation_location.clone(),
+            &kernel_segments,
+            |_| uefi_frame_allocator(st.boot_services())(),
+        )
     };
 
     info!("Set up new page table");
 
+    let statistics = PhysicalMemoryMap::global(|map| map.statistics());
     info!(
         "Pages used for page tables: 0x{:X}",
-        PhysicalMemoryMap::global(|map| map
-            .iter()
-            .filter(|entry| match entry {
-                PageUsage::PageTable { .. }
-                | PageUsage::PageTableRoot { .. } => true,
-                _ => false,
-            })
-            .count())
+        statistics.get(UsageCategory::PageTable)
+            + statistics.get(UsageCategory::PageTableRoot)
     );
     info!(
         "Available pages: 0x{:X}",
-        PhysicalMemoryMap::global(|map| map
-            .iter()
-            .filter(|entry| *entry == PageUsage::Empty)
-            .count())
+        statistics.get(UsageCategory::Empty)
     );
 
     let stack_top: Page<Size4KiB> = {
-        let stack_base: Page<Size4KiB> =
-            Page::from_start_address(VirtAddr::new(KERNEL_STACK_BASE)).unwrap();
-        let stack_top = stack_base + (STACK_SIZE_PAGES as u64);
+        // The guard pages below the stack are left unmapped
+        let stack_pages = KernelStack::boot().pages();
+        let stack_base = stack_pages.start;
+        let stack_top = stack_pages.end;
 
         unsafe {
             page_table.modify(
@@ -310,11 +364,8 @@ fn efi_main(image: Handle, st: SystemTable<Boot>) -> Status {
                 |manager| {
                     manager
                         .map_pages_external_frame_allocator(
-                            Page::from_start_address(VirtAddr::new(
-                                KERNEL_STACK_BASE,
-                            ))
-                            .unwrap(),
-                            (0..STACK_SIZE_PAGES).map(|_| {
+                            stack_base,
+                            (0..KERNEL_STACK_PAGES as usize).map(|_| {
                                 PhysFrame::<Size4KiB>::from_start_address(
                                     PhysAddr::new(
                                         st.boot_services()
@@ -379,6 +430,7 @@ fn efi_main(image: Handle, st: SystemTable<Boot>) -> Status {
             rsdp,
             physical_memory_map: PhysicalMemoryMap::take_global(),
             identity_base: desired_identity_base,
+            kernel_image: kernel.relocation_location,
         }) as *mut KernelArguments;
         let kernel_arguments = (VirtAddr::from_ptr(kernel_arguments)
             + desired_identity_base.start_address().as_u64())
@@ -1,9 +1,10 @@
 use crate::alloc_utils::{allocate_pages_array, allocate_pages_byte_size};
 use alloc::vec::Vec;
-use core::ops::Range;
+use core::{mem::size_of, ops::Range};
 use page_management::physical::{
     map::PhysicalMemoryMap,
     page_usage::{PageUsage, PageUsageRawType},
+    run_length_map::Run,
 };
 use uefi::{
     table::{
@@ -17,6 +18,14 @@ use x86_64::{
     PhysAddr,
 };
 
+/// Larger flat maps are replaced by a run length map
+///
+/// A flat map takes 8 bytes per frame, including the holes between memory ranges.
+const MAX_FLAT_MAP_SIZE: usize = 16 * 1024 * 1024;
+
+/// The number of runs of a run length map, taking 4MiB
+const RUN_LENGTH_MAP_RUNS: usize = 4 * 1024 * 1024 / size_of::<Run>();
+
 pub fn create_memory_map_vec(st: &SystemTable<Boot>) -> Vec<MemoryDescriptor> {
     let mut buffer = vec![];
     let bt = st.boot_services();
@@ -99,14 +108,30 @@ pub fn create_physical_memory_map(
 
     let physical_size = physical_end - physical_base + 1;
 
-    let buffer = allocate_pages_array::<PageUsageRawType>(
-        st.boot_services(),
-        physical_size as usize,
-    )
-    .unwrap();
-
-    let mut map =
-        PhysicalMemoryMap::create(buffer, physical_base, PageUsage::Unusable);
+    let flat_size = physical_size as usize * size_of::<PageUsageRawType>();
+
+    let mut map = if flat_size <= MAX_FLAT_MAP_SIZE {
+        let buffer = allocate_pages_array::<PageUsageRawType>(
+            st.boot_services(),
+            physical_size as usize,
+        )
+        .unwrap();
+
+        PhysicalMemoryMap::create(buffer, physical_base, PageUsage::Unusable)
+    } else {
+        let buffer = allocate_pages_array::<Run>(
+            st.boot_services(),
+            RUN_LENGTH_MAP_RUNS,
+        )
+        .unwrap();
+
+        PhysicalMemoryMap::create_run_length(
+            buffer,
+            physical_base,
+            physical_size,
+            PageUsage::Unusable,
+        )
+    };
 
     for memory in memory_info.iter() {
         enter_descriptor_into_memory_map(*memory, &mut map);
@@ -4,6 +4,14 @@ use structopt::*;
 pub struct BuildArgs {
     #[structopt(long)]
     pub release: bool,
+
+    /// Check the kernel heap with the debug allocator
+    #[structopt(long)]
+    pub debug_allocator: bool,
+
+    /// Record live kernel heap allocations with the leak tracker
+    #[structopt(long)]
+    pub leak_tracker: bool,
 }
 
 #[derive(Debug, StructOpt)]
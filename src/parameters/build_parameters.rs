@@ -42,6 +42,8 @@ pub struct BuildParameters {
     pub target: Target,
     pub manifest_directory: Option<PathBuf>,
     pub config: Config,
+    pub features: Vec<String>,
+    pub frame_pointers: bool,
 }
 
 impl BuildParameters {
@@ -50,6 +52,8 @@ impl BuildParameters {
             target: Target::builtin("x86_64-unknown-uefi".to_string()),
             manifest_directory: Some("crates/uefi/uefi_loader".into()),
             config: Default::default(),
+            features: Vec::new(),
+            frame_pointers: false,
         }
     }
 
@@ -58,6 +62,8 @@ impl BuildParameters {
             target: Target::custom("x86_64-unknown-bare".to_string()),
             manifest_directory: Some("crates/kernel/core".into()),
             config: Default::default(),
+            features: Vec::new(),
+            frame_pointers: false,
         }
     }
 
@@ -67,6 +73,38 @@ impl BuildParameters {
         }
     }
 
+    /// Enable a feature that captures backtraces
+    ///
+    /// Backtraces are walked along the frame pointers,
+    /// which are only kept for builds that need them.
+    pub fn enable_backtrace_feature(&mut self, feature: &str) {
+        self.features.push(feature.to_string());
+        self.frame_pointers = true;
+    }
+
+    pub fn cargo_args(&self) -> Vec<String> {
+        let mut args = vec![];
+
+        if self.config == Config::Release {
+            args.push("--release".to_string());
+        }
+
+        if !self.features.is_empty() {
+            args.push("--features".to_string());
+            args.push(self.features.join(" "));
+        }
+
+        args
+    }
+
+    pub fn rustflags(&self) -> Option<&'static str> {
+        if self.frame_pointers {
+            Some("-C force-frame-pointers=yes")
+        } else {
+            None
+        }
+    }
+
     pub fn manifest_path(&self) -> Option<PathBuf> {
         self.manifest_directory
             .as_ref()
@@ -33,5 +33,14 @@ impl Parameters {
     pub fn apply_cli(&mut self, args: &BuildArgs) {
         self.uefi_loader_build_parameters.apply_cli(args);
         self.kernel_build_parameters.apply_cli(args);
+
+        if args.debug_allocator {
+            self.kernel_build_parameters
+                .enable_backtrace_feature("debug_allocator");
+        }
+        if args.leak_tracker {
+            self.kernel_build_parameters
+                .enable_backtrace_feature("leak_tracker");
+        }
     }
 }
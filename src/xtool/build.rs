@@ -1,7 +1,5 @@
 use crate::{
-    parameters::{
-        build_parameters::BuildParameters, config::Config, Parameters,
-    },
+    parameters::{build_parameters::BuildParameters, Parameters},
     xtool::run_xtool,
 };
 use std::error::Error;
@@ -9,17 +7,12 @@ use std::error::Error;
 fn xbuild(parameters: &BuildParameters) {
     let manifest_path = parameters.manifest_path();
 
-    let mut args = vec![];
-
-    if parameters.config == Config::Release {
-        args.push("--release".to_string());
-    }
-
     run_xtool(
         "xbuild",
         &parameters.target.to_string(),
         manifest_path.as_ref().map(|s| s.to_str().unwrap()),
-        args,
+        parameters.cargo_args(),
+        parameters.rustflags(),
     )
 }
 
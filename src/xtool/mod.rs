@@ -6,6 +6,7 @@ pub fn run_xtool(
     target: &str,
     manifest_path: Option<&str>,
     args: Vec<String>,
+    rustflags: Option<&str>,
 ) {
     println!();
 
@@ -19,10 +20,14 @@ pub fn run_xtool(
 
     final_args.extend(args.into_iter());
 
-    let status = std::process::Command::new("cargo")
-        .args(final_args)
-        .status()
-        .unwrap();
+    let mut command = std::process::Command::new("cargo");
+    command.args(final_args);
+
+    if let Some(rustflags) = rustflags {
+        command.env("RUSTFLAGS", rustflags);
+    }
+
+    let status = command.status().unwrap();
 
     println!();
 